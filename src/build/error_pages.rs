@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use bytes::Bytes;
+use hyper::http::HeaderValue;
+
+use crate::build::{build_service_ref, LoadedService};
+use crate::config::error::ConfigError;
+use crate::config::forward::errors::{ErrorOn, ErrorPages};
+use crate::config::tls::resolve_path;
+use crate::template::{compile_template, CompiledTemplate};
+
+#[derive(Debug, Clone, Default)]
+pub struct LoadedErrorPages {
+    pub debug: bool,
+    pub pages: Vec<LoadedErrorPage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadedErrorPage {
+    pub on: Vec<ErrorOn>,
+    pub status: Option<u16>,
+    pub headers: BTreeMap<String, CompiledTemplate>,
+    pub response: PageResponse,
+}
+
+#[derive(Debug, Clone)]
+pub enum PageResponse {
+    /// Read once, when the config is loaded.
+    File { body: Bytes, content_type: HeaderValue },
+    Body(CompiledTemplate),
+    Use(Box<LoadedService>),
+}
+
+pub fn build_error_pages(cfg: &ErrorPages, base_dir: &Path) -> Result<LoadedErrorPages, ConfigError> {
+    let mut pages = Vec::with_capacity(cfg.pages.len());
+    for page in &cfg.pages {
+        let template = |t: &str| compile_template(t)
+            .map_err(|e| ConfigError::Invalid(format!("`errors.pages`: {e}")));
+        let response = if let Some(file) = &page.file {
+            let path = resolve_path(base_dir, file);
+            let body = std::fs::read(&path)
+                .map_err(|e| ConfigError::Invalid(format!("`errors.pages.file` {}: {e}", path.display())))?;
+            let mime = mime_guess::from_path(&path).first_or_octet_stream();
+            let content_type = HeaderValue::from_str(mime.as_ref())
+                .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+            PageResponse::File { body: body.into(), content_type }
+        } else if let Some(body) = &page.body {
+            PageResponse::Body(template(body)?)
+        } else if let Some(service) = &page.service {
+            PageResponse::Use(Box::new(build_service_ref(service, base_dir)?))
+        } else {
+            return Err(ConfigError::Invalid("each of `errors.pages` needs exactly one of `file`, `body` and `use`".into()));
+        };
+        let headers = page.headers.iter()
+            .map(|(name, value)| Ok((name.clone(), template(value)?)))
+            .collect::<Result<_, ConfigError>>()?;
+        pages.push(LoadedErrorPage { on: page.on.clone(), status: page.status, headers, response });
+    }
+    Ok(LoadedErrorPages { debug: cfg.debug, pages })
+}
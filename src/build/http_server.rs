@@ -1,11 +1,21 @@
+use std::sync::Arc;
+
+use crate::config::bind::{BindAddr, UnixSocketOptions};
 use crate::config::error::ConfigError;
 use crate::config::http_server::HttpServer;
-use crate::build::service::{LoadedService, build_service_ref};
+use crate::config::limits::ListenerLimits;
+use crate::build::{LoadedService, build_service_ref};
+use crate::build::tls::build_server_config;
+use crate::util::cidr::IpCidr;
 
 #[derive(Debug, Clone)]
 pub struct BuiltHttpServer {
-    pub bind: String,
-    pub tls: Option<crate::config::tls::TlsConfig>,
+    pub binds: Vec<BindAddr>,
+    pub unix_socket: UnixSocketOptions,
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+    pub proxy_protocol: bool,
+    pub trusted_proxies: Vec<IpCidr>,
+    pub limits: ListenerLimits,
     pub service: LoadedService,
 }
 
@@ -13,9 +23,23 @@ pub fn build_http_server(cfg: HttpServer) -> Result<BuiltHttpServer, ConfigError
     cfg.validate()?;
     let base = cfg.base_dir.as_deref().unwrap_or(std::path::Path::new("."));
     let service = build_service_ref(&cfg.service, base)?;
+    let tls = match &cfg.tls {
+        Some(t) if t.enabled => Some(build_server_config(t, base)?),
+        _ => None,
+    };
+    let trusted_proxies = cfg.trusted_proxies.iter()
+        .map(|c| c.parse::<IpCidr>().map_err(ConfigError::Invalid))
+        .collect::<Result<Vec<_>, _>>()?;
+    let binds = cfg.bind.iter()
+        .map(|b| b.parse::<BindAddr>().map_err(ConfigError::Invalid))
+        .collect::<Result<Vec<_>, _>>()?;
     Ok(BuiltHttpServer {
-        bind: cfg.bind,
-        tls: cfg.tls,
+        binds,
+        unix_socket: cfg.unix_socket,
+        tls,
+        proxy_protocol: cfg.proxy_protocol,
+        trusted_proxies,
+        limits: cfg.limits,
         service,
     })
 }
@@ -1,6 +1,8 @@
 pub mod service;
 pub mod router;
 pub mod http_server;
+pub mod tls;
+pub mod error_pages;
 
 pub use http_server::{BuiltHttpServer, build_http_server};
-pub use service::{LoadedService, LoadedStatic, LoadedForward, LoadedRouter, build_service, build_service_ref};
+pub use service::{LoadedService, LoadedStatic, LoadedForward, LoadedRouter, build_service_ref};
@@ -1,6 +1,6 @@
 use std::collections::BTreeMap;
 
-use crate::build::service::LoadedService;
+use crate::build::LoadedService;
 use crate::config::error::ConfigError;
 use crate::config::http_method::HttpMethod;
 use crate::pattern::{
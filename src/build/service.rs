@@ -1,14 +1,25 @@
 use crate::config::error::ConfigError;
+use crate::config::cache::CacheService;
 use crate::config::forward::ForwardService;
 use crate::config::router::RouterService;
 use crate::config::service::{Service, ServiceRef, resolve_service_ref};
 use crate::config::r#static::StaticService;
+use crate::config::http_version::{AlpnProto, HttpVersion};
+use crate::config::tls::resolve_path;
+use crate::config::url_scheme::Scheme;
+use crate::build::error_pages::{build_error_pages, LoadedErrorPages, PageResponse};
+use crate::build::tls::{build_upstream_tls, UpstreamTls};
+use crate::template::{compile_template, CompiledTemplate};
+use crate::handler::cache::Store;
+use crate::handler::forward::{build_client, start_probes, Balancer, DynamicUpstreams, HealthProbes, Upstream};
 use crate::build::router::{
+    LoadedOp,
     LoadedRule,
     compile_rules,
 };
 use std::collections::HashSet;
 use std::path::Path;
+use std::sync::Arc;
 
 const DEFAULT_MAX_STEPS: u32 = 16;
 
@@ -16,7 +27,8 @@ const DEFAULT_MAX_STEPS: u32 = 16;
 pub enum LoadedService {
     Static(LoadedStatic),
     Router(LoadedRouter),
-    Forward(LoadedForward),
+    Forward(Box<LoadedForward>),
+    Cache(LoadedCache),
 }
 
 #[derive(Debug, Clone)]
@@ -27,6 +39,28 @@ pub struct LoadedStatic {
 #[derive(Debug, Clone)]
 pub struct LoadedForward {
     pub config: ForwardService,
+    pub upstreams: ForwardUpstreams,
+    pub errors: LoadedErrorPages,
+}
+
+/// Where a Forward sends requests, shared by every request and by every
+/// listener serving it.
+#[derive(Debug, Clone)]
+pub enum ForwardUpstreams {
+    /// The `target`s and their connection pools.
+    Fixed(Arc<Balancer>),
+    /// Upstreams picked by the request URI, with `dynamic_target`.
+    Dynamic(Arc<DynamicUpstreams>),
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadedCache {
+    pub config: CacheService,
+    pub service: Arc<LoadedService>,
+    /// `None` keys responses by the request URI.
+    pub key: Option<CompiledTemplate>,
+    /// Shared like a Forward's pool, so every listener sees the same responses.
+    pub store: Arc<Store>,
 }
 
 #[derive(Debug, Clone)]
@@ -36,6 +70,55 @@ pub struct LoadedRouter {
     pub max_steps: u32,
 }
 
+impl LoadedService {
+    /// Start the active health checks of every Forward in this service, for
+    /// as long as the returned handles are kept. Only a service actually
+    /// being served should have them.
+    pub fn start_probes(&self) -> Vec<Arc<HealthProbes>> {
+        let mut probes = Vec::new();
+        self.collect_probes(&mut probes);
+        probes
+    }
+
+    fn collect_probes(&self, probes: &mut Vec<Arc<HealthProbes>>) {
+        match self {
+            LoadedService::Static(_) => {}
+            LoadedService::Router(rt) => {
+                for rule in &rt.rules {
+                    collect_op_probes(&rule.ops, probes);
+                }
+                if let Some(next) = &rt.next {
+                    next.collect_probes(probes);
+                }
+            }
+            LoadedService::Forward(fw) => {
+                if let (ForwardUpstreams::Fixed(balancer), Some(active)) = (&fw.upstreams, &fw.config.health.active) {
+                    probes.push(start_probes(balancer, active));
+                }
+                for page in &fw.errors.pages {
+                    if let PageResponse::Use(svc) = &page.response {
+                        svc.collect_probes(probes);
+                    }
+                }
+            }
+            LoadedService::Cache(cache) => cache.service.collect_probes(probes),
+        }
+    }
+}
+
+fn collect_op_probes(ops: &[LoadedOp], probes: &mut Vec<Arc<HealthProbes>>) {
+    for op in ops {
+        match op {
+            LoadedOp::Use(svc) => svc.collect_probes(probes),
+            LoadedOp::Branch(_, then, otherwise) => {
+                collect_op_probes(then, probes);
+                collect_op_probes(otherwise, probes);
+            }
+            _ => {}
+        }
+    }
+}
+
 pub fn build_service_ref(cfg: &ServiceRef, base_dir: &Path) -> Result<LoadedService, ConfigError> {
     let mut stack = HashSet::new();
     let resolved = resolve_service_ref(cfg, base_dir, &mut stack)?;
@@ -45,11 +128,71 @@ pub fn build_service_ref(cfg: &ServiceRef, base_dir: &Path) -> Result<LoadedServ
 pub fn build_service(cfg: &Service, base_dir: &Path) -> Result<LoadedService, ConfigError> {
     Ok(match cfg {
         Service::Static(st) => LoadedService::Static(LoadedStatic { config: st.clone() }),
-        Service::Forward(fw) => LoadedService::Forward(LoadedForward { config: fw.clone() }),
+        Service::Forward(fw) => {
+            let mut config = ForwardService::clone(fw);
+            // relative socket paths are relative to the config file, like TLS files
+            for path in config.target.iter_mut().filter_map(|t| t.unix.as_mut()) {
+                *path = resolve_path(base_dir, path);
+            }
+            let upstreams = match &config.dynamic_target {
+                Some(dynamic) => {
+                    let tls_cfg = config.tls.clone().unwrap_or_default();
+                    // the host only stands in until requests name theirs
+                    let tls = if tls_cfg.enabled { Some(upstream_tls(&config, "localhost", base_dir)?) } else { None };
+                    let upstreams = DynamicUpstreams::new(dynamic, tls, tls_cfg.sni.is_some())
+                        .map_err(|e| ConfigError::Invalid(format!("`forward.dynamic_target.allow`: {e}")))?;
+                    ForwardUpstreams::Dynamic(Arc::new(upstreams))
+                }
+                None => {
+                    let mut upstreams = Vec::with_capacity(config.target.len());
+                    for target in &config.target {
+                        let tls = match target.scheme {
+                            Scheme::Https => Some(upstream_tls(&config, target.host(), base_dir)?),
+                            Scheme::Http => None,
+                        };
+                        let client = build_client(&config, target, tls);
+                        upstreams.push(Upstream::new(target.clone(), client));
+                    }
+                    ForwardUpstreams::Fixed(Arc::new(Balancer::new(upstreams, &config.balance)?))
+                }
+            };
+            let errors = build_error_pages(&config.errors, base_dir)?;
+            LoadedService::Forward(Box::new(LoadedForward { config, upstreams, errors }))
+        }
         Service::Router(rt) => build_router(rt, base_dir)?,
+        Service::Cache(cache) => build_cache(cache, base_dir)?,
     })
 }
 
+fn build_cache(cache: &CacheService, base_dir: &Path) -> Result<LoadedService, ConfigError> {
+    let service = Arc::new(build_service_ref(&cache.service, base_dir)?);
+    let key = cache.key.as_deref()
+        .map(compile_template)
+        .transpose()
+        .map_err(|e| ConfigError::Invalid(format!("`cache.key`: {e}")))?;
+    let disk = match &cache.disk {
+        Some(disk) => {
+            let dir = resolve_path(base_dir, &disk.dir);
+            std::fs::create_dir_all(&dir)
+                .map_err(|e| ConfigError::Invalid(format!("`cache.disk.dir` {}: {e}", dir.display())))?;
+            Some((dir, disk.max_bytes))
+        }
+        None => None,
+    };
+    let store = Arc::new(Store::new(cache.max_bytes, cache.max_object_bytes, disk));
+    Ok(LoadedService::Cache(LoadedCache { config: cache.clone(), service, key, store }))
+}
+
+/// TLS for an `https` upstream at `host`.
+fn upstream_tls(config: &ForwardService, host: &str, base_dir: &Path) -> Result<UpstreamTls, ConfigError> {
+    let mut tls = config.tls.clone().unwrap_or_default();
+    // the client speaks nothing else, so offering more would only invite a mismatch
+    if config.http_version == HttpVersion::V2 {
+        tls.alpn = vec![AlpnProto::Http2];
+    }
+    build_upstream_tls(&tls, host, base_dir)
+}
+
 fn build_router(rt: &RouterService, base_dir: &Path) -> Result<LoadedService, ConfigError> {
     let next = match &rt.next {
         Some(n) => Some(Box::new(build_service_ref(n, base_dir)?)),
@@ -0,0 +1,268 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme,
+    SupportedCipherSuite, SupportedProtocolVersion,
+};
+
+use crate::config::error::ConfigError;
+use crate::config::forward::tls::{TlsUpstream, TlsVersion};
+use crate::config::http_version::AlpnProto;
+use crate::config::tls::{resolve_path, TlsConfig};
+
+/// Build the rustls server config for a listener from its `tls` section.
+pub fn build_server_config(cfg: &TlsConfig, base_dir: &Path) -> Result<Arc<ServerConfig>, ConfigError> {
+    let certs = load_certs(&cfg.cert_path(base_dir))?;
+    let key = load_private_key(&cfg.key_path(base_dir))?;
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(to_config_err)?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(to_config_err)?;
+
+    // advertise in the configured order of preference
+    config.alpn_protocols = cfg.alpn.iter()
+        .map(AlpnProto::protocol_id)
+        .map(<[u8]>::to_vec)
+        .collect();
+
+    Ok(Arc::new(config))
+}
+
+pub fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, ConfigError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|it| it.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| ConfigError::Invalid(format!("failed to read certificates from {}: {e}", path.display())))?;
+    if certs.is_empty() {
+        return Err(ConfigError::Invalid(format!("no certificates found in {}", path.display())));
+    }
+    Ok(certs)
+}
+
+pub fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, ConfigError> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| ConfigError::Invalid(format!("failed to read private key from {}: {e}", path.display())))
+}
+
+fn to_config_err<E: std::error::Error>(e: E) -> ConfigError {
+    ConfigError::Invalid(format!("tls: {e}"))
+}
+
+/// Everything a Forward needs to open TLS connections to its upstream.
+#[derive(Debug, Clone)]
+pub struct UpstreamTls {
+    pub config: Arc<ClientConfig>,
+    /// Sent as SNI and checked against the upstream certificate.
+    pub server_name: ServerName<'static>,
+    pub handshake_timeout: Option<Duration>,
+}
+
+/// TLS settings for an `https` Forward target; `host` names the upstream unless `sni` is set.
+pub fn build_upstream_tls(cfg: &TlsUpstream, host: &str, base_dir: &Path) -> Result<UpstreamTls, ConfigError> {
+    let name = cfg.sni.as_deref().unwrap_or(host);
+    let server_name = ServerName::try_from(name.to_string())
+        .map_err(|e| ConfigError::Invalid(format!("`tls.sni`: invalid server name `{name}`: {e}")))?;
+    Ok(UpstreamTls {
+        config: build_client_config(cfg, base_dir)?,
+        server_name,
+        handshake_timeout: cfg.handshake_timeout_ms.map(|ms| Duration::from_millis(ms.into())),
+    })
+}
+
+fn build_client_config(cfg: &TlsUpstream, base_dir: &Path) -> Result<Arc<ClientConfig>, ConfigError> {
+    let mut provider = rustls::crypto::ring::default_provider();
+    if let Some(names) = &cfg.cipher_suites {
+        provider.cipher_suites = select_cipher_suites(&provider.cipher_suites, names)?;
+    }
+    let provider = Arc::new(provider);
+
+    let versions: Vec<&'static SupportedProtocolVersion> = [
+        (TlsVersion::V12, &rustls::version::TLS12),
+        (TlsVersion::V13, &rustls::version::TLS13),
+    ]
+        .into_iter()
+        .filter(|(v, _)| (cfg.min_tls..=cfg.max_tls).contains(v))
+        .map(|(_, p)| p)
+        .collect();
+    if versions.is_empty() {
+        return Err(ConfigError::Invalid("`tls.min_tls` cannot be above `tls.max_tls`".into()));
+    }
+
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&versions)
+        .map_err(to_config_err)?;
+    let builder = if cfg.insecure_skip_verify {
+        builder.dangerous().with_custom_certificate_verifier(Arc::new(SkipVerification(provider)))
+    } else {
+        let roots = Arc::new(load_roots(cfg, base_dir)?);
+        let webpki = WebPkiServerVerifier::builder_with_provider(roots, provider)
+            .build()
+            .map_err(to_config_err)?;
+        if cfg.allow_invalid_hostnames {
+            builder.dangerous().with_custom_certificate_verifier(Arc::new(IgnoreHostname(webpki)))
+        } else {
+            builder.with_webpki_verifier(webpki)
+        }
+    };
+
+    let mut config = match (&cfg.client_cert_file, &cfg.client_key_file) {
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(
+                load_certs(&resolve_path(base_dir, cert))?,
+                load_private_key(&resolve_path(base_dir, key))?,
+            )
+            .map_err(to_config_err)?,
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err(ConfigError::Invalid(
+            "`tls.client_cert_file` and `tls.client_key_file` must be set together".into()
+        )),
+    };
+    config.alpn_protocols = cfg.alpn.iter()
+        .map(AlpnProto::protocol_id)
+        .map(<[u8]>::to_vec)
+        .collect();
+
+    Ok(Arc::new(config))
+}
+
+/// Keep the provider's suites named in `names`, in the provider's order of preference.
+fn select_cipher_suites(
+    available: &[SupportedCipherSuite],
+    names: &[String],
+) -> Result<Vec<SupportedCipherSuite>, ConfigError> {
+    let name_of = |s: &SupportedCipherSuite| s.suite().as_str().unwrap_or_default();
+    if let Some(unknown) = names.iter().find(|n| !available.iter().any(|s| name_of(s) == n.as_str())) {
+        let known: Vec<&str> = available.iter().map(name_of).collect();
+        return Err(ConfigError::Invalid(format!(
+            "`tls.cipher_suites`: unknown suite `{unknown}`, expected one of {}", known.join(", ")
+        )));
+    }
+    Ok(available.iter().filter(|s| names.iter().any(|n| n == name_of(s))).copied().collect())
+}
+
+/// Roots for verifying upstream certificates: the system store and/or configured CAs.
+fn load_roots(cfg: &TlsUpstream, base_dir: &Path) -> Result<RootCertStore, ConfigError> {
+    let mut roots = RootCertStore::empty();
+    if cfg.use_system_roots {
+        let native = rustls_native_certs::load_native_certs();
+        for e in &native.errors {
+            eprintln!("Failed to load system root certificates: {e}");
+        }
+        roots.add_parsable_certificates(native.certs);
+    }
+    for path in cfg.ca_file.iter().chain(cfg.ca_files.iter().flatten()) {
+        for cert in load_certs(&resolve_path(base_dir, path))? {
+            roots.add(cert).map_err(to_config_err)?;
+        }
+    }
+    if let Some(pem) = &cfg.ca_inline {
+        let certs = CertificateDer::pem_slice_iter(pem.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ConfigError::Invalid(format!("`tls.ca_inline`: {e}")))?;
+        if certs.is_empty() {
+            return Err(ConfigError::Invalid("`tls.ca_inline` contains no certificate".into()));
+        }
+        for cert in certs {
+            roots.add(cert).map_err(to_config_err)?;
+        }
+    }
+    if roots.is_empty() {
+        return Err(ConfigError::Invalid(
+            "`tls` trusts no certificate authority: set `ca_file`, `ca_files` or `ca_inline`, or use the system roots".into()
+        ));
+    }
+    Ok(roots)
+}
+
+/// `allow_invalid_hostnames`: the chain must still be trusted, the name may differ.
+#[derive(Debug)]
+struct IgnoreHostname(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for IgnoreHostname {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self.0.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. }
+            )) => Ok(ServerCertVerified::assertion()),
+            other => other,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+/// `insecure_skip_verify`: any certificate is accepted, though the handshake
+/// signatures are still checked so the session keys belong to that certificate.
+#[derive(Debug)]
+struct SkipVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
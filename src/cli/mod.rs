@@ -1,12 +1,19 @@
+use std::collections::BTreeSet;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use clap::{Parser, ArgGroup};
 
 use crate::config::error::ConfigError;
+use crate::config::files;
 use crate::config::http_server::{HttpServer, ServersFile};
 use crate::config::service::{ServiceRef};
 
+mod watch;
+
+pub use watch::ConfigWatcher;
+
 // Why port 7589? oxidase -> 0x1da5e (121438, too large) -> 0x1da5 -> 7589 (bingo!)
 
 #[derive(Parser, Debug)]
@@ -41,6 +48,16 @@ pub struct Args {
     /// Watch for configuration changes and reload servers
     #[arg(short = 'w', long)]
     pub watch: bool,
+
+    /// Seconds in-flight connections get to finish on shutdown or reload
+    #[arg(long, default_value_t = 30)]
+    pub drain_timeout: u64,
+}
+
+impl Args {
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout)
+    }
 }
 
 pub fn load_http_servers(args: &Args) -> Result<Vec<HttpServer>, ConfigError> {
@@ -64,6 +81,19 @@ pub fn load_http_servers(args: &Args) -> Result<Vec<HttpServer>, ConfigError> {
     Ok(servers)
 }
 
+/// Files the configuration selected by `args` reads, for `--watch`.
+pub fn watched_files(args: &Args) -> BTreeSet<PathBuf> {
+    if let Some(cfg) = &args.config {
+        files::referenced_files(cfg)
+    } else if let Some(svc_file) = &args.service_file {
+        files::referenced_files(svc_file)
+    } else if let Some(inline) = &args.service_inline {
+        files::referenced_by(inline, &std::env::current_dir().unwrap_or_default())
+    } else {
+        BTreeSet::new()
+    }
+}
+
 fn load_from_config(path: &Path) -> Result<Vec<HttpServer>, ConfigError> {
     // single server
     if let Ok(svc) = HttpServer::load_from_file(path) {
@@ -100,8 +130,12 @@ fn load_from_service_file(path: &Path, bind: &str) -> Result<Vec<HttpServer>, Co
     let svc_ref = ServiceRef::Import { import: path.to_path_buf() };
     let hs = HttpServer {
         name: None,
-        bind: bind.to_string(),
+        bind: vec![bind.to_string()],
         tls: None,
+        proxy_protocol: false,
+        trusted_proxies: Vec::new(),
+        unix_socket: Default::default(),
+        limits: Default::default(),
         service: svc_ref,
         base_dir: path.parent().map(|p| p.to_path_buf()),
     };
@@ -113,8 +147,12 @@ fn load_from_inline(data: &str, bind: &str) -> Result<Vec<HttpServer>, ConfigErr
     let svc_ref: ServiceRef = serde_yaml::from_str(data)?;
     let hs = HttpServer {
         name: None,
-        bind: bind.to_string(),
+        bind: vec![bind.to_string()],
         tls: None,
+        proxy_protocol: false,
+        trusted_proxies: Vec::new(),
+        unix_socket: Default::default(),
+        limits: Default::default(),
         service: svc_ref,
         base_dir: Some(std::env::current_dir().unwrap_or_default()),
     };
@@ -1,6 +1,8 @@
+use std::collections::BTreeSet;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use super::{Args, load_http_servers};
+use super::{Args, ConfigWatcher, load_http_servers, watched_files};
 
 fn fixture_path(name: &str) -> PathBuf {
     PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
@@ -17,10 +19,11 @@ fn load_single_http_server_file() {
         pick: None,
         validate_only: false,
         watch: false,
+        drain_timeout: 30,
     };
     let servers = load_http_servers(&args).expect("load failed");
     assert_eq!(servers.len(), 1);
-    assert_eq!(servers[0].bind, "127.0.0.1:7589");
+    assert_eq!(servers[0].bind, ["127.0.0.1:7589"]);
 }
 
 #[test]
@@ -34,11 +37,12 @@ fn load_servers_wrapper_and_pick() {
         pick: Some("second".into()),
         validate_only: false,
         watch: false,
+        drain_timeout: 30,
     };
     let servers = load_http_servers(&args).expect("load failed");
     assert_eq!(servers.len(), 1);
     assert_eq!(servers[0].name.as_deref(), Some("second"));
-    assert_eq!(servers[0].bind, "0.0.0.0:9090");
+    assert_eq!(servers[0].bind, ["0.0.0.0:9090"]);
 }
 
 #[test]
@@ -52,6 +56,7 @@ fn load_plain_array() {
         pick: None,
         validate_only: false,
         watch: false,
+        drain_timeout: 30,
     };
     let servers = load_http_servers(&args).expect("load failed");
     assert_eq!(servers.len(), 2);
@@ -68,10 +73,11 @@ fn load_service_file_with_bind() {
         pick: None,
         validate_only: false,
         watch: false,
+        drain_timeout: 30,
     };
     let servers = load_http_servers(&args).expect("load failed");
     assert_eq!(servers.len(), 1);
-    assert_eq!(servers[0].bind, "0.0.0.0:8088");
+    assert_eq!(servers[0].bind, ["0.0.0.0:8088"]);
 }
 
 #[test]
@@ -88,8 +94,103 @@ source_dir: /tmp
         pick: None,
         validate_only: false,
         watch: false,
+        drain_timeout: 30,
     };
     let servers = load_http_servers(&args).expect("load failed");
     assert_eq!(servers.len(), 1);
-    assert_eq!(servers[0].bind, "127.0.0.1:12345");
+    assert_eq!(servers[0].bind, ["127.0.0.1:12345"]);
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("oxidase-cli-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn watched_files_follow_imports_and_tls() {
+    let dir = temp_dir("watched");
+    std::fs::create_dir_all(dir.join("svc")).unwrap();
+    std::fs::write(dir.join("main.yaml"), r#"
+bind: "127.0.0.1:0"
+tls:
+  cert_file: certs/cert.pem
+  key_file: certs/key.pem
+service:
+  import: ./svc/router.yaml
+"#).unwrap();
+    std::fs::write(dir.join("svc/router.yaml"), r#"
+handler: router
+rules:
+  - ops:
+      - use:
+          import: ../broken.yaml
+next:
+  handler: forward
+  target: { scheme: https, host: example.com }
+  tls:
+    ca_files: [/etc/ca-a.pem, ca-b.pem]
+    client_cert_file: client.pem
+"#).unwrap();
+    // unparsable imports are still followed
+    std::fs::write(dir.join("broken.yaml"), "handler: [").unwrap();
+
+    let args = Args {
+        config: Some(dir.join("main.yaml")),
+        service_file: None,
+        service_inline: None,
+        bind: "0.0.0.0:0".into(),
+        pick: None,
+        validate_only: false,
+        watch: true,
+        drain_timeout: 30,
+    };
+    let files = watched_files(&args);
+    let expected: BTreeSet<PathBuf> = [
+        dir.join("main.yaml"),
+        dir.join("certs/cert.pem"),
+        dir.join("certs/key.pem"),
+        dir.join("svc/router.yaml"),
+        dir.join("broken.yaml"),
+        PathBuf::from("/etc/ca-a.pem"),
+        dir.join("svc/ca-b.pem"),
+        dir.join("svc/client.pem"),
+    ].into_iter().collect();
+    assert_eq!(files, expected);
+}
+
+#[test]
+fn watched_files_survive_import_cycles() {
+    let dir = temp_dir("cycle");
+    std::fs::write(dir.join("a.yaml"), "import: ./b.yaml").unwrap();
+    std::fs::write(dir.join("b.yaml"), "import: ./a.yaml").unwrap();
+
+    let files = crate::config::files::referenced_files(&dir.join("a.yaml"));
+    assert_eq!(files.len(), 2);
+}
+
+#[tokio::test]
+async fn config_watcher_debounces_and_ignores_other_files() {
+    let dir = temp_dir("watcher");
+    let file = dir.join("config.yaml");
+    std::fs::write(&file, "a").unwrap();
+
+    let mut watcher = ConfigWatcher::new().unwrap();
+    watcher.set_files([file.clone()].into_iter().collect());
+
+    // editors often save by writing a temp file and renaming it over the original
+    std::fs::write(dir.join("config.yaml.tmp"), "b").unwrap();
+    std::fs::rename(dir.join("config.yaml.tmp"), &file).unwrap();
+    std::fs::write(&file, "c").unwrap();
+    tokio::time::timeout(Duration::from_secs(5), watcher.changed()).await
+        .expect("change not reported");
+
+    // the burst above was reported once
+    std::fs::write(dir.join("unrelated.txt"), "x").unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(800), watcher.changed()).await.is_err());
+
+    std::fs::write(&file, "d").unwrap();
+    tokio::time::timeout(Duration::from_secs(5), watcher.changed()).await
+        .expect("change not reported");
 }
@@ -0,0 +1,111 @@
+//! File watching for `--watch`: follows a changing set of files and debounces bursts of events.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::time::{timeout_at, Instant};
+
+/// How long the files must stay untouched before a change is reported.
+const QUIET_PERIOD: Duration = Duration::from_millis(300);
+
+pub struct ConfigWatcher {
+    watcher: RecommendedWatcher,
+    events: UnboundedReceiver<notify::Result<Event>>,
+    files: BTreeSet<PathBuf>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+impl ConfigWatcher {
+    pub fn new() -> notify::Result<Self> {
+        let (tx, events) = unbounded_channel();
+        let watcher = notify::recommended_watcher(move |res| {
+            let _ = tx.send(res);
+        })?;
+        Ok(ConfigWatcher { watcher, events, files: BTreeSet::new(), dirs: BTreeSet::new() })
+    }
+
+    pub fn files(&self) -> &BTreeSet<PathBuf> {
+        &self.files
+    }
+
+    /// Follow exactly `files` from now on.
+    ///
+    /// Parent directories are watched rather than the files themselves, so files
+    /// replaced by a rename (as most editors save) or created later are still
+    /// seen. A missing directory is covered by its nearest existing ancestor.
+    pub fn set_files(&mut self, files: BTreeSet<PathBuf>) {
+        let wanted: BTreeSet<PathBuf> = files.iter()
+            .filter_map(|f| f.parent())
+            .map(existing_ancestor)
+            .collect();
+
+        for dir in self.dirs.difference(&wanted) {
+            let _ = self.watcher.unwatch(dir);
+        }
+        let mut dirs = BTreeSet::new();
+        for dir in wanted {
+            if self.dirs.contains(&dir) {
+                dirs.insert(dir);
+                continue;
+            }
+            match self.watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                Ok(()) => { dirs.insert(dir); }
+                Err(e) => eprintln!("Cannot watch {}: {e}", dir.display()),
+            }
+        }
+
+        self.dirs = dirs;
+        self.files = files;
+    }
+
+    /// Wait until one of the files changes, then until they have been quiet for
+    /// `QUIET_PERIOD`, so a burst of writes yields a single reload.
+    pub async fn changed(&mut self) {
+        loop {
+            match self.events.recv().await {
+                Some(res) if self.is_relevant(&res) => break,
+                Some(_) => {}
+                None => std::future::pending::<()>().await,
+            }
+        }
+
+        let mut deadline = Instant::now() + QUIET_PERIOD;
+        loop {
+            match timeout_at(deadline, self.events.recv()).await {
+                Ok(Some(res)) => {
+                    if self.is_relevant(&res) {
+                        deadline = Instant::now() + QUIET_PERIOD;
+                    }
+                }
+                Ok(None) | Err(_) => return,
+            }
+        }
+    }
+
+    fn is_relevant(&self, res: &notify::Result<Event>) -> bool {
+        let event = match res {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("File watcher error: {e}");
+                return false;
+            }
+        };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return false;
+        }
+        // a watched file, or a directory on the way to one (created, removed, renamed)
+        event.paths.iter()
+            .filter(|p| !self.dirs.contains(*p))
+            .any(|p| self.files.iter().any(|f| f.starts_with(p)))
+    }
+}
+
+fn existing_ancestor(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .find(|d| d.is_dir())
+        .unwrap_or(dir)
+        .to_path_buf()
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+
+use super::error::ConfigError;
+
+/// One `bind` entry: `ip:port`, `[ipv6]:port`, `hostname:port`,
+/// `unix:/path/to.sock` or `systemd:<name>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BindAddr {
+    Tcp { host: String, port: u16 },
+    /// A Unix domain socket created at this path.
+    Unix(PathBuf),
+    /// A socket passed in by systemd socket activation, by `FileDescriptorName`.
+    Systemd(String),
+}
+
+impl BindAddr {
+    /// The socket addresses to listen on; a hostname may resolve to several.
+    pub async fn resolve(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        let mut addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+        addrs.sort();
+        addrs.dedup();
+        if addrs.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("`{host}` resolved to no address")));
+        }
+        Ok(addrs)
+    }
+
+    /// The TCP port that `0.0.0.0` takes on every IPv4 address, if this is such a bind.
+    pub fn ipv4_any_port(&self) -> Option<u16> {
+        match self {
+            BindAddr::Tcp { host, port } if *port != 0 && host.parse() == Ok(Ipv4Addr::UNSPECIFIED) => Some(*port),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for BindAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(format!("invalid bind `{s}`: missing socket path"));
+            }
+            return Ok(BindAddr::Unix(PathBuf::from(path)));
+        }
+        if let Some(name) = s.strip_prefix("systemd:") {
+            if name.is_empty() || name.contains(':') {
+                return Err(format!("invalid bind `{s}`: expected `systemd:<name>`"));
+            }
+            return Ok(BindAddr::Systemd(name.to_string()));
+        }
+        let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+            let (ip, tail) = rest.split_once(']')
+                .ok_or_else(|| format!("invalid bind `{s}`: missing `]`"))?;
+            ip.parse::<std::net::Ipv6Addr>()
+                .map_err(|e| format!("invalid bind `{s}`: {e}"))?;
+            let port = tail.strip_prefix(':')
+                .ok_or_else(|| format!("invalid bind `{s}`: missing port"))?;
+            (ip, port)
+        } else {
+            let (host, port) = s.rsplit_once(':')
+                .ok_or_else(|| format!("invalid bind `{s}`: expected `host:port`"))?;
+            if host.contains(':') {
+                return Err(format!("invalid bind `{s}`: IPv6 addresses must be bracketed, e.g. `[::]:80`"));
+            }
+            (host, port)
+        };
+        if host.is_empty() {
+            return Err(format!("invalid bind `{s}`: missing host"));
+        }
+        let port = port.parse::<u16>().map_err(|_| format!("invalid bind `{s}`: bad port `{port}`"))?;
+        Ok(BindAddr::Tcp { host: host.to_string(), port })
+    }
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindAddr::Tcp { host, port } if host.contains(':') => write!(f, "[{host}]:{port}"),
+            BindAddr::Tcp { host, port } => write!(f, "{host}:{port}"),
+            BindAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+            BindAddr::Systemd(name) => write!(f, "systemd:{name}"),
+        }
+    }
+}
+
+/// Accept `bind: "addr"` as well as `bind: ["addr", ...]`, and likewise for
+/// other fields that take one value or a list.
+pub fn one_or_many<'de, D: Deserializer<'de>, T: Deserialize<'de>>(d: D) -> Result<Vec<T>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> { One(T), Many(Vec<T>) }
+
+    Ok(match OneOrMany::deserialize(d)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    })
+}
+
+/// Permissions for the sockets created by `unix:` binds.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", default)]
+pub struct UnixSocketOptions {
+    pub mode: Option<String>, // octal, e.g. "660"
+    pub owner: Option<String>, // user name or uid
+    pub group: Option<String>, // group name or gid
+}
+
+impl UnixSocketOptions {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.mode_bits()?;
+        for (key, v) in [("owner", &self.owner), ("group", &self.group)] {
+            if v.as_deref().is_some_and(|v| v.trim().is_empty()) {
+                return Err(ConfigError::Invalid(format!("`unix_socket.{key}` cannot be empty if provided")));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn mode_bits(&self) -> Result<Option<u32>, ConfigError> {
+        self.mode.as_deref()
+            .map(|m| u32::from_str_radix(m.trim_start_matches("0o"), 8)
+                .ok()
+                .filter(|bits| *bits <= 0o7777)
+                .ok_or_else(|| ConfigError::Invalid(format!("`unix_socket.mode` must be octal permissions, got `{m}`"))))
+            .transpose()
+    }
+}
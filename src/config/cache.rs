@@ -0,0 +1,66 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+
+use crate::config::error::ConfigError;
+use crate::config::service::ServiceRef;
+
+fn default_max_bytes() -> u64 { 64 << 20 }
+fn default_max_object_bytes() -> u64 { 1 << 20 }
+
+/// An HTTP cache (RFC 9111) in front of another service.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct CacheService {
+    pub service: Box<ServiceRef>,
+    #[serde(default)]
+    pub key: Option<String>, // a template over the request; the request URI if unset
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64, // kept in memory, least recently used go first
+    #[serde(default = "default_max_object_bytes")]
+    pub max_object_bytes: u64, // larger responses are passed through unstored
+    #[serde(default)]
+    pub disk: Option<CacheDisk>,
+    #[serde(default)]
+    pub default_ttl_ms: Option<u64>, // for cacheable responses that say nothing of freshness; stored only when they do if unset
+    #[serde(default)]
+    pub cache_status: Option<String>, // name reported in an RFC 9211 `Cache-Status` header
+}
+
+/// Where responses pushed out of memory go before they are dropped.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct CacheDisk {
+    pub dir: PathBuf, // relative to the config file
+    pub max_bytes: u64,
+}
+
+impl CacheService {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_bytes == 0 || self.max_object_bytes == 0 {
+            return Err(ConfigError::Invalid("`cache` sizes must be positive".into()));
+        }
+        if self.max_object_bytes > self.max_bytes {
+            return Err(ConfigError::Invalid("`cache.max_object_bytes` cannot exceed `max_bytes`".into()));
+        }
+        if let Some(disk) = &self.disk {
+            if disk.dir.as_os_str().is_empty() {
+                return Err(ConfigError::Invalid("`cache.disk.dir` cannot be empty".into()));
+            }
+            if disk.max_bytes < self.max_object_bytes {
+                return Err(ConfigError::Invalid("`cache.disk.max_bytes` cannot be below `max_object_bytes`".into()));
+            }
+        }
+        if self.default_ttl_ms == Some(0) {
+            return Err(ConfigError::Invalid("`cache.default_ttl_ms` must be positive".into()));
+        }
+        if let Some(name) = &self.cache_status {
+            // an sf-token, or the header does not parse
+            let token = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '*')
+                && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~:/".contains(&b));
+            if !token {
+                return Err(ConfigError::Invalid(format!("`cache.cache_status` must be a token, got `{name}`")));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+//! Files a configuration depends on, so `--watch` can follow all of them.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use serde_yaml::Value;
+
+use super::tls::resolve_path;
+
+/// Keys whose string value names a file read while loading or building.
+const FILE_KEYS: &[&str] = &["cert_file", "key_file", "ca_file", "client_cert_file", "client_key_file"];
+
+/// Every file reachable from the config at `path`: the file itself, imported
+/// services, TLS certificates, keys and CA bundles, and static roots.
+///
+/// The walk works on raw YAML, so files that are missing or fail to parse are
+/// still reported and fixing them triggers a reload.
+pub fn referenced_files(path: &Path) -> BTreeSet<PathBuf> {
+    let mut out = BTreeSet::new();
+    visit_file(&absolute(path), &mut out);
+    out
+}
+
+/// Like `referenced_files` for a document that does not live in a file.
+pub fn referenced_by(doc: &str, base_dir: &Path) -> BTreeSet<PathBuf> {
+    let mut out = BTreeSet::new();
+    if let Ok(value) = serde_yaml::from_str::<Value>(doc) {
+        visit_value(&value, &absolute(base_dir), &mut out);
+    }
+    out
+}
+
+fn visit_file(path: &Path, out: &mut BTreeSet<PathBuf>) {
+    // also guards against import cycles
+    if !out.insert(path.to_path_buf()) {
+        return;
+    }
+    let Ok(raw) = fs::read_to_string(path) else { return };
+    let Ok(value) = serde_yaml::from_str::<Value>(&raw) else { return };
+    let base = path.parent().unwrap_or(Path::new("/"));
+    visit_value(&value, base, out);
+}
+
+fn visit_value(value: &Value, base: &Path, out: &mut BTreeSet<PathBuf>) {
+    match value {
+        Value::Mapping(map) => {
+            for (key, v) in map {
+                match (key.as_str(), v) {
+                    (Some("import"), Value::String(p)) => visit_file(&resolve(base, p), out),
+                    (Some(k), Value::String(p)) if FILE_KEYS.contains(&k) => {
+                        out.insert(resolve(base, p));
+                    }
+                    (Some("ca_files"), Value::Sequence(items)) => {
+                        for p in items.iter().filter_map(Value::as_str) {
+                            out.insert(resolve(base, p));
+                        }
+                    }
+                    // the static handler resolves its root against the working directory
+                    (Some("source_dir"), Value::String(p)) => {
+                        out.insert(absolute(Path::new(p)));
+                    }
+                    _ => visit_value(v, base, out),
+                }
+            }
+        }
+        Value::Sequence(items) => items.iter().for_each(|v| visit_value(v, base, out)),
+        Value::Tagged(tagged) => visit_value(&tagged.value, base, out),
+        _ => {}
+    }
+}
+
+fn resolve(base: &Path, p: &str) -> PathBuf {
+    absolute(&resolve_path(base, Path::new(p)))
+}
+
+/// Absolute and free of `.`/`..`, matching the paths the watcher reports.
+fn absolute(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::ParentDir => { out.pop(); }
+            Component::CurDir => {}
+            other => out.push(other),
+        }
+    }
+    out
+}
@@ -0,0 +1,79 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::bind::one_or_many;
+use crate::config::error::ConfigError;
+use crate::config::service::{validate_service_ref, ServiceRef};
+
+/// What clients see when forwarding fails or the upstream answers with an error.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct ErrorPages {
+    #[serde(default)]
+    pub debug: bool, // why forwarding failed goes in the response body; it is only logged otherwise
+    #[serde(default)]
+    pub pages: Vec<ErrorPage>, // the first one that matches is used
+}
+
+/// A response to send instead: a file, a body template, or another service.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct ErrorPage {
+    #[serde(deserialize_with = "one_or_many")]
+    pub on: Vec<ErrorOn>,
+    #[serde(default)]
+    pub status: Option<u16>, // the status replaced if unset
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    #[serde(default)]
+    pub body: Option<String>, // a template over the request, like router `respond`
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>, // templates, with `file` or `body`
+    #[serde(default, rename = "use")]
+    pub service: Option<Box<ServiceRef>>,
+}
+
+/// A status matches upstream answers and the proxy's own errors alike.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ErrorOn {
+    Failure(ProxyFailure),
+    Status(u16),
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyFailure {
+    ConnectError,
+    TlsError,
+    Timeout,
+    Reset,
+}
+
+impl ErrorPages {
+    pub fn validate(&self, base_dir: &Path) -> Result<(), ConfigError> {
+        for page in &self.pages {
+            if page.on.is_empty() {
+                return Err(ConfigError::Invalid("`errors.pages.on` cannot be empty".into()));
+            }
+            let statuses = page.on.iter()
+                .filter_map(|on| match on { ErrorOn::Status(code) => Some(*code), ErrorOn::Failure(_) => None })
+                .chain(page.status);
+            if let Some(code) = statuses.into_iter().find(|c| !(100..=599).contains(c)) {
+                return Err(ConfigError::Invalid(format!("`errors.pages` has invalid status {code}")));
+            }
+            let responses = [page.file.is_some(), page.body.is_some(), page.service.is_some()];
+            if responses.iter().filter(|set| **set).count() != 1 {
+                return Err(ConfigError::Invalid("each of `errors.pages` needs exactly one of `file`, `body` and `use`".into()));
+            }
+            if page.service.is_some() && !page.headers.is_empty() {
+                return Err(ConfigError::Invalid("`errors.pages.headers` cannot be used with `use`".into()));
+            }
+            if let Some(svc) = &page.service {
+                validate_service_ref(svc, base_dir)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use serde::Deserialize;
+
+use crate::config::error::ConfigError;
+
+fn default_path() -> String { "/".into() }
+fn default_interval_ms() -> u64 { 5_000 }
+fn default_probe_timeout_ms() -> u64 { 2_000 }
+fn default_healthy_threshold() -> u32 { 2 }
+fn default_unhealthy_threshold() -> u32 { 3 }
+fn default_max_failures() -> u32 { 5 }
+fn default_eject_ms() -> u64 { 30_000 }
+
+/// How a Forward finds out which targets to leave alone.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct HealthChecks {
+    #[serde(default)]
+    pub active: Option<ActiveHealth>,
+    #[serde(default)]
+    pub passive: Option<PassiveHealth>,
+}
+
+/// Requests sent to every target on a timer.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct ActiveHealth {
+    #[serde(default = "default_path")]
+    pub path: String,
+    #[serde(default)]
+    pub expect_status: Option<Vec<u16>>, // any 2xx or 3xx if unset
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    #[serde(default = "default_probe_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_healthy_threshold")]
+    pub healthy_threshold: u32, // passed probes in a row to bring a target back
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32, // failed probes in a row to take it out
+}
+
+/// Targets taken out for a while after failing real requests.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct PassiveHealth {
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32, // 5xx answers, connect errors and timeouts in a row
+    #[serde(default = "default_eject_ms")]
+    pub eject_ms: u64,
+}
+
+impl ActiveHealth {
+    pub fn expects(&self, status: u16) -> bool {
+        match &self.expect_status {
+            Some(codes) => codes.contains(&status),
+            None => (200..400).contains(&status),
+        }
+    }
+}
+
+impl HealthChecks {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(active) = &self.active {
+            if !active.path.starts_with('/') {
+                return Err(ConfigError::Invalid("`health.active.path` must start with '/'".into()));
+            }
+            if active.interval_ms == 0 || active.timeout_ms == 0 {
+                return Err(ConfigError::Invalid("`health.active` durations must be positive".into()));
+            }
+            if active.healthy_threshold == 0 || active.unhealthy_threshold == 0 {
+                return Err(ConfigError::Invalid("`health.active` thresholds must be positive".into()));
+            }
+            if let Some(code) = active.expect_status.iter().flatten().find(|c| !(100..=599).contains(*c)) {
+                return Err(ConfigError::Invalid(format!("`health.active.expect_status` has invalid status {code}")));
+            }
+        }
+        if let Some(passive) = &self.passive
+            && (passive.max_failures == 0 || passive.eject_ms == 0) {
+            return Err(ConfigError::Invalid("`health.passive` settings must be positive".into()));
+        }
+        Ok(())
+    }
+}
@@ -1,35 +1,177 @@
+pub mod errors;
+pub mod health;
 pub mod tls;
 
 use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
+use super::bind::one_or_many;
+use super::http_method::HttpMethod;
 use super::http_version::{HttpVersion, default_http_version};
 use super::url_scheme::Scheme;
+use crate::util::cidr::IpCidr;
 
 fn default_true() -> bool { true }
+fn default_pool_idle_timeout_ms() -> u64 { 90_000 }
+fn default_weight() -> u32 { 1 }
+fn default_retry_attempts() -> u32 { 2 }
+fn default_retry_on() -> Vec<RetryOn> { vec![RetryOn::Failure(RetryFailure::ConnectError), RetryOn::Failure(RetryFailure::Reset)] }
+fn default_backoff_ms() -> u64 { 25 }
+fn default_max_backoff_ms() -> u64 { 1_000 }
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct ForwardService {
-    pub target: ForwardTarget,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub target: Vec<ForwardTarget>, // requests are spread over these by `balance`
+    #[serde(default)]
+    pub dynamic_target: Option<DynamicTarget>, // instead of `target`
+    #[serde(default)]
+    pub balance: Balance,
     #[serde(default)]
     pub pass_host: PassHost,
     #[serde(default = "default_true")]
     pub x_forwarded: bool,
+    #[serde(default)]
+    pub forwarded: bool, // RFC 7239 `Forwarded` header
+    #[serde(default)]
+    pub via: Option<String>, // name this proxy goes by in `Via` headers, both ways; none if unset
+    #[serde(default)]
+    pub response_headers: HeaderFilter,
+    #[serde(default)]
+    pub rewrite_redirects: bool, // `Location`, `Content-Location` and `Refresh` into the target point back at the public host and path
+    #[serde(default)]
+    pub rewrite_cookies: bool, // `Set-Cookie` `Domain` of the target becomes the public host, `Path` loses `path_prefix`
+    #[serde(default)]
+    pub max_request_body_bytes: Option<u64>, // 413 when exceeded, on top of the listener's `max_body_bytes`
     #[serde(default, flatten)]
     pub timeouts: Timeouts,
     #[serde(default = "default_http_version")]
-    pub http_version: HttpVersion,
+    pub http_version: HttpVersion, // 2: h2 through ALPN over TLS, prior knowledge (h2c) over cleartext
+    #[serde(default)]
+    pub tls: Option<tls::TlsUpstream>, // for `scheme: https`
+    #[serde(default)]
+    pub pool: UpstreamPool,
+    #[serde(default)]
+    pub health: health::HealthChecks,
     #[serde(default)]
-    pub tls: Option<tls::TlsUpstream>,
+    pub retry: Option<RetryPolicy>,
+    #[serde(default)]
+    pub errors: errors::ErrorPages,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ForwardTarget {
     pub scheme: Scheme,
-    pub host: String,
-    pub port: u16,
+    #[serde(default)]
+    pub host: String, // may be omitted with `unix`, the upstream then sees `localhost`
+    #[serde(default)]
+    pub port: Option<u16>, // default port of the scheme
+    #[serde(default)]
+    pub unix: Option<PathBuf>, // connect over this Unix domain socket instead of TCP
     #[serde(default)]
     pub path_prefix: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32, // share of requests relative to the other targets
+}
+
+impl ForwardTarget {
+    /// Host sent to the upstream, in the request URI and `pass_host: target`.
+    pub fn host(&self) -> &str {
+        if self.host.is_empty() && self.unix.is_some() { "localhost" } else { &self.host }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(match self.scheme {
+            Scheme::Http => 80,
+            Scheme::Https => 443,
+        })
+    }
+
+    /// Where connections go, as shown in logs.
+    pub fn endpoint(&self) -> String {
+        match &self.unix {
+            Some(path) => format!("unix:{}", path.display()),
+            None => format!("{}:{}", self.host(), self.port()),
+        }
+    }
+}
+
+/// The upstream taken from the request URI, as a Router in front may have
+/// rewritten it, rather than from a fixed `target`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct DynamicTarget {
+    pub allow: Vec<String>, // host names, `*.example.com` for any subdomain, IPs or CIDRs; others get 403
+    #[serde(default = "default_dynamic_ports")]
+    pub ports: Vec<u16>,
+    /// Upstreams whose connection pools are kept; the least recently used
+    /// one is dropped for a new one past this.
+    #[serde(default = "default_max_upstreams")]
+    pub max_upstreams: usize,
+}
+
+fn default_dynamic_ports() -> Vec<u16> { vec![80, 443] }
+fn default_max_upstreams() -> usize { 256 }
+
+/// One `dynamic_target.allow` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowedHost {
+    Name(String),
+    /// Any name under this domain, from `*.domain`; not the domain itself.
+    Subdomains(String),
+    /// IP address hosts only: names are not resolved to be checked.
+    Network(IpCidr),
+}
+
+impl AllowedHost {
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        match self {
+            AllowedHost::Name(name) => host.eq_ignore_ascii_case(name),
+            AllowedHost::Subdomains(domain) => host.to_ascii_lowercase()
+                .strip_suffix(domain.as_str())
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            AllowedHost::Network(net) => host.trim_start_matches('[').trim_end_matches(']')
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| net.contains(&ip)),
+        }
+    }
+}
+
+impl FromStr for AllowedHost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().trim_end_matches('.');
+        if s.contains('/') || s.parse::<IpAddr>().is_ok() {
+            return s.parse().map(AllowedHost::Network);
+        }
+        let (wildcard, name) = match s.strip_prefix("*.") {
+            Some(domain) => (true, domain),
+            None => (false, s),
+        };
+        let valid_label = |l: &str| !l.is_empty() && l.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !name.split('.').all(valid_label) {
+            return Err(format!("invalid host `{s}`"));
+        }
+        let name = name.to_ascii_lowercase();
+        Ok(if wildcard { AllowedHost::Subdomains(name) } else { AllowedHost::Name(name) })
+    }
+}
+
+/// How requests are spread over the targets.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Balance {
+    #[default]
+    RoundRobin,
+    LeastConnections, // fewest requests in flight, relative to weight
+    RandomTwoChoices, // the less busy of two targets picked at random
+    Hash(String), // template; equal keys go to the same target while the targets stay the same
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -44,19 +186,117 @@ impl Default for PassHost {
     fn default() -> Self { PassHost::Mode(PassHostMode::Incoming) }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct Timeouts {
-    pub connect_ms: Option<u32>,
-    pub read_ms: Option<u32>,
-    pub write_ms: Option<u32>,
+    pub connect_ms: Option<u32>, // opening the connection, TLS handshake excluded
+    pub read_ms: Option<u32>, // for the response head once the request is sent, then between response body chunks
+    pub write_ms: Option<u32>, // between request body chunks
+    pub upgrade_idle_ms: Option<u32>, // silence either way on an upgraded connection, 5 minutes if unset
+}
+
+impl Timeouts {
+    pub fn connect(&self) -> Option<Duration> {
+        self.connect_ms.map(|ms| Duration::from_millis(ms.into()))
+    }
+
+    pub fn read(&self) -> Option<Duration> {
+        self.read_ms.map(|ms| Duration::from_millis(ms.into()))
+    }
+
+    pub fn write(&self) -> Option<Duration> {
+        self.write_ms.map(|ms| Duration::from_millis(ms.into()))
+    }
+
+    pub fn upgrade_idle(&self) -> Duration {
+        Duration::from_millis(self.upgrade_idle_ms.map_or(300_000, u64::from))
+    }
 }
 
-impl Default for Timeouts {
+/// Connections to the upstream, kept open and reused across requests.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", default)]
+pub struct UpstreamPool {
+    pub max_idle_per_host: Option<usize>, // 0 opens a new connection per request
+    pub idle_timeout_ms: u64, // idle connections are closed after this
+    pub tcp_keepalive_ms: Option<u64>, // TCP keepalive probes on upstream connections
+    pub tcp_nodelay: bool,
+}
+
+impl Default for UpstreamPool {
     fn default() -> Self {
-        Timeouts {
-            connect_ms: None,
-            read_ms: None,
-            write_ms: None,
+        Self {
+            max_idle_per_host: None,
+            idle_timeout_ms: default_pool_idle_timeout_ms(),
+            tcp_keepalive_ms: None,
+            tcp_nodelay: true,
+        }
+    }
+}
+
+/// Which upstream response headers reach the client. Hop-by-hop headers
+/// never do, whatever is listed.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct HeaderFilter {
+    #[serde(default)]
+    pub allow: Option<Vec<String>>, // only these if set
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl HeaderFilter {
+    pub fn passes(&self, name: &str) -> bool {
+        let listed = |names: &[String]| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+        self.allow.as_deref().is_none_or(listed) && !listed(&self.deny)
+    }
+}
+
+/// Trying a request again after it failed, on another target when there is one.
+/// Only requests without a body are retried, as they are not kept for a replay.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct RetryPolicy {
+    #[serde(default = "default_retry_attempts")]
+    pub attempts: u32, // tries in total, the first one included
+    #[serde(default = "default_retry_on")]
+    pub on: Vec<RetryOn>,
+    #[serde(default)]
+    pub methods: Option<Vec<HttpMethod>>, // idempotent methods if unset
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64, // before the first retry, doubling for each one after
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    #[serde(default)]
+    pub budget_ms: Option<u64>, // no retry starts later than this after the first try
+}
+
+/// What a retry is made for: a failure, or an upstream answer with this status.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum RetryOn {
+    Failure(RetryFailure),
+    Status(u16),
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryFailure {
+    ConnectError,
+    Reset, // the connection broke before a response came
+    Timeout,
+}
+
+impl RetryPolicy {
+    pub fn allows(&self, method: &HttpMethod) -> bool {
+        match &self.methods {
+            Some(methods) => methods.contains(method),
+            None => matches!(method, HttpMethod::Get | HttpMethod::Head | HttpMethod::Options | HttpMethod::Put | HttpMethod::Delete),
         }
     }
+
+    /// How long to wait before retry number `retry`, counting from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let ms = self.backoff_ms.saturating_mul(1u64 << retry.saturating_sub(1).min(32));
+        Duration::from_millis(ms.min(self.max_backoff_ms))
+    }
 }
@@ -55,3 +55,26 @@ pub enum TlsVersion {
     #[serde(rename = "1.2")] V12,
     #[serde(rename = "1.3")] V13,
 }
+
+impl Default for TlsUpstream {
+    /// What an `https` target without a `tls` section uses.
+    fn default() -> Self {
+        TlsUpstream {
+            enabled: true,
+            sni: None,
+            alpn: default_alpn(),
+            use_system_roots: true,
+            ca_file: None,
+            ca_files: None,
+            ca_inline: None,
+            allow_invalid_hostnames: false,
+            insecure_skip_verify: false,
+            client_cert_file: None,
+            client_key_file: None,
+            min_tls: default_min_tls(),
+            max_tls: default_max_tls(),
+            cipher_suites: None,
+            handshake_timeout_ms: None,
+        }
+    }
+}
@@ -5,16 +5,28 @@ use super::error::ConfigError;
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 
-use super::service::{validate_service, resolve_service_ref, ServiceRef};
+use super::service::{validate_service_ref, ServiceRef};
+use super::bind::{one_or_many, BindAddr, UnixSocketOptions};
+use super::limits::ListenerLimits;
+use crate::util::cidr::IpCidr;
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct HttpServer {
     #[serde(default)]
     pub name: Option<String>,
-    pub bind: String, // listened host + port
+    #[serde(deserialize_with = "one_or_many")]
+    pub bind: Vec<String>, // listened host + port, one or several
     #[serde(default)]
     pub tls: Option<super::tls::TlsConfig>,
+    #[serde(default)]
+    pub proxy_protocol: bool, // expect a PROXY v1/v2 header on every connection
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>, // CIDRs allowed to set X-Forwarded-For / Forwarded
+    #[serde(default)]
+    pub unix_socket: UnixSocketOptions, // applied to the sockets of `unix:` binds
+    #[serde(default)]
+    pub limits: ListenerLimits,
     pub service: ServiceRef,
     #[serde(skip)]
     pub base_dir: Option<PathBuf>,
@@ -36,23 +48,34 @@ impl HttpServer {
     }
 
     pub fn validate(&self) -> Result<(), ConfigError> {
-        if self.bind.trim().is_empty() {
+        if self.bind.is_empty() {
             return Err(ConfigError::Invalid("`bind` cannot be empty".into()));
         }
-        if let Some(name) = &self.name {
-            if name.trim().is_empty() {
-                return Err(ConfigError::Invalid("`name` cannot be empty if provided".into()));
+        let mut binds = HashSet::new();
+        for b in &self.bind {
+            let addr = b.parse::<BindAddr>().map_err(ConfigError::Invalid)?;
+            if !binds.insert(addr) {
+                return Err(ConfigError::Invalid(format!("`bind` lists `{b}` more than once")));
             }
         }
-        if let Some(tls) = &self.tls {
-            if tls.enabled && (tls.cert_file.exists() || tls.key_file.exists()) {
-                return Err(ConfigError::Invalid("`tls.enabled=true` requires `cert_file` & `key_file`".into()));
-            }
+        if let Some(name) = &self.name
+            && name.trim().is_empty() {
+            return Err(ConfigError::Invalid("`name` cannot be empty if provided".into()));
+        }
+        for cidr in &self.trusted_proxies {
+            cidr.parse::<IpCidr>()
+                .map_err(|e| ConfigError::Invalid(format!("`trusted_proxies`: {e}")))?;
         }
+        if self.proxy_protocol && self.trusted_proxies.is_empty() {
+            return Err(ConfigError::Invalid("`proxy_protocol` needs `trusted_proxies` to accept headers from".into()));
+        }
+        self.unix_socket.validate()?;
+        self.limits.validate()?;
         let base = self.base_dir.as_deref().unwrap_or(Path::new("."));
-        let mut stack = HashSet::new();
-        let resolved = resolve_service_ref(&self.service, base, &mut stack)?;
-        validate_service(&resolved, base)?;
-        Ok(())
+        if let Some(tls) = &self.tls
+            && tls.enabled && !(tls.cert_path(base).is_file() && tls.key_path(base).is_file()) {
+            return Err(ConfigError::Invalid("`tls.enabled=true` requires `cert_file` & `key_file`".into()));
+        }
+        validate_service_ref(&self.service, base)
     }
 }
@@ -1,16 +1,32 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
 pub fn default_http_version() -> HttpVersion { HttpVersion::V1_1 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
-#[serde(rename_all = "lowercase")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpVersion {
-    #[serde(rename = "1.1")]
     V1_1,
-    #[serde(rename = "2")]
     V2,
 }
 
+/// Written `"1.1"` / `"2"`, or unquoted as YAML numbers.
+impl<'de> Deserialize<'de> for HttpVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw { Text(String), Number(f64) }
+
+        let text = match Raw::deserialize(deserializer)? {
+            Raw::Text(s) => s,
+            Raw::Number(n) => n.to_string(),
+        };
+        match text.as_str() {
+            "1.1" => Ok(HttpVersion::V1_1),
+            "2" | "2.0" => Ok(HttpVersion::V2),
+            other => Err(serde::de::Error::custom(format!("unknown HTTP version `{other}`, expected 1.1 or 2"))),
+        }
+    }
+}
+
 pub fn default_alpn() -> Vec<AlpnProto> { vec![AlpnProto::Http1_1] }
 
 #[derive(Debug, Deserialize, Clone, Copy)]
@@ -21,3 +37,13 @@ pub enum AlpnProto {
     #[serde(rename = "http/2", alias = "h2")]
     Http2,
 }
+
+impl AlpnProto {
+    /// Protocol id as sent on the wire during ALPN negotiation.
+    pub fn protocol_id(&self) -> &'static [u8] {
+        match self {
+            AlpnProto::Http1_1 => b"http/1.1",
+            AlpnProto::Http2 => b"h2",
+        }
+    }
+}
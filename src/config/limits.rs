@@ -0,0 +1,54 @@
+use serde::Deserialize;
+
+use super::error::ConfigError;
+
+fn default_header_read_timeout_ms() -> u64 { 30_000 }
+fn default_idle_timeout_ms() -> u64 { 75_000 }
+
+/// Hyper refuses read buffers smaller than this, and the buffer bounds the request head.
+const MIN_HEADER_BYTES: usize = 8 * 1024;
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", default)]
+pub struct ListenerLimits {
+    pub max_connections: Option<usize>, // further connections get 503
+    pub header_read_timeout_ms: u64, // request head incl. TLS / PROXY handshake, 408 on expiry
+    pub idle_timeout_ms: u64, // keep-alive connections without traffic are closed
+    pub max_header_bytes: Option<usize>, // 431 when exceeded
+    pub max_headers: Option<usize>, // 431 when exceeded (HTTP/1)
+    pub max_body_bytes: Option<u64>, // 413 when exceeded
+}
+
+impl Default for ListenerLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            header_read_timeout_ms: default_header_read_timeout_ms(),
+            idle_timeout_ms: default_idle_timeout_ms(),
+            max_header_bytes: None,
+            max_headers: None,
+            max_body_bytes: None,
+        }
+    }
+}
+
+impl ListenerLimits {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_connections == Some(0) {
+            return Err(ConfigError::Invalid("`limits.max_connections` must be positive".into()));
+        }
+        if self.header_read_timeout_ms == 0 || self.idle_timeout_ms == 0 {
+            return Err(ConfigError::Invalid("`limits` timeouts must be positive".into()));
+        }
+        if let Some(n) = self.max_header_bytes
+            && (n < MIN_HEADER_BYTES || n > u32::MAX as usize) {
+            return Err(ConfigError::Invalid(format!(
+                "`limits.max_header_bytes` must be between {MIN_HEADER_BYTES} and {}", u32::MAX
+            )));
+        }
+        if self.max_headers == Some(0) {
+            return Err(ConfigError::Invalid("`limits.max_headers` must be positive".into()));
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,14 @@
 pub mod http_server;
+pub mod bind;
 pub mod tls;
+pub mod limits;
 pub mod service;
 pub mod r#static;
 pub mod router;
 pub mod forward;
+pub mod cache;
 pub mod url_scheme;
 pub mod http_version;
 pub mod http_method;
 pub mod error;
+pub mod files;
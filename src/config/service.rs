@@ -3,20 +3,26 @@ use serde::Deserialize;
 use super::error::ConfigError;
 
 use super::{
+    cache::CacheService,
     r#static::StaticService,
-    router::RouterService,
-    forward::ForwardService,
+    router::{op::RouterOp, RouterService},
+    forward::{AllowedHost, DynamicTarget, ForwardService, ForwardTarget, RetryOn, RetryPolicy},
+    url_scheme::Scheme,
 };
 use std::collections::HashSet;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
+/// Weights are relative, so this is plenty and keeps hash rings small.
+const MAX_WEIGHT: u32 = 1000;
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(tag = "handler", rename_all = "lowercase")]
 pub enum Service {
     Static(StaticService),
     Router(RouterService),
-    Forward(ForwardService),
+    Forward(Box<ForwardService>),
+    Cache(CacheService),
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -64,17 +70,144 @@ pub fn validate_service(svc: &Service, base_dir: &Path) -> Result<(), ConfigErro
             if rt.rules.is_empty() {
                 return Err(ConfigError::Invalid("`router.rules` cannot be empty".into()));
             }
+            for rule in &rt.rules {
+                validate_ops(&rule.ops, base_dir)?;
+            }
             if let Some(n) = &rt.next {
-                let mut stack = HashSet::new();
-                let resolved = resolve_service_ref(n, base_dir, &mut stack)?;
-                validate_service(&resolved, base_dir)?;
+                validate_service_ref(n, base_dir)?;
             }
         }
         Service::Forward(fw) => {
-            if fw.target.host.trim().is_empty() {
-                return Err(ConfigError::Invalid("`forward.target.host` cannot be empty".into()));
+            match &fw.dynamic_target {
+                Some(_) if !fw.target.is_empty() =>
+                    return Err(ConfigError::Invalid("`forward.target` and `forward.dynamic_target` cannot both be set".into())),
+                Some(dynamic) => validate_dynamic_target(dynamic, fw)?,
+                None if fw.target.is_empty() =>
+                    return Err(ConfigError::Invalid("`forward.target` cannot be empty".into())),
+                None => {}
+            }
+            for target in &fw.target {
+                validate_target(target, fw)?;
+            }
+            if fw.pool.idle_timeout_ms == 0 || fw.pool.tcp_keepalive_ms == Some(0) {
+                return Err(ConfigError::Invalid("`forward.pool` durations must be positive".into()));
+            }
+            let t = &fw.timeouts;
+            if [t.connect_ms, t.read_ms, t.write_ms, t.upgrade_idle_ms].contains(&Some(0)) {
+                return Err(ConfigError::Invalid("`forward` timeouts must be positive".into()));
+            }
+            if fw.max_request_body_bytes == Some(0) {
+                return Err(ConfigError::Invalid("`forward.max_request_body_bytes` must be positive".into()));
+            }
+            validate_headers(fw)?;
+            fw.health.validate()?;
+            fw.errors.validate(base_dir)?;
+            if let Some(retry) = &fw.retry {
+                validate_retry(retry)?;
+            }
+        }
+        Service::Cache(cache) => {
+            cache.validate()?;
+            validate_service_ref(&cache.service, base_dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Resolve `svc` and validate the service it names.
+pub fn validate_service_ref(svc: &ServiceRef, base_dir: &Path) -> Result<(), ConfigError> {
+    let mut stack = HashSet::new();
+    let resolved = resolve_service_ref(svc, base_dir, &mut stack)?;
+    validate_service(&resolved, base_dir)
+}
+
+/// Validate the services router ops `use`, branches included.
+fn validate_ops(ops: &[RouterOp], base_dir: &Path) -> Result<(), ConfigError> {
+    for op in ops {
+        match op {
+            RouterOp::Use(svc) => validate_service_ref(svc, base_dir)?,
+            RouterOp::Branch(branch) => {
+                validate_ops(&branch.then, base_dir)?;
+                validate_ops(&branch.r#else, base_dir)?;
             }
+            _ => {}
         }
     }
     Ok(())
 }
+
+fn validate_dynamic_target(dynamic: &DynamicTarget, fw: &ForwardService) -> Result<(), ConfigError> {
+    if dynamic.allow.is_empty() {
+        return Err(ConfigError::Invalid("`forward.dynamic_target.allow` cannot be empty".into()));
+    }
+    for entry in &dynamic.allow {
+        entry.parse::<AllowedHost>()
+            .map_err(|e| ConfigError::Invalid(format!("`forward.dynamic_target.allow`: {e}")))?;
+    }
+    if dynamic.ports.is_empty() || dynamic.ports.contains(&0) {
+        return Err(ConfigError::Invalid("`forward.dynamic_target.ports` must list valid ports".into()));
+    }
+    if dynamic.max_upstreams == 0 {
+        return Err(ConfigError::Invalid("`forward.dynamic_target.max_upstreams` must be positive".into()));
+    }
+    if fw.health.active.is_some() {
+        return Err(ConfigError::Invalid("`forward.health.active` needs fixed targets to probe".into()));
+    }
+    Ok(())
+}
+
+fn validate_headers(fw: &ForwardService) -> Result<(), ConfigError> {
+    if let Some(via) = &fw.via {
+        // a `received-by` pseudonym is a single token
+        if via.is_empty() || !via.bytes().all(|b| b.is_ascii_graphic() && !b",;()\"".contains(&b)) {
+            return Err(ConfigError::Invalid(format!("`forward.via` must be a single token, got `{via}`")));
+        }
+    }
+    let filter = &fw.response_headers;
+    for name in filter.allow.iter().flatten().chain(&filter.deny) {
+        if hyper::http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+            return Err(ConfigError::Invalid(format!("`forward.response_headers` has invalid header name `{name}`")));
+        }
+    }
+    Ok(())
+}
+
+fn validate_retry(retry: &RetryPolicy) -> Result<(), ConfigError> {
+    if retry.attempts == 0 {
+        return Err(ConfigError::Invalid("`forward.retry.attempts` must be positive".into()));
+    }
+    if retry.max_backoff_ms < retry.backoff_ms {
+        return Err(ConfigError::Invalid("`forward.retry.max_backoff_ms` cannot be below `backoff_ms`".into()));
+    }
+    if retry.budget_ms == Some(0) {
+        return Err(ConfigError::Invalid("`forward.retry.budget_ms` must be positive".into()));
+    }
+    for on in &retry.on {
+        if let RetryOn::Status(code) = on
+            && !(100..=599).contains(code) {
+            return Err(ConfigError::Invalid(format!("`forward.retry.on` has invalid status {code}")));
+        }
+    }
+    Ok(())
+}
+
+fn validate_target(target: &ForwardTarget, fw: &ForwardService) -> Result<(), ConfigError> {
+    if target.host.trim().is_empty() && target.unix.is_none() {
+        return Err(ConfigError::Invalid("`forward.target.host` cannot be empty".into()));
+    }
+    if target.unix.as_ref().is_some_and(|p| p.as_os_str().is_empty()) {
+        return Err(ConfigError::Invalid("`forward.target.unix` cannot be empty if provided".into()));
+    }
+    if !(1..=MAX_WEIGHT).contains(&target.weight) {
+        return Err(ConfigError::Invalid(format!("`forward.target.weight` must be between 1 and {MAX_WEIGHT}")));
+    }
+    if let Some(tls) = &fw.tls
+        && !tls.enabled && matches!(target.scheme, Scheme::Https) {
+        return Err(ConfigError::Invalid("`forward.tls.enabled: false` contradicts `target.scheme: https`".into()));
+    }
+    #[cfg(not(unix))]
+    if target.unix.is_some() {
+        return Err(ConfigError::Invalid("`forward.target.unix` is not supported on this platform".into()));
+    }
+    Ok(())
+}
@@ -13,6 +13,7 @@ pub struct StaticService {
     #[serde(default = "default_file_404")]
     pub file_404: String,
     #[serde(default = "default_file_500")]
+    #[allow(dead_code)] // WIP
     pub file_500: String,
     #[serde(default = "default_index_strategy")]
     pub index_strategy: IndexStrategy,
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use serde::Deserialize;
 
 use super::http_version::{default_alpn, AlpnProto};
@@ -15,3 +15,19 @@ pub struct TlsConfig {
     #[serde(default = "default_alpn")]
     pub alpn: Vec<AlpnProto>,
 }
+
+impl TlsConfig {
+    /// Certificate chain path, relative paths are resolved against `base_dir`.
+    pub fn cert_path(&self, base_dir: &Path) -> PathBuf { resolve_path(base_dir, &self.cert_file) }
+
+    /// Private key path, relative paths are resolved against `base_dir`.
+    pub fn key_path(&self, base_dir: &Path) -> PathBuf { resolve_path(base_dir, &self.key_file) }
+}
+
+pub fn resolve_path(base_dir: &Path, path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base_dir.join(path)
+    }
+}
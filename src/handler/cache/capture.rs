@@ -0,0 +1,71 @@
+//! A response body passed on to the client while a copy is kept for the cache.
+
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::{Bytes, BytesMut};
+use hyper::body::{Body, Frame, SizeHint};
+
+use crate::handler::{BoxError, ResponseBody};
+
+type Done = Box<dyn FnOnce(Bytes) + Send + Sync>;
+
+/// Streams `body` and hands the whole of it to `done` at the end, unless it
+/// grew past `limit`, failed, or had trailers.
+pub struct Capture {
+    body: ResponseBody,
+    copy: Option<BytesMut>,
+    limit: usize,
+    done: Option<Done>,
+}
+
+impl Capture {
+    pub fn new(body: ResponseBody, limit: usize, done: impl FnOnce(Bytes) + Send + Sync + 'static) -> Self {
+        Capture { body, copy: Some(BytesMut::new()), limit, done: Some(Box::new(done)) }
+    }
+
+    fn finish(&mut self) {
+        if let (Some(copy), Some(done)) = (self.copy.take(), self.done.take()) {
+            done(copy.freeze());
+        }
+    }
+
+    /// Stop copying; `done` goes now, with whatever it holds, rather than
+    /// with the rest of the body.
+    fn give_up(&mut self) {
+        self.copy = None;
+        self.done = None;
+    }
+}
+
+impl Body for Capture {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let this = &mut *self;
+        let frame = ready!(Pin::new(&mut this.body).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => match (frame.data_ref(), &mut this.copy) {
+                (Some(data), Some(copy)) if copy.len() + data.len() <= this.limit => copy.extend_from_slice(data),
+                // trailers are not stored, so neither is what they belong to
+                _ => this.give_up(),
+            },
+            Some(Err(_)) => this.give_up(),
+            None => this.finish(),
+        }
+        // the server may not ask again once the body says it is over
+        if this.body.is_end_stream() {
+            this.finish();
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
@@ -0,0 +1,279 @@
+//! `handler: cache`, an HTTP cache (RFC 9111) in front of another service.
+
+mod capture;
+mod policy;
+mod store;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::body::Body;
+use hyper::http::{self, header, HeaderMap, HeaderValue, Method, StatusCode};
+
+use crate::build::service::LoadedCache;
+use crate::handler::router::RouterCtx;
+use crate::handler::{empty, full, BoxResponseFuture, RequestBody, ResponseBody, ServiceHandler};
+use crate::template::expand_template;
+use crate::util::http::{ClientAddr, ListenerScheme, PeerAddr};
+
+use capture::Capture;
+use policy::{not_modified, storable, CacheControl};
+use store::{Entry, Fetch, FetchGuard};
+pub use store::Store;
+
+/// How long a miss waits for another request fetching the same key before
+/// it goes to the service itself.
+const FETCH_WAIT: Duration = Duration::from_secs(5);
+
+impl ServiceHandler for LoadedCache {
+    fn handle_request<'a>(&'a self, req: &'a mut http::Request<RequestBody>) -> BoxResponseFuture<'a> {
+        Box::pin(self.serve(req))
+    }
+}
+
+impl LoadedCache {
+    async fn serve(&self, req: &mut http::Request<RequestBody>) -> http::Response<ResponseBody> {
+        let req_cc = CacheControl::parse(req.headers());
+        let usable = matches!(*req.method(), Method::GET | Method::HEAD)
+            && !req_cc.has("no-store")
+            // ranges and upgrades are left to the service
+            && !req.headers().contains_key(header::RANGE)
+            && !req.headers().contains_key(header::UPGRADE);
+        let Some(key) = usable.then(|| self.key(req)).flatten() else {
+            let resp = self.service.handle_request(req).await;
+            return self.cache_status(resp, "fwd=bypass");
+        };
+
+        let mut waited = false;
+        loop {
+            if let Some(entry) = self.store.lookup(&key, req.headers()) {
+                return self.cached(req, key, entry, &req_cc).await;
+            }
+            // answered, but only GET responses are stored
+            if *req.method() == Method::HEAD || waited {
+                return self.fetch(req, key, None, "fwd=miss").await;
+            }
+            match self.store.fetch(&key) {
+                Fetch::Leader(guard) => return self.fetch(req, key, Some(guard), "fwd=miss").await,
+                Fetch::Follower(mut done) => {
+                    // never sent, only closed once the response is stored or given up on
+                    let _ = tokio::time::timeout(FETCH_WAIT, done.changed()).await;
+                    waited = true;
+                }
+            }
+        }
+    }
+
+    fn key(&self, req: &http::Request<RequestBody>) -> Option<String> {
+        match &self.key {
+            Some(template) => expand_template(template, &RouterCtx::from_request(req)).ok(),
+            None if req.uri().authority().is_some() => Some(req.uri().to_string()),
+            None => {
+                let host = req.headers().get(header::HOST).and_then(|h| h.to_str().ok()).unwrap_or_default();
+                Some(format!("{host}{}", req.uri()))
+            }
+        }
+    }
+
+    /// Answer from `entry`, revalidating it first if it is stale or the client asks.
+    async fn cached(
+        &self,
+        req: &mut http::Request<RequestBody>,
+        key: String,
+        entry: Arc<Entry>,
+        req_cc: &CacheControl,
+    ) -> http::Response<ResponseBody> {
+        let (age, freshness) = (entry.age(), entry.freshness);
+        let pragma = !req.headers().contains_key(header::CACHE_CONTROL)
+            && req.headers().get(header::PRAGMA).is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"no-cache"));
+        let requested = pragma || req_cc.has("no-cache") || req_cc.seconds("max-age").is_some_and(|max| age > max);
+        if !requested && age < freshness.lifetime {
+            let ttl = (freshness.lifetime - age).as_secs();
+            if let Some(resp) = self.respond(req.method(), req.headers(), &entry, &format!("hit; ttl={ttl}")).await {
+                return resp;
+            }
+            return self.fetch(req, key, None, "fwd=miss").await;
+        }
+        if !requested && age < freshness.lifetime + freshness.stale_while_revalidate {
+            self.revalidate_in_background(req, &key, &entry);
+            if let Some(resp) = self.respond(req.method(), req.headers(), &entry, "hit; detail=stale-while-revalidate").await {
+                return resp;
+            }
+            return self.fetch(req, key, None, "fwd=miss").await;
+        }
+        self.revalidate(req, key, entry, if requested { "fwd=request" } else { "fwd=stale" }).await
+    }
+
+    /// Ask the service whether `entry` still holds, answering from it if so.
+    async fn revalidate(
+        &self,
+        req: &mut http::Request<RequestBody>,
+        key: String,
+        entry: Arc<Entry>,
+        fwd: &str,
+    ) -> http::Response<ResponseBody> {
+        let (method, req_headers) = (req.method().clone(), req.headers().clone());
+        let validated = set_validators(req.headers_mut(), &entry);
+        let resp = self.service.handle_request(req).await;
+        let status = resp.status();
+        if validated && status == StatusCode::NOT_MODIFIED {
+            let entry = entry.refreshed(resp.headers());
+            self.store.put(key.clone(), entry.clone());
+            let detail = format!("{fwd}; fwd-status=304");
+            if let Some(resp) = self.respond(&method, &req_headers, &entry, &detail).await {
+                return resp;
+            }
+            *req.headers_mut() = req_headers;
+            return self.fetch(req, key, None, "fwd=miss").await;
+        }
+        let failed = status.is_server_error() && status != StatusCode::NOT_IMPLEMENTED;
+        if failed && entry.age() < entry.freshness.lifetime + entry.freshness.stale_if_error {
+            let detail = format!("{fwd}; fwd-status={}; detail=stale-if-error", status.as_u16());
+            if let Some(stale) = self.respond(&method, &req_headers, &entry, &detail).await {
+                return stale;
+            }
+        }
+        self.keep(&method, &req_headers, key, resp, None, &format!("{fwd}; fwd-status={}", status.as_u16()))
+    }
+
+    /// Refresh a stale `entry` while it is still being served, unless that is under way.
+    fn revalidate_in_background(&self, req: &http::Request<RequestBody>, key: &str, entry: &Arc<Entry>) {
+        let Fetch::Leader(guard) = self.store.fetch(key) else { return };
+        let mut bg = http::Request::new(empty());
+        *bg.uri_mut() = req.uri().clone();
+        *bg.version_mut() = req.version();
+        *bg.headers_mut() = req.headers().clone();
+        // who asked, for `X-Forwarded-For` and the like; not the connection itself
+        if let Some(client) = req.extensions().get::<ClientAddr>() {
+            bg.extensions_mut().insert(*client);
+        }
+        if let Some(peer) = req.extensions().get::<PeerAddr>() {
+            bg.extensions_mut().insert(*peer);
+        }
+        if let Some(scheme) = req.extensions().get::<ListenerScheme>() {
+            bg.extensions_mut().insert(scheme.clone());
+        }
+        let req_headers = req.headers().clone();
+        let validated = set_validators(bg.headers_mut(), entry);
+        let (service, store, entry, key) = (self.service.clone(), self.store.clone(), entry.clone(), key.to_string());
+        let default_ttl = self.config.default_ttl_ms.map(Duration::from_millis);
+        tokio::spawn(async move {
+            let resp = service.handle_request(&mut bg).await;
+            if validated && resp.status() == StatusCode::NOT_MODIFIED {
+                store.put(key, entry.refreshed(resp.headers()));
+            } else if let Some(freshness) = storable(&Method::GET, &req_headers, resp.status(), resp.headers(), default_ttl) {
+                let (parts, body) = resp.into_parts();
+                let limit = usize::try_from(store.max_object_bytes()).unwrap_or(usize::MAX);
+                let store_as = move |body| store.put(key, Entry::new(parts.status, parts.headers, body, &req_headers, freshness));
+                // read through as a client would, nobody is waiting for it
+                let _ = Capture::new(body, limit, store_as).collect().await;
+            }
+            drop(guard);
+        });
+    }
+
+    /// Pass the request on, storing what comes back if it may be.
+    async fn fetch(
+        &self,
+        req: &mut http::Request<RequestBody>,
+        key: String,
+        guard: Option<FetchGuard>,
+        detail: &str,
+    ) -> http::Response<ResponseBody> {
+        let (method, req_headers) = (req.method().clone(), req.headers().clone());
+        let resp = self.service.handle_request(req).await;
+        self.keep(&method, &req_headers, key, resp, guard, detail)
+    }
+
+    /// `resp` on its way to the client, copied into the store as it goes if
+    /// it may be stored. `guard` is let go once it is.
+    fn keep(
+        &self,
+        method: &Method,
+        req_headers: &HeaderMap,
+        key: String,
+        resp: http::Response<ResponseBody>,
+        guard: Option<FetchGuard>,
+        detail: &str,
+    ) -> http::Response<ResponseBody> {
+        let default_ttl = self.config.default_ttl_ms.map(Duration::from_millis);
+        let Some(freshness) = storable(method, req_headers, resp.status(), resp.headers(), default_ttl) else {
+            return self.cache_status(resp, detail);
+        };
+        let limit = usize::try_from(self.store.max_object_bytes()).unwrap_or(usize::MAX);
+        if resp.body().size_hint().lower() > limit as u64 {
+            return self.cache_status(resp, detail);
+        }
+        let (parts, body) = resp.into_parts();
+        let (store, status, headers, req_headers) = (self.store.clone(), parts.status, parts.headers.clone(), req_headers.clone());
+        let store_as = move |body: Bytes| {
+            store.put(key, Entry::new(status, headers, body, &req_headers, freshness));
+            drop(guard);
+        };
+        let body = if body.is_end_stream() {
+            // nothing to wait for
+            store_as(Bytes::new());
+            body
+        } else {
+            Capture::new(body, limit, store_as).boxed()
+        };
+        self.cache_status(http::Response::from_parts(parts, body), detail)
+    }
+
+    /// The response for `entry`, `None` if its body is gone.
+    async fn respond(
+        &self,
+        method: &Method,
+        req_headers: &HeaderMap,
+        entry: &Entry,
+        detail: &str,
+    ) -> Option<http::Response<ResponseBody>> {
+        let unchanged = entry.status == StatusCode::OK && not_modified(req_headers, &entry.headers);
+        let body = if unchanged || *method == Method::HEAD {
+            empty()
+        } else {
+            full(Store::body(entry).await?)
+        };
+        let mut resp = http::Response::new(body);
+        *resp.headers_mut() = entry.headers.clone();
+        *resp.status_mut() = entry.status;
+        if unchanged {
+            *resp.status_mut() = StatusCode::NOT_MODIFIED;
+            resp.headers_mut().remove(header::CONTENT_LENGTH);
+        }
+        resp.headers_mut().insert(header::AGE, HeaderValue::from(entry.age().as_secs()));
+        Some(self.cache_status(resp, detail))
+    }
+
+    /// Report what the cache did in `Cache-Status`, when asked to (RFC 9211).
+    fn cache_status(&self, mut resp: http::Response<ResponseBody>, detail: &str) -> http::Response<ResponseBody> {
+        let Some(name) = &self.config.cache_status else { return resp };
+        if let Ok(value) = HeaderValue::from_str(&format!("{name}; {detail}")) {
+            resp.headers_mut().append("cache-status", value);
+        }
+        resp
+    }
+}
+
+/// Make `headers` ask whether `entry` is still current; `false` if it has
+/// nothing to ask with.
+fn set_validators(headers: &mut HeaderMap, entry: &Entry) -> bool {
+    // the client's own are answered here, from the entry
+    for name in [header::IF_NONE_MATCH, header::IF_MODIFIED_SINCE, header::IF_MATCH, header::IF_UNMODIFIED_SINCE, header::IF_RANGE] {
+        headers.remove(name);
+    }
+    let etag = entry.headers.get(header::ETAG);
+    if let Some(etag) = etag {
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+    }
+    let modified = entry.headers.get(header::LAST_MODIFIED);
+    if let Some(modified) = modified {
+        headers.insert(header::IF_MODIFIED_SINCE, modified.clone());
+    }
+    etag.is_some() || modified.is_some()
+}
+
+#[cfg(test)]
+mod tests;
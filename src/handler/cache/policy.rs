@@ -0,0 +1,194 @@
+//! What `Cache-Control`, `Expires` and validators say about storing and
+//! reusing a response, for a shared cache (RFC 9111).
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::http::{header, HeaderMap, Method, StatusCode};
+
+/// Statuses that may be stored without explicit freshness (RFC 9110 section 15.1).
+const HEURISTIC_STATUSES: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// The directives of the `Cache-Control` headers of a message.
+#[derive(Debug, Default)]
+pub struct CacheControl(HashMap<String, Option<String>>);
+
+impl CacheControl {
+    pub fn parse(headers: &HeaderMap) -> Self {
+        let mut directives = HashMap::new();
+        let values = headers.get_all(header::CACHE_CONTROL).iter().filter_map(|v| v.to_str().ok());
+        for directive in values.flat_map(|v| v.split(',')) {
+            let (name, arg) = match directive.split_once('=') {
+                Some((name, arg)) => (name, Some(arg.trim().trim_matches('"').to_string())),
+                None => (directive, None),
+            };
+            let name = name.trim().to_ascii_lowercase();
+            if !name.is_empty() {
+                directives.entry(name).or_insert(arg);
+            }
+        }
+        CacheControl(directives)
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    /// The delta-seconds argument of `name`.
+    pub fn seconds(&self, name: &str) -> Option<Duration> {
+        let arg = self.0.get(name)?.as_deref()?;
+        match arg.parse() {
+            Ok(secs) => Some(Duration::from_secs(secs)),
+            // overflowing is as good as forever (RFC 9111 section 1.2.2)
+            Err(_) if !arg.is_empty() && arg.bytes().all(|b| b.is_ascii_digit()) => Some(Duration::from_secs(1 << 31)),
+            Err(_) => None,
+        }
+    }
+}
+
+/// How long a stored response may be used, and stale how long after that.
+#[derive(Debug, Clone, Copy)]
+pub struct Freshness {
+    pub lifetime: Duration,
+    pub stale_while_revalidate: Duration,
+    pub stale_if_error: Duration,
+}
+
+/// The freshness of a response a shared cache may store, `None` if it may not.
+///
+/// `default_ttl` stands in for explicit freshness on statuses cacheable by default.
+pub fn storable(
+    method: &Method,
+    req_headers: &HeaderMap,
+    status: StatusCode,
+    headers: &HeaderMap,
+    default_ttl: Option<Duration>,
+) -> Option<Freshness> {
+    let cc = CacheControl::parse(headers);
+    // neither a 304 nor a 206 is a whole response
+    let partial = matches!(status, StatusCode::NOT_MODIFIED | StatusCode::PARTIAL_CONTENT) || status.is_informational();
+    if *method != Method::GET || partial || cc.has("no-store") || cc.has("private") {
+        return None;
+    }
+    if CacheControl::parse(req_headers).has("no-store") {
+        return None;
+    }
+    if req_headers.contains_key(header::AUTHORIZATION)
+        && !["public", "s-maxage", "must-revalidate"].iter().any(|d| cc.has(d))
+    {
+        return None;
+    }
+    // a cookie is one client's; stored, everyone would get it
+    if headers.contains_key(header::SET_COOKIE) || vary_names(headers).is_none() {
+        return None;
+    }
+    let explicit = cc.seconds("s-maxage")
+        .or_else(|| cc.seconds("max-age"))
+        .or_else(|| expires(headers));
+    let lifetime = match explicit {
+        _ if cc.has("no-cache") => Duration::ZERO,
+        Some(lifetime) => lifetime,
+        None if HEURISTIC_STATUSES.contains(&status.as_u16()) || cc.has("public") => default_ttl?,
+        None => return None,
+    };
+    let has_validator = headers.contains_key(header::ETAG) || headers.contains_key(header::LAST_MODIFIED);
+    if lifetime.is_zero() && !has_validator {
+        // it could never be used
+        return None;
+    }
+    Some(freshness(&cc, lifetime))
+}
+
+/// The freshness of `headers` refreshed by a `304`, keeping `lifetime` when
+/// they no longer say.
+pub fn refreshed(headers: &HeaderMap, lifetime: Duration) -> Freshness {
+    let cc = CacheControl::parse(headers);
+    let lifetime = match cc.seconds("s-maxage").or_else(|| cc.seconds("max-age")).or_else(|| expires(headers)) {
+        _ if cc.has("no-cache") => Duration::ZERO,
+        Some(explicit) => explicit,
+        None => lifetime,
+    };
+    freshness(&cc, lifetime)
+}
+
+fn freshness(cc: &CacheControl, lifetime: Duration) -> Freshness {
+    // nothing stale may be used without the origin's say-so
+    let strict = ["must-revalidate", "proxy-revalidate", "s-maxage", "no-cache"].iter().any(|d| cc.has(d));
+    let window = |name| if strict { Duration::ZERO } else { cc.seconds(name).unwrap_or_default() };
+    Freshness {
+        lifetime,
+        stale_while_revalidate: window("stale-while-revalidate"),
+        stale_if_error: window("stale-if-error"),
+    }
+}
+
+/// `Expires` relative to `Date`; an invalid one is already in the past.
+fn expires(headers: &HeaderMap) -> Option<Duration> {
+    let expires = headers.get(header::EXPIRES)?;
+    let Some(expires) = expires.to_str().ok().and_then(parse_http_date) else { return Some(Duration::ZERO) };
+    let date = headers.get(header::DATE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_http_date)
+        .unwrap_or_else(SystemTime::now);
+    Some(expires.duration_since(date).unwrap_or_default())
+}
+
+/// The request headers named by `Vary`, `None` for `Vary: *`.
+pub fn vary_names(headers: &HeaderMap) -> Option<Vec<String>> {
+    let mut names = Vec::new();
+    let values = headers.get_all(header::VARY).iter().filter_map(|v| v.to_str().ok());
+    for name in values.flat_map(|v| v.split(',')).map(str::trim).filter(|n| !n.is_empty()) {
+        if name == "*" {
+            return None;
+        }
+        names.push(name.to_ascii_lowercase());
+    }
+    Some(names)
+}
+
+/// Whether the client's own `If-None-Match` / `If-Modified-Since` hold for a
+/// response with `headers`, so a `304` does.
+pub fn not_modified(req_headers: &HeaderMap, headers: &HeaderMap) -> bool {
+    if let Some(tags) = req_headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        let Some(etag) = headers.get(header::ETAG).and_then(|v| v.to_str().ok()) else { return false };
+        // weak comparison
+        let etag = etag.trim_start_matches("W/");
+        return tags.split(',').map(str::trim).any(|t| t == "*" || t.trim_start_matches("W/") == etag);
+    }
+    let date = |headers: &HeaderMap, name| headers.get(name).and_then(|v| v.to_str().ok()).and_then(parse_http_date);
+    match (date(req_headers, header::IF_MODIFIED_SINCE), date(headers, header::LAST_MODIFIED)) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+/// An IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`; the obsolete
+/// formats are not accepted.
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let (_, rest) = s.trim().split_once(", ")?;
+    let mut parts = rest.split(' ');
+    let (day, month, year, time, zone) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    if zone != "GMT" || parts.next().is_some() || day.len() != 2 || year.len() != 4 {
+        return None;
+    }
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let (day, year): (i64, i64) = (day.parse().ok()?, year.parse().ok()?);
+    let mut hms = time.split(':').map(|t| t.parse::<i64>().ok().filter(|_| t.len() == 2));
+    let (h, m, sec) = (hms.next()??, hms.next()??, hms.next()??);
+    if hms.next().is_some() || !(1..=31).contains(&day) || h > 23 || m > 59 || sec > 60 {
+        return None;
+    }
+    let secs = days_from_civil(year, month, day) * 86_400 + h * 3_600 + m * 60 + sec;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
@@ -0,0 +1,348 @@
+//! Stored responses: in memory until the least recently used are pushed out,
+//! then on disk when there is one.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use hyper::http::{header, HeaderMap, StatusCode};
+use tokio::sync::watch;
+
+use super::policy::{refreshed, vary_names, Freshness};
+
+/// Stores alive in this process, so their files never collide.
+static STORES: AtomicU64 = AtomicU64::new(0);
+
+/// A response as it was stored.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Stored,
+    /// What the request had for each header named by `Vary`.
+    pub vary: Vec<(String, Option<String>)>,
+    pub freshness: Freshness,
+    /// When it was received or last revalidated.
+    pub stored_at: Instant,
+    /// The `Age` it arrived with.
+    pub initial_age: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub enum Stored {
+    Memory(Bytes),
+    Disk(PathBuf),
+}
+
+impl Entry {
+    pub fn new(status: StatusCode, headers: HeaderMap, body: Bytes, req_headers: &HeaderMap, freshness: Freshness) -> Self {
+        let names = vary_names(&headers).unwrap_or_default();
+        Entry {
+            status,
+            initial_age: initial_age(&headers),
+            headers,
+            body: Stored::Memory(body),
+            vary: vary_values(&names, req_headers),
+            freshness,
+            stored_at: Instant::now(),
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        self.initial_age + self.stored_at.elapsed()
+    }
+
+    /// Whether it answers a request with `req_headers`, as far as `Vary` goes.
+    pub fn matches(&self, req_headers: &HeaderMap) -> bool {
+        let names: Vec<String> = self.vary.iter().map(|(name, _)| name.clone()).collect();
+        vary_values(&names, req_headers) == self.vary
+    }
+
+    /// The entry once a `304` with `headers` confirmed it (RFC 9111 section 4.3.4).
+    pub fn refreshed(&self, headers: &HeaderMap) -> Self {
+        let mut entry = self.clone();
+        for name in headers.keys() {
+            // these describe the 304, not the stored body
+            if *name == header::CONTENT_LENGTH || *name == header::TRANSFER_ENCODING {
+                continue;
+            }
+            entry.headers.remove(name);
+            for value in headers.get_all(name) {
+                entry.headers.append(name, value.clone());
+            }
+        }
+        entry.freshness = refreshed(&entry.headers, self.freshness.lifetime);
+        entry.initial_age = initial_age(headers);
+        entry.stored_at = Instant::now();
+        entry
+    }
+
+    fn size(&self) -> u64 {
+        let body = match &self.body {
+            Stored::Memory(body) => body.len(),
+            Stored::Disk(_) => 0,
+        };
+        let headers: usize = self.headers.iter().map(|(n, v)| n.as_str().len() + v.len()).sum();
+        (body + headers) as u64
+    }
+}
+
+fn initial_age(headers: &HeaderMap) -> Duration {
+    let age = headers.get(header::AGE).and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok());
+    Duration::from_secs(age.unwrap_or(0))
+}
+
+fn vary_values(names: &[String], req_headers: &HeaderMap) -> Vec<(String, Option<String>)> {
+    names.iter()
+        .map(|name| {
+            let values: Vec<&str> = req_headers.get_all(name.as_str()).iter().filter_map(|v| v.to_str().ok()).collect();
+            (name.clone(), if values.is_empty() { None } else { Some(values.join(", ")) })
+        })
+        .collect()
+}
+
+/// Responses by cache key, each key holding one per `Vary` variant.
+pub struct Store {
+    max_bytes: u64,
+    max_object_bytes: u64,
+    disk: Option<(PathBuf, u64)>,
+    /// Names this store's files start with.
+    prefix: String,
+    files: AtomicU64,
+    tiers: Mutex<Tiers>,
+    fetching: Mutex<HashMap<String, watch::Receiver<()>>>,
+}
+
+impl std::fmt::Debug for Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Store")
+            .field("max_bytes", &self.max_bytes)
+            .field("disk", &self.disk)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Default)]
+struct Tiers {
+    entries: HashMap<String, Vec<Slot>>,
+    memory: Lru,
+    disk: Lru,
+    /// Last use of any slot; also what tells slots apart.
+    ticks: u64,
+}
+
+struct Slot {
+    entry: Arc<Entry>,
+    tick: u64,
+    /// What it counts against its tier.
+    size: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    order: BTreeMap<u64, String>,
+    bytes: u64,
+}
+
+impl Tiers {
+    fn lru(&mut self, entry: &Entry) -> &mut Lru {
+        match entry.body {
+            Stored::Memory(_) => &mut self.memory,
+            Stored::Disk(_) => &mut self.disk,
+        }
+    }
+
+    fn add(&mut self, key: String, entry: Arc<Entry>, size: u64) {
+        self.ticks += 1;
+        let tick = self.ticks;
+        let lru = self.lru(&entry);
+        lru.order.insert(tick, key.clone());
+        lru.bytes += size;
+        self.entries.entry(key).or_default().push(Slot { entry, tick, size });
+    }
+
+    fn remove(&mut self, key: &str, tick: u64) -> Option<Arc<Entry>> {
+        let slots = self.entries.get_mut(key)?;
+        let at = slots.iter().position(|s| s.tick == tick)?;
+        let Slot { entry, size, .. } = slots.remove(at);
+        if slots.is_empty() {
+            self.entries.remove(key);
+        }
+        let lru = self.lru(&entry);
+        lru.order.remove(&tick);
+        lru.bytes -= size;
+        Some(entry)
+    }
+
+    /// Take out the variants of `key` with the same `Vary` values as `entry`.
+    fn remove_variant(&mut self, key: &str, entry: &Entry) -> Vec<Arc<Entry>> {
+        let ticks: Vec<u64> = self.entries.get(key).into_iter().flatten()
+            .filter(|s| s.entry.vary == entry.vary)
+            .map(|s| s.tick)
+            .collect();
+        ticks.into_iter().filter_map(|tick| self.remove(key, tick)).collect()
+    }
+
+    /// Take out the least recently used of a tier until it fits in `max`.
+    fn evict(&mut self, disk: bool, max: u64) -> Vec<(String, Arc<Entry>)> {
+        let mut evicted = Vec::new();
+        loop {
+            let lru = if disk { &self.disk } else { &self.memory };
+            if lru.bytes <= max {
+                return evicted;
+            }
+            let Some((&tick, key)) = lru.order.first_key_value() else { return evicted };
+            let key = key.clone();
+            match self.remove(&key, tick) {
+                Some(entry) => evicted.push((key, entry)),
+                // cannot happen while the two agree, but must not spin if they do not
+                None => {
+                    let lru = if disk { &mut self.disk } else { &mut self.memory };
+                    lru.order.remove(&tick);
+                }
+            }
+        }
+    }
+}
+
+/// Whether a request fetches a missing response itself, or waits for
+/// another already fetching it.
+pub enum Fetch {
+    Leader(FetchGuard),
+    Follower(watch::Receiver<()>),
+}
+
+/// Held while fetching; followers wake up when it is dropped.
+pub struct FetchGuard {
+    store: Arc<Store>,
+    key: String,
+    _done: watch::Sender<()>,
+}
+
+impl Drop for FetchGuard {
+    fn drop(&mut self) {
+        lock(&self.store.fetching).remove(&self.key);
+    }
+}
+
+impl Store {
+    pub fn new(max_bytes: u64, max_object_bytes: u64, disk: Option<(PathBuf, u64)>) -> Self {
+        let store = STORES.fetch_add(1, Ordering::Relaxed);
+        Store {
+            max_bytes,
+            max_object_bytes,
+            disk,
+            prefix: format!("oxidase-{}-{store}", std::process::id()),
+            files: AtomicU64::new(0),
+            tiers: Mutex::default(),
+            fetching: Mutex::default(),
+        }
+    }
+
+    pub fn max_object_bytes(&self) -> u64 {
+        self.max_object_bytes
+    }
+
+    /// The newest response stored under `key` for a request with `req_headers`.
+    pub fn lookup(&self, key: &str, req_headers: &HeaderMap) -> Option<Arc<Entry>> {
+        let mut tiers = lock(&self.tiers);
+        let tiers = &mut *tiers;
+        let slot = tiers.entries.get_mut(key)?.iter_mut()
+            .filter(|s| s.entry.matches(req_headers))
+            .max_by_key(|s| s.entry.stored_at)?;
+        // used again: last in line to go
+        tiers.ticks += 1;
+        let now = tiers.ticks;
+        let last = std::mem::replace(&mut slot.tick, now);
+        let entry = slot.entry.clone();
+        let lru = tiers.lru(&entry);
+        lru.order.remove(&last);
+        lru.order.insert(now, key.to_string());
+        Some(entry)
+    }
+
+    /// Store `entry` under `key`, replacing the variant it is a newer copy of.
+    pub fn put(self: &Arc<Self>, key: String, entry: Entry) {
+        let size = entry.size();
+        if size > self.max_object_bytes {
+            return;
+        }
+        let mut tiers = lock(&self.tiers);
+        let replaced = tiers.remove_variant(&key, &entry);
+        tiers.add(key, Arc::new(entry), size);
+        let evicted = tiers.evict(false, self.max_bytes);
+        drop(tiers);
+        delete_files(&replaced);
+        if self.disk.is_some() && !evicted.is_empty() {
+            tokio::spawn(self.clone().spill(evicted));
+        }
+    }
+
+    /// Move entries pushed out of memory to disk.
+    async fn spill(self: Arc<Self>, evicted: Vec<(String, Arc<Entry>)>) {
+        let Some((dir, max_bytes)) = &self.disk else { return };
+        for (key, entry) in evicted {
+            let Stored::Memory(body) = &entry.body else { continue };
+            let n = self.files.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("{}-{n}", self.prefix));
+            if let Err(e) = tokio::fs::write(&path, body).await {
+                eprintln!("Cache: cannot write {}: {e}", path.display());
+                continue;
+            }
+            let on_disk = Arc::new(Entry { body: Stored::Disk(path), ..(*entry).clone() });
+            let mut tiers = lock(&self.tiers);
+            // a newer copy arrived while this one was written
+            let superseded = tiers.entries.get(&key).into_iter().flatten().any(|s| s.entry.vary == on_disk.vary);
+            let doomed = if superseded {
+                vec![on_disk]
+            } else {
+                tiers.add(key, on_disk, body.len() as u64);
+                tiers.evict(true, *max_bytes).into_iter().map(|(_, entry)| entry).collect()
+            };
+            drop(tiers);
+            delete_files(&doomed);
+        }
+    }
+
+    /// The body of `entry`, `None` if its file is gone.
+    pub async fn body(entry: &Entry) -> Option<Bytes> {
+        match &entry.body {
+            Stored::Memory(body) => Some(body.clone()),
+            Stored::Disk(path) => tokio::fs::read(path).await.ok().map(Bytes::from),
+        }
+    }
+
+    /// Join the fetch of `key` going on, or become the one fetching it.
+    pub fn fetch(self: &Arc<Self>, key: &str) -> Fetch {
+        let mut fetching = lock(&self.fetching);
+        if let Some(done) = fetching.get(key) {
+            return Fetch::Follower(done.clone());
+        }
+        let (tx, rx) = watch::channel(());
+        fetching.insert(key.to_string(), rx);
+        Fetch::Leader(FetchGuard { store: self.clone(), key: key.to_string(), _done: tx })
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        let tiers = self.tiers.get_mut().unwrap_or_else(|e| e.into_inner());
+        let entries: Vec<Arc<Entry>> = tiers.entries.values().flatten().map(|s| s.entry.clone()).collect();
+        delete_files(&entries);
+    }
+}
+
+fn delete_files(entries: &[Arc<Entry>]) {
+    for entry in entries {
+        if let Stored::Disk(path) = &entry.body {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
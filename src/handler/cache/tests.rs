@@ -0,0 +1,312 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{http, Request, Response};
+
+use crate::test_util::{get, read_until, send, spawn_server, spawn_upstream};
+
+fn cache_yaml(upstream: std::net::SocketAddr, extra: &str) -> String {
+    format!(r#"
+bind: "127.0.0.1:0"
+service:
+  handler: cache
+  cache_status: oxidase
+{extra}
+  service:
+    handler: forward
+    target:
+      scheme: http
+      host: "{}"
+      port: {}
+"#, upstream.ip(), upstream.port())
+}
+
+/// Upstream answering `respond(nth request, request)`, numbered from 1.
+async fn spawn_numbered_upstream<F>(respond: F) -> (std::net::SocketAddr, Arc<AtomicUsize>)
+where
+    F: Fn(usize, &Request<hyper::body::Incoming>) -> Response<Full<Bytes>> + Clone + Send + Sync + 'static,
+{
+    let hits = Arc::new(AtomicUsize::new(0));
+    let count = hits.clone();
+    let addr = spawn_upstream(move |req| respond(count.fetch_add(1, Ordering::SeqCst) + 1, &req)).await;
+    (addr, hits)
+}
+
+fn reply(n: usize, headers: &[(&str, &str)]) -> Response<Full<Bytes>> {
+    let mut resp = Response::builder();
+    for (name, value) in headers {
+        resp = resp.header(*name, *value);
+    }
+    resp.body(Full::from(format!("v{n}"))).unwrap()
+}
+
+fn cache_status(headers: &http::HeaderMap) -> String {
+    headers.get("cache-status").and_then(|v| v.to_str().ok()).unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn fresh_responses_are_served_from_the_cache() {
+    let (upstream, hits) = spawn_numbered_upstream(|n, req| match req.uri().path() {
+        "/private" => reply(n, &[("cache-control", "private, max-age=60")]),
+        _ => reply(n, &[("cache-control", "max-age=60")]),
+    }).await;
+    let gw = spawn_server(&cache_yaml(upstream, "")).await;
+
+    let (status, headers, body) = send(gw, get("/asset")).await;
+    assert_eq!((status, &body[..]), (http::StatusCode::OK, &b"v1"[..]));
+    assert_eq!(cache_status(&headers), "oxidase; fwd=miss");
+
+    let (status, headers, body) = send(gw, get("/asset")).await;
+    assert_eq!((status, &body[..]), (http::StatusCode::OK, &b"v1"[..]));
+    assert!(cache_status(&headers).starts_with("oxidase; hit; ttl="), "{headers:?}");
+    assert_eq!(headers.get(http::header::AGE).unwrap(), "0");
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+    // another key, and responses only the one client may keep
+    send(gw, get("/asset?v=2")).await;
+    send(gw, get("/private")).await;
+    let (_, _, body) = send(gw, get("/private")).await;
+    assert_eq!(&body[..], b"v4");
+
+    let head = Request::builder().method("HEAD").uri("/asset")
+        .header(http::header::HOST, "gateway.test")
+        .body(Full::default()).unwrap();
+    let (status, headers, body) = send(gw, head).await;
+    assert_eq!((status, body.len()), (http::StatusCode::OK, 0));
+    assert!(cache_status(&headers).contains("hit"), "{headers:?}");
+
+    let no_cache = Request::builder().uri("/asset")
+        .header(http::header::HOST, "gateway.test")
+        .header(http::header::CACHE_CONTROL, "no-cache")
+        .body(Full::default()).unwrap();
+    let (_, headers, body) = send(gw, no_cache).await;
+    assert_eq!(&body[..], b"v5");
+    assert_eq!(cache_status(&headers), "oxidase; fwd=request; fwd-status=200");
+    assert_eq!(hits.load(Ordering::SeqCst), 5);
+}
+
+#[tokio::test]
+async fn vary_keeps_a_copy_per_variant_and_keys_are_templates() {
+    let (upstream, hits) = spawn_numbered_upstream(|n, _| {
+        reply(n, &[("cache-control", "max-age=60"), ("vary", "Accept-Language")])
+    }).await;
+    let gw = spawn_server(&cache_yaml(upstream, "  key: \"${host}${path}\"")).await;
+
+    let in_language = |lang: &str, query: &str| Request::builder()
+        .uri(format!("/page{query}"))
+        .header(http::header::HOST, "gateway.test")
+        .header(http::header::ACCEPT_LANGUAGE, lang)
+        .body(Full::default())
+        .unwrap();
+    let (_, _, en) = send(gw, in_language("en", "")).await;
+    let (_, _, fr) = send(gw, in_language("fr", "?utm=1")).await;
+    let (_, _, en_again) = send(gw, in_language("en", "?utm=2")).await;
+    assert_eq!((&en[..], &fr[..], &en_again[..]), (&b"v1"[..], &b"v2"[..], &b"v1"[..]));
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn stale_entries_are_revalidated_with_their_validators() {
+    let (upstream, hits) = spawn_numbered_upstream(|n, req| {
+        if req.headers().get(http::header::IF_NONE_MATCH).is_some_and(|v| v == "\"one\"") {
+            let mut resp = reply(n, &[("cache-control", "max-age=0"), ("x-checked", &n.to_string())]);
+            *resp.body_mut() = Full::default();
+            *resp.status_mut() = http::StatusCode::NOT_MODIFIED;
+            return resp;
+        }
+        reply(n, &[("cache-control", "max-age=0"), ("etag", "\"one\"")])
+    }).await;
+    let gw = spawn_server(&cache_yaml(upstream, "")).await;
+
+    send(gw, get("/doc")).await;
+    let (status, headers, body) = send(gw, get("/doc")).await;
+    assert_eq!((status, &body[..]), (http::StatusCode::OK, &b"v1"[..]));
+    assert_eq!(cache_status(&headers), "oxidase; fwd=stale; fwd-status=304");
+    // the 304's headers update the stored ones
+    assert_eq!(headers.get("x-checked").unwrap(), "2");
+
+    // the client's own validator is answered from the entry
+    let conditional = Request::builder().uri("/doc")
+        .header(http::header::HOST, "gateway.test")
+        .header(http::header::IF_NONE_MATCH, "\"one\"")
+        .body(Full::default()).unwrap();
+    let (status, _, body) = send(gw, conditional).await;
+    assert_eq!((status, body.len()), (http::StatusCode::NOT_MODIFIED, 0));
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn stale_copies_cover_revalidation_and_errors() {
+    let (upstream, hits) = spawn_numbered_upstream(|n, _| {
+        reply(n, &[("cache-control", "max-age=0, stale-while-revalidate=60"), ("etag", &format!("\"{n}\""))])
+    }).await;
+    let gw = spawn_server(&cache_yaml(upstream, "")).await;
+
+    send(gw, get("/swr")).await;
+    let (_, headers, body) = send(gw, get("/swr")).await;
+    assert_eq!(&body[..], b"v1");
+    assert_eq!(cache_status(&headers), "oxidase; hit; detail=stale-while-revalidate");
+    // the refresh happens behind the client's back
+    let mut refreshed = Bytes::new();
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        refreshed = send(gw, get("/swr")).await.2;
+        if &refreshed[..] != b"v1" {
+            break;
+        }
+    }
+    assert_eq!(&refreshed[..], b"v2");
+    assert!(hits.load(Ordering::SeqCst) >= 2);
+
+    let (upstream, _) = spawn_numbered_upstream(|n, _| match n {
+        1 => reply(n, &[("cache-control", "max-age=0, stale-if-error=60"), ("etag", "\"f\"")]),
+        _ => {
+            let mut resp = reply(n, &[]);
+            *resp.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+            resp
+        }
+    }).await;
+    let gw = spawn_server(&cache_yaml(upstream, "")).await;
+    send(gw, get("/fails")).await;
+    let (status, headers, body) = send(gw, get("/fails")).await;
+    assert_eq!((status, &body[..]), (http::StatusCode::OK, &b"v1"[..]));
+    assert_eq!(cache_status(&headers), "oxidase; fwd=stale; fwd-status=503; detail=stale-if-error");
+}
+
+/// Upstream that takes `delay` to answer, counting requests.
+async fn spawn_slow_upstream(delay: Duration) -> (std::net::SocketAddr, Arc<AtomicUsize>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let hits = Arc::new(AtomicUsize::new(0));
+    let count = hits.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let count = count.clone();
+            let svc = hyper::service::service_fn(move |_req| {
+                let n = count.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    tokio::time::sleep(delay).await;
+                    Ok::<_, hyper::Error>(reply(n, &[("cache-control", "max-age=60")]))
+                }
+            });
+            tokio::spawn(hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), svc));
+        }
+    });
+    (addr, hits)
+}
+
+#[tokio::test]
+async fn concurrent_misses_wait_for_one_fetch() {
+    let (upstream, hits) = spawn_slow_upstream(Duration::from_millis(200)).await;
+    let gw = spawn_server(&cache_yaml(upstream, "")).await;
+
+    let requests = (0..5).map(|_| send(gw, get("/popular")));
+    let bodies: Vec<Bytes> = bodies_of(requests).await;
+    assert!(bodies.iter().all(|b| &b[..] == b"v1"), "{bodies:?}");
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn followers_stop_waiting_once_the_leader_cannot_store() {
+    use tokio::io::AsyncWriteExt;
+
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let (oversize, oversized) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(async move {
+        // the leader's body goes over the limit and then never ends
+        let (mut leader, _) = upstream.accept().await.unwrap();
+        read_until(&mut leader, "\r\n\r\n").await;
+        leader.write_all(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nTransfer-Encoding: chunked\r\n\r\n").await.unwrap();
+        oversized.await.unwrap();
+        leader.write_all(format!("12c\r\n{}\r\n", "x".repeat(300)).as_bytes()).await.unwrap();
+        let (mut follower, _) = upstream.accept().await.unwrap();
+        read_until(&mut follower, "\r\n\r\n").await;
+        follower.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nsmall").await.unwrap();
+        std::future::pending::<()>().await;
+    });
+    let gw = spawn_server(&cache_yaml(upstream_addr, "  max_object_bytes: 200")).await;
+
+    let mut leader = tokio::net::TcpStream::connect(gw).await.unwrap();
+    leader.write_all(b"GET /stream HTTP/1.1\r\nHost: gateway.test\r\n\r\n").await.unwrap();
+    read_until(&mut leader, "\r\n\r\n").await;
+    let follower = tokio::spawn(send(gw, get("/stream")));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    oversize.send(()).unwrap();
+    let (_, headers, body) = tokio::time::timeout(Duration::from_secs(2), follower).await
+        .expect("the follower waited for the leader's whole body").unwrap();
+    assert_eq!(&body[..], b"small");
+    assert_eq!(cache_status(&headers), "oxidase; fwd=miss");
+}
+
+async fn bodies_of<F>(requests: impl Iterator<Item = F>) -> Vec<Bytes>
+where
+    F: std::future::Future<Output = (http::StatusCode, http::HeaderMap, Bytes)> + Send + 'static,
+{
+    let tasks: Vec<_> = requests.map(tokio::spawn).collect();
+    let mut bodies = Vec::new();
+    for task in tasks {
+        bodies.push(task.await.unwrap().2);
+    }
+    bodies
+}
+
+#[tokio::test]
+async fn responses_pushed_out_of_memory_spill_to_disk() {
+    let dir = std::env::temp_dir().join(format!("oxidase-cache-{}", std::process::id()));
+    let (upstream, hits) = spawn_numbered_upstream(|n, _| {
+        let mut resp = reply(n, &[("cache-control", "max-age=60")]);
+        *resp.body_mut() = Full::from(format!("v{n}").repeat(50));
+        resp
+    }).await;
+    let extra = format!("  max_bytes: 300\n  max_object_bytes: 200\n  disk:\n    dir: \"{}\"\n    max_bytes: 1000", dir.display());
+    let gw = spawn_server(&cache_yaml(upstream, &extra)).await;
+
+    for path in ["/a", "/b", "/c"] {
+        send(gw, get(path)).await;
+    }
+    // `/a` went to disk first, in the background
+    let mut spilled = false;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        if std::fs::read_dir(&dir).unwrap().count() > 0 {
+            spilled = true;
+            break;
+        }
+    }
+    assert!(spilled);
+    let (_, headers, body) = send(gw, get("/a")).await;
+    assert_eq!(body, "v1".repeat(50));
+    assert!(cache_status(&headers).contains("hit"), "{headers:?}");
+
+    // too large to keep at all
+    let (upstream, hits_large) = spawn_numbered_upstream(|n, _| {
+        let mut resp = reply(n, &[("cache-control", "max-age=60")]);
+        *resp.body_mut() = Full::from("x".repeat(500));
+        resp
+    }).await;
+    let gw = spawn_server(&cache_yaml(upstream, "  max_object_bytes: 200")).await;
+    send(gw, get("/large")).await;
+    send(gw, get("/large")).await;
+    assert_eq!((hits.load(Ordering::SeqCst), hits_large.load(Ordering::SeqCst)), (3, 2));
+}
+
+#[test]
+fn cache_settings_are_validated() {
+    let built_err = |extra: &str| {
+        let up: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+        let cfg: crate::config::http_server::HttpServer = serde_yaml::from_str(&cache_yaml(up, extra)).unwrap();
+        crate::build::build_http_server(cfg).expect_err("build should fail").to_string()
+    };
+    let err = built_err("  max_bytes: 100\n  max_object_bytes: 200");
+    assert!(err.contains("`cache.max_object_bytes` cannot exceed `max_bytes`"), "{err}");
+    let err = built_err("  default_ttl_ms: 0");
+    assert!(err.contains("`cache.default_ttl_ms` must be positive"), "{err}");
+    let err = built_err("  key: \"${path\"");
+    assert!(err.contains("`cache.key`"), "{err}");
+}
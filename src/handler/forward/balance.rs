@@ -0,0 +1,273 @@
+//! Spreading a Forward's requests over its targets.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::http;
+
+use crate::config::error::ConfigError;
+use crate::config::forward::{Balance, ForwardTarget};
+use crate::handler::router::RouterCtx;
+use crate::handler::RequestBody;
+use crate::template::{compile_template, expand_template, CompiledTemplate};
+
+use super::health::{Health, HealthProbes};
+use super::UpstreamClient;
+
+/// Points each unit of weight gets on the hash ring; more points even out
+/// the share each target gets.
+const RING_POINTS_PER_WEIGHT: u32 = 100;
+
+/// One target with its connection pool.
+#[derive(Debug)]
+pub struct Upstream {
+    pub target: ForwardTarget,
+    pub client: UpstreamClient,
+    pub health: Health,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Upstream {
+    pub fn new(target: ForwardTarget, client: UpstreamClient) -> Self {
+        Upstream { target, client, health: Health::default(), in_flight: Arc::default() }
+    }
+
+    /// Count a request against this upstream until the guard is dropped.
+    pub fn start_request(&self) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.in_flight.clone())
+    }
+
+    fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed) as u64
+    }
+
+    fn weight(&self) -> u64 {
+        self.target.weight.into()
+    }
+}
+
+/// A request in flight to an upstream, counted by least-connections.
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Keeps a request counted while its response body is still being read.
+pub struct Counted<B> {
+    body: B,
+    _in_flight: InFlight,
+}
+
+impl<B> Counted<B> {
+    pub fn new(body: B, in_flight: InFlight) -> Self {
+        Counted { body, _in_flight: in_flight }
+    }
+}
+
+impl<B: Body + Unpin> Body for Counted<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<B::Data>, B::Error>>> {
+        Pin::new(&mut self.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+pub struct Balancer {
+    upstreams: Vec<Upstream>,
+    strategy: Strategy,
+    /// Advances with every pick, to rotate through ties.
+    turn: AtomicUsize,
+    /// The active checks running for it, while anyone serving it holds them.
+    pub(super) probes: Mutex<Weak<HealthProbes>>,
+}
+
+enum Strategy {
+    /// Smooth weighted round-robin, as nginx does it: the current score of
+    /// each upstream, raised by its weight on every pick.
+    RoundRobin(Mutex<Vec<i64>>),
+    LeastConnections,
+    RandomTwoChoices,
+    Hash { key: CompiledTemplate, ring: Vec<(u64, usize)> },
+}
+
+impl fmt::Debug for Balancer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let strategy = match &self.strategy {
+            Strategy::RoundRobin(_) => "round_robin",
+            Strategy::LeastConnections => "least_connections",
+            Strategy::RandomTwoChoices => "random_two_choices",
+            Strategy::Hash { .. } => "hash",
+        };
+        f.debug_struct("Balancer")
+            .field("upstreams", &self.upstreams)
+            .field("strategy", &strategy)
+            .finish()
+    }
+}
+
+impl Balancer {
+    pub fn new(upstreams: Vec<Upstream>, balance: &Balance) -> Result<Self, ConfigError> {
+        // picking indexes into the list and divides by weights
+        if upstreams.is_empty() {
+            return Err(ConfigError::Invalid("`forward.target` cannot be empty".into()));
+        }
+        if upstreams.iter().any(|up| up.weight() == 0) {
+            return Err(ConfigError::Invalid("`forward.target.weight` must be positive".into()));
+        }
+        let strategy = match balance {
+            Balance::RoundRobin => Strategy::RoundRobin(Mutex::new(vec![0; upstreams.len()])),
+            Balance::LeastConnections => Strategy::LeastConnections,
+            Balance::RandomTwoChoices => Strategy::RandomTwoChoices,
+            Balance::Hash(key) => {
+                let key = compile_template(key)
+                    .map_err(|e| ConfigError::Invalid(format!("`forward.balance.hash`: {e}")))?;
+                Strategy::Hash { key, ring: hash_ring(&upstreams) }
+            }
+        };
+        Ok(Balancer { upstreams, strategy, turn: AtomicUsize::new(0), probes: Mutex::default() })
+    }
+
+    pub fn upstreams(&self) -> &[Upstream] {
+        &self.upstreams
+    }
+
+    /// The upstream the request goes to, among those health checks have not
+    /// taken out and, for a retry, those not `tried` yet. When that leaves
+    /// none, the available ones are picked from, and failing those any one.
+    pub fn pick(&self, req: &http::Request<RequestBody>, tried: &[&Upstream]) -> &Upstream {
+        if self.upstreams.len() == 1 {
+            return &self.upstreams[0];
+        }
+        let available: Vec<bool> = self.upstreams.iter().map(|u| u.health.available()).collect();
+        let untried: Vec<bool> = self.upstreams.iter().zip(&available)
+            .map(|(u, ok)| *ok && !tried.iter().any(|t| std::ptr::eq(*t, u)))
+            .collect();
+        let usable = [untried, available].into_iter()
+            .find(|usable| usable.contains(&true))
+            .unwrap_or_else(|| vec![true; self.upstreams.len()]);
+
+        let index = match &self.strategy {
+            Strategy::RoundRobin(scores) => self.round_robin(scores, &usable),
+            Strategy::LeastConnections => self.least_connections(&usable),
+            Strategy::RandomTwoChoices => {
+                let (a, b) = (self.weighted_random(&usable), self.weighted_random(&usable));
+                if self.busier(a, b) { b } else { a }
+            }
+            Strategy::Hash { key, ring } => {
+                let ctx = RouterCtx::from_request(req);
+                match expand_template(key, &ctx) {
+                    Ok(key) if !key.is_empty() => ring_lookup(ring, stable_hash(key.as_bytes()), &usable),
+                    // nothing to be sticky on
+                    _ => self.weighted_random(&usable),
+                }
+            }
+        };
+        &self.upstreams[index]
+    }
+
+    fn round_robin(&self, scores: &Mutex<Vec<i64>>, usable: &[bool]) -> usize {
+        let mut scores = scores.lock().unwrap_or_else(|e| e.into_inner());
+        let mut best = None;
+        let mut total = 0;
+        for (i, up) in self.upstreams.iter().enumerate().filter(|(i, _)| usable[*i]) {
+            scores[i] += up.weight() as i64;
+            total += up.weight() as i64;
+            if best.is_none_or(|b| scores[i] > scores[b]) {
+                best = Some(i);
+            }
+        }
+        let best = best.unwrap_or(0);
+        scores[best] -= total;
+        best
+    }
+
+    fn least_connections(&self, usable: &[bool]) -> usize {
+        // start somewhere else each time so ties are shared out
+        let n = self.upstreams.len();
+        let start = self.turn.fetch_add(1, Ordering::Relaxed) % n;
+        (0..n).map(|i| (start + i) % n)
+            .filter(|i| usable[*i])
+            .reduce(|best, i| if self.busier(best, i) { i } else { best })
+            .unwrap_or(0)
+    }
+
+    /// Whether `a` has more requests in flight than `b`, for its weight.
+    fn busier(&self, a: usize, b: usize) -> bool {
+        let (a, b) = (&self.upstreams[a], &self.upstreams[b]);
+        a.in_flight() * b.weight() > b.in_flight() * a.weight()
+    }
+
+    fn weighted_random(&self, usable: &[bool]) -> usize {
+        let candidates = || self.upstreams.iter().enumerate().filter(|(i, _)| usable[*i]);
+        let total: u64 = candidates().map(|(_, up)| up.weight()).sum();
+        let mut point = random() % total;
+        for (i, up) in candidates() {
+            if point < up.weight() {
+                return i;
+            }
+            point -= up.weight();
+        }
+        0
+    }
+}
+
+/// Points on the ring for every upstream, keyed by where it connects rather
+/// than its position, so adding or removing a target only moves its own keys.
+fn hash_ring(upstreams: &[Upstream]) -> Vec<(u64, usize)> {
+    let mut ring: Vec<(u64, usize)> = upstreams.iter().enumerate()
+        .flat_map(|(i, up)| {
+            let endpoint = up.target.endpoint();
+            (0..up.target.weight * RING_POINTS_PER_WEIGHT)
+                .map(move |n| (stable_hash(format!("{endpoint}#{n}").as_bytes()), i))
+        })
+        .collect();
+    ring.sort_unstable();
+    ring
+}
+
+/// The first usable point at or after `hash`, wrapping around, so keys of a
+/// target that is out go to the next one while the rest stay put.
+fn ring_lookup(ring: &[(u64, usize)], hash: u64, usable: &[bool]) -> usize {
+    let at = ring.partition_point(|(point, _)| *point < hash);
+    (0..ring.len())
+        .map(|i| ring[(at + i) % ring.len()].1)
+        .find(|i| usable[*i])
+        .unwrap_or(0)
+}
+
+/// FNV-1a with a final mix, as keys differing only in their last bytes would
+/// otherwise land close together. Stable across runs and builds, unlike the
+/// std hashers.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut h = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01b3));
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+/// Randomly keyed std hashers make a good enough source of randomness here.
+fn random() -> u64 {
+    RandomState::new().hash_one(0u8)
+}
+
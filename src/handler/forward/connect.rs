@@ -0,0 +1,148 @@
+//! Connections to a Forward upstream: TCP or a Unix domain socket, with TLS on
+//! top for `https` targets.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::Uri;
+use hyper::rt::ReadBufCursor;
+use hyper_util::client::legacy::connect::{Connected, Connection, HttpConnector};
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::timeout;
+use tower_service::Service;
+
+use crate::build::tls::UpstreamTls;
+use crate::config::forward::{ForwardService, ForwardTarget};
+
+use super::timeout::TimedOut;
+
+#[cfg(unix)]
+use std::{path::Path, sync::Arc};
+
+const ALPN_H2: &[u8] = b"h2";
+
+#[derive(Clone)]
+pub struct UpstreamConnector {
+    transport: Transport,
+    tls: Option<UpstreamTls>,
+    connect_timeout: Option<Duration>,
+}
+
+#[derive(Clone)]
+enum Transport {
+    Tcp(HttpConnector),
+    /// Every connection goes to this socket, whatever the URI names.
+    #[cfg(unix)]
+    Unix(Arc<Path>),
+}
+
+impl UpstreamConnector {
+    pub fn new(config: &ForwardService, target: &ForwardTarget, tls: Option<UpstreamTls>) -> Self {
+        let transport = match &target.unix {
+            #[cfg(unix)]
+            Some(path) => Transport::Unix(Arc::from(path.as_path())),
+            _ => {
+                let mut http = HttpConnector::new();
+                // the scheme is ours to handle, TLS included
+                http.enforce_http(false);
+                http.set_nodelay(config.pool.tcp_nodelay);
+                http.set_keepalive(config.pool.tcp_keepalive_ms.map(Duration::from_millis));
+                Transport::Tcp(http)
+            }
+        };
+        UpstreamConnector { transport, tls, connect_timeout: config.timeouts.connect() }
+    }
+}
+
+impl Service<Uri> for UpstreamConnector {
+    type Response = UpstreamIo;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<UpstreamIo>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let this = self.clone();
+        Box::pin(async move {
+            let connecting = this.transport.connect(uri);
+            let stream = match this.connect_timeout {
+                Some(limit) => timeout(limit, connecting).await
+                    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, TimedOut { what: "connect", after: limit }))??,
+                None => connecting.await?,
+            };
+            match &this.tls {
+                Some(tls) => handshake(stream, tls).await,
+                None => Ok(UpstreamIo { io: TokioIo::new(stream), h2: false }),
+            }
+        })
+    }
+}
+
+impl Transport {
+    async fn connect(self, uri: Uri) -> io::Result<Box<dyn Stream>> {
+        Ok(match self {
+            Transport::Tcp(mut http) => Box::new(http.call(uri).await.map_err(io::Error::other)?.into_inner()),
+            #[cfg(unix)]
+            Transport::Unix(path) => Box::new(
+                tokio::net::UnixStream::connect(&*path).await
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?,
+            ),
+        })
+    }
+}
+
+async fn handshake(stream: Box<dyn Stream>, tls: &UpstreamTls) -> io::Result<UpstreamIo> {
+    let connecting = tokio_rustls::TlsConnector::from(tls.config.clone())
+        .connect(tls.server_name.clone(), stream);
+    let stream = match tls.handshake_timeout {
+        Some(limit) => timeout(limit, connecting).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))??,
+        None => connecting.await?,
+    };
+    let h2 = stream.get_ref().1.alpn_protocol() == Some(ALPN_H2);
+    Ok(UpstreamIo { io: TokioIo::new(Box::new(stream)), h2 })
+}
+
+trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Stream for T {}
+
+/// An open upstream connection, whatever it runs over.
+pub struct UpstreamIo {
+    io: TokioIo<Box<dyn Stream>>,
+    /// The upstream picked HTTP/2 through ALPN.
+    h2: bool,
+}
+
+impl Connection for UpstreamIo {
+    fn connected(&self) -> Connected {
+        let connected = Connected::new();
+        if self.h2 { connected.negotiated_h2() } else { connected }
+    }
+}
+
+impl hyper::rt::Read for UpstreamIo {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: ReadBufCursor<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl hyper::rt::Write for UpstreamIo {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,111 @@
+//! Upstreams taken from the request URI (`dynamic_target`), each with a
+//! connection pool of its own once it has been used.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use hyper::http;
+use rustls::pki_types::ServerName;
+
+use crate::build::tls::UpstreamTls;
+use crate::config::forward::{AllowedHost, DynamicTarget, ForwardService, ForwardTarget};
+use crate::config::url_scheme::Scheme;
+use crate::handler::RequestBody;
+
+use super::{build_client, ForwardError, Upstream};
+
+#[derive(Debug)]
+pub struct DynamicUpstreams {
+    allow: Vec<AllowedHost>,
+    ports: Vec<u16>,
+    max_upstreams: usize,
+    /// Settings for `https` upstreams, with the server name to replace; none
+    /// with `tls.enabled: false`.
+    tls: Option<UpstreamTls>,
+    /// `tls.sni` names every upstream rather than its own host.
+    fixed_sni: bool,
+    /// By endpoint, so requests to one upstream share its connections.
+    upstreams: Mutex<Pools>,
+}
+
+/// Upstreams in use, each with when it last was, so the least recently used
+/// can make room.
+#[derive(Debug, Default)]
+struct Pools {
+    by_key: HashMap<String, (Arc<Upstream>, u64)>,
+    ticks: u64,
+}
+
+impl DynamicUpstreams {
+    pub fn new(cfg: &DynamicTarget, tls: Option<UpstreamTls>, fixed_sni: bool) -> Result<Self, String> {
+        let allow = cfg.allow.iter().map(|a| a.parse()).collect::<Result<_, _>>()?;
+        Ok(DynamicUpstreams {
+            allow,
+            ports: cfg.ports.clone(),
+            max_upstreams: cfg.max_upstreams,
+            tls,
+            fixed_sni,
+            upstreams: Mutex::default(),
+        })
+    }
+
+    /// The upstream named by the request URI, if the allow-list has it.
+    pub(super) fn upstream(&self, req: &http::Request<RequestBody>, config: &ForwardService) -> Result<Arc<Upstream>, ForwardError> {
+        let uri = req.uri();
+        let scheme = match uri.scheme_str() {
+            Some("https") => Scheme::Https,
+            Some("http") | None => Scheme::Http,
+            Some(other) => return Err(ForwardError::Forbidden(format!("upstream scheme `{other}` is not allowed"))),
+        };
+        let host = uri.host().filter(|h| !h.is_empty())
+            .ok_or_else(|| ForwardError::Forbidden("no upstream host in the request".into()))?;
+        let target = ForwardTarget {
+            scheme,
+            host: host.to_string(),
+            port: uri.port_u16(),
+            unix: None,
+            path_prefix: String::new(),
+            weight: 1,
+        };
+        if !self.allow.iter().any(|a| a.matches(host))
+            || !self.ports.contains(&target.port())
+        {
+            return Err(ForwardError::Forbidden(format!("upstream {} is not allowed", target.endpoint())));
+        }
+
+        let key = format!("{}://{}", scheme.as_str(), target.endpoint()).to_ascii_lowercase();
+        let mut pools = self.upstreams.lock().unwrap_or_else(|e| e.into_inner());
+        pools.ticks += 1;
+        let now = pools.ticks;
+        if let Some((upstream, used)) = pools.by_key.get_mut(&key) {
+            *used = now;
+            return Ok(upstream.clone());
+        }
+        let tls = match scheme {
+            Scheme::Https => Some(self.tls_for(host)?),
+            Scheme::Http => None,
+        };
+        if pools.by_key.len() >= self.max_upstreams {
+            // requests still using it keep it until they are done
+            let oldest = pools.by_key.iter().min_by_key(|(_, (_, used))| *used).map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                pools.by_key.remove(&oldest);
+            }
+        }
+        let upstream = Arc::new(Upstream::new(target.clone(), build_client(config, &target, tls)));
+        pools.by_key.insert(key, (upstream.clone(), now));
+        Ok(upstream)
+    }
+
+    fn tls_for(&self, host: &str) -> Result<UpstreamTls, ForwardError> {
+        let tls = self.tls.clone()
+            .ok_or_else(|| ForwardError::Forbidden("https upstreams are disabled by `tls.enabled: false`".into()))?;
+        if self.fixed_sni {
+            return Ok(tls);
+        }
+        let name = host.trim_start_matches('[').trim_end_matches(']');
+        let server_name = ServerName::try_from(name.to_string())
+            .map_err(|e| ForwardError::Upstream(format!("invalid server name `{name}`: {e}")))?;
+        Ok(UpstreamTls { server_name, ..tls })
+    }
+}
@@ -0,0 +1,57 @@
+//! Responses sent in place of upstream errors and the proxy's own (`errors.pages`).
+
+use hyper::http;
+
+use crate::build::error_pages::{LoadedErrorPages, PageResponse};
+use crate::config::forward::errors::{ErrorOn, ProxyFailure};
+use crate::handler::router::RouterCtx;
+use crate::handler::{full, RequestBody, ResponseBody, ServiceHandler};
+use crate::template::expand_template;
+use crate::util::http::make_error_resp;
+
+/// `resp` as the client gets it: replaced by the first page for its status,
+/// or for the `failure` it stands for.
+pub async fn intercept(
+    errors: &LoadedErrorPages,
+    req: &mut http::Request<RequestBody>,
+    resp: http::Response<ResponseBody>,
+    failure: Option<ProxyFailure>,
+) -> http::Response<ResponseBody> {
+    let status = resp.status();
+    if status == http::StatusCode::SWITCHING_PROTOCOLS {
+        return resp;
+    }
+    let matches = |on: &ErrorOn| match on {
+        ErrorOn::Status(code) => *code == status.as_u16(),
+        ErrorOn::Failure(f) => failure == Some(*f),
+    };
+    let Some(page) = errors.pages.iter().find(|page| page.on.iter().any(matches)) else { return resp };
+    // the upstream's own body is dropped unread
+    drop(resp);
+
+    let ctx = RouterCtx::from_request(req);
+    let mut page_resp = match &page.response {
+        PageResponse::Use(service) => service.handle_request(req).await,
+        PageResponse::File { body, content_type } => {
+            let mut resp = http::Response::new(full(body.clone()));
+            resp.headers_mut().insert(http::header::CONTENT_TYPE, content_type.clone());
+            resp
+        }
+        PageResponse::Body(template) => match expand_template(template, &ctx) {
+            Ok(body) => http::Response::new(full(body)),
+            Err(_) => return make_error_resp(http::StatusCode::INTERNAL_SERVER_ERROR, "template error"),
+        },
+    };
+    *page_resp.status_mut() = page.status
+        .and_then(|code| http::StatusCode::from_u16(code).ok())
+        .unwrap_or(status);
+    for (name, value) in &page.headers {
+        let Ok(value) = expand_template(value, &ctx) else {
+            return make_error_resp(http::StatusCode::INTERNAL_SERVER_ERROR, "template error");
+        };
+        if let (Ok(name), Ok(value)) = (http::HeaderName::try_from(name.as_str()), http::HeaderValue::from_str(&value)) {
+            page_resp.headers_mut().insert(name, value);
+        }
+    }
+    page_resp
+}
@@ -0,0 +1,66 @@
+//! Headers that only concern one connection, which a proxy must not pass on
+//! (RFC 9110 section 7.6.1), and the `Via` it adds instead.
+
+use hyper::http::{self, header, HeaderMap, HeaderName};
+
+use crate::config::forward::HeaderFilter;
+
+use super::append_list;
+
+/// Whether `name` is hop-by-hop, either always or because the message's
+/// `Connection` header lists it.
+pub fn is_hop_by_hop(name: &HeaderName, listed: &[HeaderName]) -> bool {
+    matches!(*name, header::CONNECTION | header::TE | header::TRANSFER_ENCODING | header::UPGRADE)
+        || matches!(name.as_str(), "keep-alive" | "proxy-connection")
+        // the proxy's own credentials and challenges
+        || matches!(*name, header::PROXY_AUTHENTICATE | header::PROXY_AUTHORIZATION)
+        || listed.contains(name)
+}
+
+/// The headers a message's `Connection` header names as its own.
+pub fn connection_listed(headers: &HeaderMap) -> Vec<HeaderName> {
+    headers.get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect()
+}
+
+/// Whether the client takes trailers, the one `TE` value worth passing on:
+/// gRPC servers insist on it.
+pub fn accepts_trailers(headers: &HeaderMap) -> bool {
+    headers.get_all(header::TE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| t.split(';').next().is_some_and(|t| t.trim().eq_ignore_ascii_case("trailers")))
+}
+
+/// Drop what is hop-by-hop or filtered out from an upstream response.
+pub fn filter_response_headers(headers: &mut HeaderMap, filter: &HeaderFilter) {
+    let listed = connection_listed(headers);
+    let names: Vec<HeaderName> = headers.keys()
+        .filter(|name| is_hop_by_hop(name, &listed) || !filter.passes(name.as_str()))
+        .cloned()
+        .collect();
+    for name in names {
+        headers.remove(name);
+    }
+}
+
+/// Append this proxy to the `Via` chain of `headers`, for a message received
+/// over `version`.
+pub fn append_via(headers: &mut HeaderMap, version: http::Version, pseudonym: &str) {
+    let protocol = match version {
+        http::Version::HTTP_09 => "0.9",
+        http::Version::HTTP_10 => "1.0",
+        http::Version::HTTP_2 => "2",
+        http::Version::HTTP_3 => "3",
+        _ => "1.1",
+    };
+    if let Some(via) = append_list(headers, &header::VIA, Some(format!("{protocol} {pseudonym}"))) {
+        headers.insert(header::VIA, via);
+    }
+}
+
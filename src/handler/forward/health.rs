@@ -0,0 +1,123 @@
+//! Keeping track of which targets are fit for requests: probes sent on a
+//! timer (active checks), and failures of real requests (passive checks).
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use hyper::http;
+use tokio::task::JoinSet;
+use tokio::time::{interval, timeout, MissedTickBehavior};
+
+use crate::config::forward::health::{ActiveHealth, PassiveHealth};
+use crate::handler::empty;
+
+use super::balance::{Balancer, Upstream};
+use super::{error_chain, format_host};
+
+#[derive(Debug, Default)]
+pub struct Health {
+    /// Set by failing probes, cleared by passing ones.
+    down: AtomicBool,
+    /// Failed requests in a row, for passive checks.
+    failures: AtomicU32,
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl Health {
+    pub fn available(&self) -> bool {
+        if self.down.load(Ordering::Relaxed) {
+            return false;
+        }
+        let ejected = self.ejected_until.lock().unwrap_or_else(|e| e.into_inner());
+        ejected.is_none_or(|until| Instant::now() >= until)
+    }
+
+    /// Count a forwarded request, ejecting the target once it failed
+    /// `max_failures` times in a row.
+    pub fn record(&self, ok: bool, passive: &PassiveHealth, endpoint: &str) {
+        if ok {
+            self.failures.store(0, Ordering::Relaxed);
+            return;
+        }
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= passive.max_failures {
+            self.failures.store(0, Ordering::Relaxed);
+            let eject = Duration::from_millis(passive.eject_ms);
+            *self.ejected_until.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now() + eject);
+            eprintln!("Upstream {endpoint} ejected for {} ms after {failures} failed requests in a row", passive.eject_ms);
+        }
+    }
+}
+
+/// Active checks running for one balancer; they stop once the last handle
+/// to them is dropped.
+#[derive(Debug)]
+pub struct HealthProbes {
+    _tasks: JoinSet<()>,
+}
+
+/// Probe every upstream of `balancer` for as long as the returned handle is
+/// kept. Listeners serving the same balancer share one set of probes.
+pub fn start_probes(balancer: &Arc<Balancer>, active: &ActiveHealth) -> Arc<HealthProbes> {
+    let mut running = balancer.probes.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(probes) = running.upgrade() {
+        return probes;
+    }
+    let mut tasks = JoinSet::new();
+    for index in 0..balancer.upstreams().len() {
+        tasks.spawn(probe_loop(Arc::downgrade(balancer), index, active.clone()));
+    }
+    let probes = Arc::new(HealthProbes { _tasks: tasks });
+    *running = Arc::downgrade(&probes);
+    probes
+}
+
+async fn probe_loop(balancer: Weak<Balancer>, index: usize, active: ActiveHealth) {
+    let mut ticks = interval(Duration::from_millis(active.interval_ms));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let (mut passed, mut failed) = (0u32, 0u32);
+    loop {
+        ticks.tick().await;
+        // the service was replaced or dropped
+        let Some(balancer) = balancer.upgrade() else { return };
+        let upstream = &balancer.upstreams()[index];
+        let health = &upstream.health;
+        let endpoint = upstream.target.endpoint();
+        match probe(upstream, &active).await {
+            Ok(()) => {
+                failed = 0;
+                passed = passed.saturating_add(1);
+                if passed >= active.healthy_threshold && health.down.swap(false, Ordering::Relaxed) {
+                    eprintln!("Upstream {endpoint} is healthy again after {passed} passed health checks");
+                }
+            }
+            Err(why) => {
+                passed = 0;
+                failed = failed.saturating_add(1);
+                if failed >= active.unhealthy_threshold && !health.down.swap(true, Ordering::Relaxed) {
+                    eprintln!("Upstream {endpoint} is unhealthy after {failed} failed health checks: {why}");
+                }
+            }
+        }
+    }
+}
+
+async fn probe(upstream: &Upstream, active: &ActiveHealth) -> Result<(), String> {
+    let target = &upstream.target;
+    let scheme = target.scheme.as_str();
+    let uri = format!("{scheme}://{}:{}{}", target.host(), target.port(), active.path);
+    let req = http::Request::get(uri)
+        .header(http::header::HOST, format_host(target.host(), target.port(), target.scheme))
+        .body(empty())
+        .map_err(|e| format!("invalid health check request: {e}"))?;
+    let limit = Duration::from_millis(active.timeout_ms);
+    let resp = timeout(limit, upstream.client.request(req)).await
+        .map_err(|_| format!("no answer within {} ms", active.timeout_ms))?
+        .map_err(|e| error_chain(&e))?;
+    if active.expects(resp.status().as_u16()) {
+        Ok(())
+    } else {
+        Err(format!("status {}", resp.status().as_u16()))
+    }
+}
@@ -0,0 +1,581 @@
+mod balance;
+mod connect;
+mod dynamic;
+mod error_pages;
+mod headers;
+mod health;
+mod rewrite;
+mod timeout;
+mod upgrade;
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use hyper::body::Body;
+use hyper::upgrade::OnUpgrade;
+use hyper::{http, Uri};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use tokio::time::sleep;
+
+use crate::build::LoadedForward;
+use crate::build::service::ForwardUpstreams;
+use crate::build::tls::UpstreamTls;
+use crate::config::forward::errors::ProxyFailure;
+use crate::config::forward::{ForwardService, ForwardTarget, PassHost, PassHostMode, RetryFailure, RetryOn, RetryPolicy};
+use crate::config::http_method::HttpMethod;
+use crate::config::http_version::HttpVersion;
+use crate::config::url_scheme::Scheme;
+use crate::handler::{empty, BoxError, BoxResponseFuture, RequestBody, ResponseBody, ServiceHandler};
+use crate::util::http::{content_length, make_error_resp, request_scheme, ClientAddr, ConnTasks, PeerAddr};
+
+pub use balance::{Balancer, Upstream};
+pub use connect::UpstreamConnector;
+pub use dynamic::DynamicUpstreams;
+pub use health::{start_probes, HealthProbes};
+use balance::{Counted, InFlight};
+use error_pages::intercept;
+use headers::{accepts_trailers, append_via, connection_listed, filter_response_headers, is_hop_by_hop};
+use rewrite::ResponseRewrite;
+use timeout::{IdleTimeout, Sent, TimedOut};
+use upgrade::{requested_protocol, spawn_splice};
+
+pub type ForwardResult<T> = Result<T, String>;
+
+/// Client for one Forward service; clones share its connection pool.
+pub type UpstreamClient = Client<UpstreamConnector, RequestBody>;
+
+/// Build the client a Forward uses for every request to `target`, so upstream
+/// connections are kept alive and reused.
+pub fn build_client(config: &ForwardService, target: &ForwardTarget, tls: Option<UpstreamTls>) -> UpstreamClient {
+    let mut builder = Client::builder(TokioExecutor::new());
+    builder
+        .timer(TokioTimer::new())
+        .pool_timer(TokioTimer::new())
+        .pool_idle_timeout(Duration::from_millis(config.pool.idle_timeout_ms))
+        .http2_only(config.http_version == HttpVersion::V2);
+    if let Some(n) = config.pool.max_idle_per_host {
+        builder.pool_max_idle_per_host(n);
+    }
+    builder.build(UpstreamConnector::new(config, target, tls))
+}
+
+impl ServiceHandler for LoadedForward {
+    fn handle_request<'a>(
+        &'a self,
+        req: &'a mut http::Request<RequestBody>,
+    ) -> BoxResponseFuture<'a> {
+        Box::pin(async move {
+            let (resp, failure) = match self.forward(req).await {
+                Ok(resp) => (resp, None),
+                Err(e) => {
+                    let failure = e.proxy_failure();
+                    (e.into_response(self.errors.debug), failure)
+                }
+            };
+            intercept(&self.errors, req, resp, failure).await
+        })
+    }
+}
+
+/// Where the upstreams of one request come from.
+enum Route<'a> {
+    Balanced(&'a Balancer),
+    Dynamic(std::sync::Arc<Upstream>),
+}
+
+/// Why a request could not be forwarded.
+enum ForwardError {
+    /// The client sent more than the listener's `max_body_bytes` or `max_request_body_bytes`.
+    BodyTooLarge,
+    /// One of the `timeouts` ran out: 504.
+    Timeout(String),
+    /// No connection to the upstream: 502.
+    Connect(String),
+    /// The TLS handshake with the upstream failed: 502.
+    Tls(String),
+    /// The connection broke before a response came: 502.
+    Reset(String),
+    /// The request names an upstream `dynamic_target` does not allow: 403.
+    Forbidden(String),
+    /// Anything else: 502.
+    Upstream(String),
+}
+
+impl ForwardError {
+    fn from_client(e: &hyper_util::client::legacy::Error) -> Self {
+        let msg = format!("upstream request failed: {}", error_chain(e));
+        if find_source::<LengthLimitError>(e).is_some() {
+            ForwardError::BodyTooLarge
+        } else if is_timeout(e) {
+            ForwardError::Timeout(msg)
+        } else if is_tls_failure(e) {
+            ForwardError::Tls(msg)
+        } else if e.is_connect() {
+            ForwardError::Connect(msg)
+        } else if is_reset(e) {
+            ForwardError::Reset(msg)
+        } else {
+            ForwardError::Upstream(msg)
+        }
+    }
+
+    fn failure(&self) -> Option<RetryFailure> {
+        match self {
+            ForwardError::Timeout(_) => Some(RetryFailure::Timeout),
+            // the handshake is part of connecting
+            ForwardError::Connect(_) | ForwardError::Tls(_) => Some(RetryFailure::ConnectError),
+            ForwardError::Reset(_) => Some(RetryFailure::Reset),
+            ForwardError::BodyTooLarge | ForwardError::Forbidden(_) | ForwardError::Upstream(_) => None,
+        }
+    }
+
+    /// What `errors.pages` knows this failure as.
+    fn proxy_failure(&self) -> Option<ProxyFailure> {
+        match self {
+            ForwardError::Timeout(_) => Some(ProxyFailure::Timeout),
+            ForwardError::Connect(_) => Some(ProxyFailure::ConnectError),
+            ForwardError::Tls(_) => Some(ProxyFailure::TlsError),
+            ForwardError::Reset(_) => Some(ProxyFailure::Reset),
+            ForwardError::BodyTooLarge | ForwardError::Forbidden(_) | ForwardError::Upstream(_) => None,
+        }
+    }
+
+    /// The error response; what went wrong upstream is only told with `debug`.
+    fn into_response(self, debug: bool) -> http::Response<ResponseBody> {
+        let (status, msg) = match self {
+            ForwardError::BodyTooLarge =>
+                return make_error_resp(http::StatusCode::PAYLOAD_TOO_LARGE, "request body too large"),
+            ForwardError::Forbidden(msg) => return make_error_resp(http::StatusCode::FORBIDDEN, &msg),
+            ForwardError::Timeout(msg) => (http::StatusCode::GATEWAY_TIMEOUT, msg),
+            ForwardError::Connect(msg) | ForwardError::Tls(msg) | ForwardError::Reset(msg) | ForwardError::Upstream(msg) =>
+                (http::StatusCode::BAD_GATEWAY, msg),
+        };
+        eprintln!("Forward: {msg}");
+        if debug {
+            make_error_resp(status, &msg)
+        } else {
+            make_error_resp(status, status.canonical_reason().unwrap_or("upstream error"))
+        }
+    }
+}
+
+impl From<String> for ForwardError {
+    fn from(msg: String) -> Self {
+        ForwardError::Upstream(msg)
+    }
+}
+
+/// The answer to one try, with the request still counted against its upstream.
+type Attempt = Result<(http::Response<hyper::body::Incoming>, InFlight), ForwardError>;
+
+impl LoadedForward {
+    /// Send the request upstream, streaming its body, and hand the upstream
+    /// response back as soon as its head arrives; the body follows as it comes.
+    /// Failed tries are repeated as far as the `retry` policy allows.
+    async fn forward(
+        &self,
+        req: &mut http::Request<RequestBody>,
+    ) -> Result<http::Response<ResponseBody>, ForwardError> {
+        if let Some(max) = self.config.max_request_body_bytes {
+            // turned down before the body is read, so a client waiting on
+            // `Expect: 100-continue` is never told to send it
+            if content_length(req).is_some_and(|len| len > max) {
+                return Err(ForwardError::BodyTooLarge);
+            }
+            // chunked bodies are counted as they stream through
+            let limit = usize::try_from(max).unwrap_or(usize::MAX);
+            let body = std::mem::replace(req.body_mut(), empty());
+            *req.body_mut() = Limited::new(body, limit).boxed();
+        }
+        let retry = self.config.retry.as_ref().filter(|policy| {
+            // the body is streamed, not kept, so only an empty one can be sent again
+            req.body().is_end_stream()
+                && HttpMethod::try_from(req.method().as_str()).is_ok_and(|m| policy.allows(&m))
+        });
+        let route = match &self.upstreams {
+            ForwardUpstreams::Fixed(balancer) => Route::Balanced(balancer),
+            ForwardUpstreams::Dynamic(dynamic) => Route::Dynamic(dynamic.upstream(req, &self.config)?),
+        };
+        let started = Instant::now();
+        let mut tried: Vec<&Upstream> = Vec::new();
+        let (upstream_resp, in_flight) = loop {
+            let upstream = match &route {
+                Route::Balanced(balancer) => balancer.pick(req, &tried),
+                Route::Dynamic(upstream) => upstream,
+            };
+            let attempt = self.send(upstream, req).await;
+            tried.push(upstream);
+            let Some(policy) = retry else { break attempt? };
+            let Some(why) = retry_reason(policy, &attempt) else { break attempt? };
+            let retries = tried.len() as u32;
+            let backoff = policy.backoff(retries);
+            let over_budget = policy.budget_ms
+                .is_some_and(|ms| started.elapsed() + backoff > Duration::from_millis(ms));
+            if retries >= policy.attempts || over_budget {
+                break attempt?;
+            }
+            eprintln!("Forward: retrying {} {} after {why} from {}", req.method(), req.uri().path(), upstream.target.endpoint());
+            drop(attempt);
+            tokio::time::sleep(backoff).await;
+        };
+
+        // the one that answered
+        let target = &tried[tried.len() - 1].target;
+        if upstream_resp.status() == http::StatusCode::SWITCHING_PROTOCOLS {
+            return self.switch_protocols(req, target, upstream_resp, in_flight);
+        }
+        let (mut parts, body) = upstream_resp.into_parts();
+        self.response_headers(req, target, &mut parts)?;
+        let upstream_resp = http::Response::from_parts(parts, body);
+
+        let timeouts = &self.config.timeouts;
+        // the head is gone by the time the body fails, so the log is all that is left
+        Ok(upstream_resp.map(|body| match timeouts.read() {
+            Some(limit) => IdleTimeout::new(Counted::new(body, in_flight), "reading the response body", limit)
+                .map_err(|e| {
+                    eprintln!("Forward: {e}");
+                    e
+                })
+                .boxed(),
+            None => Counted::new(body, in_flight).map_err(BoxError::from).boxed(),
+        }))
+    }
+
+    /// Pass a `101 Switching Protocols` on, then join both connections once
+    /// they have switched.
+    fn switch_protocols(
+        &self,
+        req: &mut http::Request<RequestBody>,
+        target: &ForwardTarget,
+        mut upstream_resp: http::Response<hyper::body::Incoming>,
+        in_flight: InFlight,
+    ) -> Result<http::Response<ResponseBody>, ForwardError> {
+        let downstream = requested_protocol(req, &self.config)
+            .and_then(|_| req.extensions_mut().remove::<OnUpgrade>())
+            .ok_or_else(|| "upstream switched protocols without being asked to".to_string())?;
+        // only requests from a listener can be upgraded, and they all have one
+        let tasks = req.extensions().get::<ConnTasks>().cloned()
+            .ok_or_else(|| "no connection to upgrade".to_string())?;
+        let protocol = upstream_resp.headers().get(http::header::UPGRADE).cloned()
+            .ok_or_else(|| "upstream switched protocols without naming one".to_string())?;
+        let upstream = hyper::upgrade::on(&mut upstream_resp);
+        spawn_splice(&tasks, downstream, upstream, self.config.timeouts.upgrade_idle(), in_flight);
+
+        let (mut parts, _) = upstream_resp.into_parts();
+        self.response_headers(req, target, &mut parts)?;
+        parts.headers.insert(http::header::CONNECTION, http::HeaderValue::from_static("upgrade"));
+        parts.headers.insert(http::header::UPGRADE, protocol);
+        Ok(http::Response::from_parts(parts, empty()))
+    }
+
+    /// Headers of an upstream response as the client gets them.
+    fn response_headers(
+        &self,
+        req: &http::Request<RequestBody>,
+        target: &ForwardTarget,
+        parts: &mut http::response::Parts,
+    ) -> Result<(), ForwardError> {
+        filter_response_headers(&mut parts.headers, &self.config.response_headers);
+        if let Some(pseudonym) = &self.config.via {
+            append_via(&mut parts.headers, parts.version, pseudonym);
+        }
+        if self.config.rewrite_redirects || self.config.rewrite_cookies {
+            let upstream_host = self.host_header(target, req)?;
+            let public_host = incoming_host(req);
+            let rewrite = ResponseRewrite {
+                target,
+                upstream_host: upstream_host.as_ref().and_then(|h| h.to_str().ok()),
+                public_scheme: request_scheme(req).unwrap_or("http"),
+                public_host: public_host.as_ref().and_then(|h| h.to_str().ok()).unwrap_or_else(|| target.host()),
+            };
+            if self.config.rewrite_redirects {
+                rewrite.urls(&mut parts.headers);
+            }
+            if self.config.rewrite_cookies {
+                rewrite.cookies(&mut parts.headers);
+            }
+        }
+        Ok(())
+    }
+
+    /// One try at `upstream`, taking the request body along.
+    async fn send(&self, upstream: &Upstream, req: &mut http::Request<RequestBody>) -> Attempt {
+        let target = &upstream.target;
+        let upstream_uri = build_upstream_uri(target, req)?;
+        let timeouts = &self.config.timeouts;
+        let mut body = std::mem::replace(req.body_mut(), empty());
+        if let Some(limit) = timeouts.write() {
+            body = IdleTimeout::new(body, "sending the request body", limit).boxed();
+        }
+        let (body, sent) = Sent::new(body);
+
+        let mut upstream_req = http::Request::builder()
+            .method(req.method())
+            .uri(upstream_uri)
+            .body(body.boxed())
+            .map_err(|e| format!("failed to build upstream request: {e}"))?;
+
+        // copy rest of headers
+        copy_headers(req, &mut upstream_req, self.host_header(target, req)?, &self.config);
+
+        let in_flight = upstream.start_request();
+        let sending = upstream.client.request(upstream_req);
+        let result = match timeouts.read() {
+            Some(limit) => {
+                // the response is only due once the whole request is sent
+                let due = async {
+                    let _ = sent.await;
+                    sleep(limit).await;
+                };
+                tokio::select! {
+                    resp = sending => resp.map_err(|e| ForwardError::from_client(&e)),
+                    () = due => Err(ForwardError::Timeout(TimedOut { what: "waiting for the response", after: limit }.to_string())),
+                }
+            }
+            None => sending.await.map_err(|e| ForwardError::from_client(&e)),
+        };
+        if let Some(passive) = &self.config.health.passive {
+            let failed = match &result {
+                Ok(resp) => resp.status().is_server_error(),
+                // the client's fault, not the upstream's
+                Err(ForwardError::BodyTooLarge) => false,
+                Err(_) => true,
+            };
+            upstream.health.record(!failed, passive, &target.endpoint());
+        }
+        result.map(|resp| (resp, in_flight))
+    }
+
+    /// Decide the Host header value based on pass_host strategy.
+    fn host_header(
+        &self,
+        target: &ForwardTarget,
+        req: &http::Request<RequestBody>,
+    ) -> ForwardResult<Option<http::HeaderValue>> {
+        match &self.config.pass_host {
+            PassHost::Mode(PassHostMode::Incoming) =>
+                incoming_host(req)
+                    .and_then(|v| v.to_str().ok().map(|s| s.to_string())),
+            PassHost::Mode(PassHostMode::Target) =>
+                Some(format_host(target.host(), target.port(), target.scheme)),
+            PassHost::Custom { custom } => Some(custom.clone()),
+        }.map(|h| http::HeaderValue::from_str(&h)
+            .map_err(|e| format!("invalid host header value: {e}")))
+            .transpose()
+    }
+}
+
+fn build_upstream_uri(
+    target: &ForwardTarget,
+    req: &http::Request<RequestBody>,
+) -> ForwardResult<Uri> {
+    let scheme = target.scheme.as_str();
+
+    let mut path = target.path_prefix.clone();
+
+    if path.ends_with('/') && req.uri().path().starts_with('/') {
+        path.pop();
+    }
+
+    path.push_str(req.uri().path());
+
+    if !path.starts_with('/') {
+        path.insert(0, '/');
+    }
+
+    let mut uri = format!("{scheme}://{}:{}{}", target.host(), target.port(), path);
+    if let Some(q) = req.uri().query() {
+        uri.push('?');
+        uri.push_str(q);
+    }
+
+    uri.parse::<Uri>()
+        .map_err(|e| format!("failed to build upstream URI: {e}"))
+}
+
+/// Copy downstream headers into the upstream request, then apply Host, X-Forwarded-* and Forwarded if enabled.
+fn copy_headers(
+    downstream: &http::Request<RequestBody>,
+    upstream: &mut http::Request<RequestBody>,
+    host_header: Option<http::HeaderValue>,
+    config: &ForwardService,
+) {
+    let headers = upstream.headers_mut();
+
+    let listed = connection_listed(downstream.headers());
+    for (name, value) in downstream.headers() {
+        if name == http::header::HOST || is_hop_by_hop(name, &listed) {
+            continue;
+        }
+        headers.append(name, value.clone());
+    }
+    if let Some(protocol) = requested_protocol(downstream, config) {
+        headers.insert(http::header::CONNECTION, http::HeaderValue::from_static("upgrade"));
+        headers.insert(http::header::UPGRADE, protocol);
+    }
+    if accepts_trailers(downstream.headers()) {
+        headers.insert(http::header::TE, http::HeaderValue::from_static("trailers"));
+    }
+    if let Some(pseudonym) = &config.via {
+        append_via(headers, downstream.version(), pseudonym);
+    }
+
+    if let Some(host) = host_header {
+        headers.insert(http::header::HOST, host);
+    }
+
+    // the hop we append is whoever connected to us, not the resolved client
+    let peer = downstream.extensions().get::<PeerAddr>().map(|p| p.0.ip())
+        .or_else(|| downstream.extensions().get::<ClientAddr>().map(|c| c.ip));
+    let proto = request_scheme(downstream).unwrap_or("http");
+
+    if config.x_forwarded {
+        if let Some(host) = incoming_host(downstream) {
+            headers.insert(
+                http::header::HeaderName::from_static("x-forwarded-host"),
+                host,
+            );
+        }
+
+        if let Ok(xfp) = http::HeaderValue::from_str(proto) {
+            headers.insert(
+                http::header::HeaderName::from_static("x-forwarded-proto"),
+                xfp,
+            );
+        }
+
+        let xff_name = http::header::HeaderName::from_static("x-forwarded-for");
+        let peer_ip = peer.map(|ip| ip.to_string());
+        if let Some(xff) = append_list(downstream.headers(), &xff_name, peer_ip) {
+            headers.insert(xff_name, xff);
+        }
+    }
+
+    if config.forwarded {
+        let mut element = String::new();
+        if let Some(ip) = peer {
+            element.push_str(&format!("for={};", forwarded_node(ip)));
+        }
+        element.push_str(&format!("proto={proto}"));
+        if let Some(host) = incoming_host(downstream).and_then(|h| h.to_str().ok().map(|s| s.to_string())) {
+            element.push_str(&format!(";host=\"{host}\""));
+        }
+        if let Some(fwd) = append_list(downstream.headers(), &http::header::FORWARDED, Some(element)) {
+            headers.insert(http::header::FORWARDED, fwd);
+        }
+    }
+}
+
+/// Join all values of a list-valued header and append `item`, as proxies do for
+/// `X-Forwarded-For` and `Forwarded`.
+fn append_list(
+    headers: &http::HeaderMap,
+    name: &http::header::HeaderName,
+    item: Option<String>,
+) -> Option<http::HeaderValue> {
+    let mut items: Vec<String> = headers.get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .map(|s| s.to_string())
+        .collect();
+    items.extend(item);
+    if items.is_empty() {
+        return None;
+    }
+    http::HeaderValue::from_str(&items.join(", ")).ok()
+}
+
+/// RFC 7239 node: IPv6 addresses are bracketed and quoted.
+fn forwarded_node(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{ip}]\""),
+    }
+}
+
+/// Host the client asked for: the `Host` header, or the `:authority` of HTTP/2 requests.
+fn incoming_host(req: &http::Request<RequestBody>) -> Option<http::HeaderValue> {
+    req.headers().get(http::header::HOST).cloned().or_else(|| {
+        req.uri().authority().and_then(|a| http::HeaderValue::from_str(a.as_str()).ok())
+    })
+}
+
+/// The first error of type `E` in the chain of `e`, itself included.
+fn find_source<'a, E: std::error::Error + 'static>(e: &'a (dyn std::error::Error + 'static)) -> Option<&'a E> {
+    let mut source = Some(e);
+    while let Some(cause) = source {
+        if let Some(found) = cause.downcast_ref::<E>() {
+            return Some(found);
+        }
+        source = cause.source();
+    }
+    None
+}
+
+/// What about `attempt` calls for a retry under `policy`, if anything.
+fn retry_reason(policy: &RetryPolicy, attempt: &Attempt) -> Option<String> {
+    let on = match attempt {
+        Ok((resp, _)) => RetryOn::Status(resp.status().as_u16()),
+        Err(e) => RetryOn::Failure(e.failure()?),
+    };
+    if !policy.on.contains(&on) {
+        return None;
+    }
+    Some(match (on, attempt) {
+        (RetryOn::Status(code), _) => format!("status {code}"),
+        (_, Err(ForwardError::Timeout(msg) | ForwardError::Connect(msg) | ForwardError::Tls(msg) | ForwardError::Reset(msg))) => msg.clone(),
+        _ => "a failure".to_string(),
+    })
+}
+
+/// Whether the upstream failed the TLS handshake, or we refused its certificate.
+fn is_tls_failure(e: &(dyn std::error::Error + 'static)) -> bool {
+    // tokio-rustls hands its errors over inside io errors, which hide them from `source()`
+    find_source::<std::io::Error>(e)
+        .and_then(|io| io.get_ref())
+        .is_some_and(|inner| inner.is::<rustls::Error>())
+}
+
+/// Whether the connection to the upstream broke before the response came.
+fn is_reset(e: &(dyn std::error::Error + 'static)) -> bool {
+    find_source::<hyper::Error>(e).is_some_and(|h| h.is_incomplete_message() || h.is_canceled())
+        || find_source::<std::io::Error>(e).is_some_and(|io| matches!(
+            io.kind(),
+            std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::UnexpectedEof,
+        ))
+}
+
+/// Whether a failed upstream request ran into a timeout: ours, or one the
+/// connection reported.
+fn is_timeout(e: &(dyn std::error::Error + 'static)) -> bool {
+    find_source::<TimedOut>(e).is_some()
+        || find_source::<std::io::Error>(e).is_some_and(|io| io.kind() == std::io::ErrorKind::TimedOut)
+}
+
+/// An error with its sources, as hyper's own messages leave out the cause.
+pub(crate) fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut out = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        out.push_str(": ");
+        out.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    out
+}
+
+/// Drop default ports for http/https when formatting host header.
+pub(crate) fn format_host(host: &str, port: u16, scheme: Scheme) -> String {
+    let default_port = matches!((scheme, port), (Scheme::Http, 80) | (Scheme::Https, 443));
+    if default_port {
+        host.to_string()
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+#[cfg(test)]
+mod tests;
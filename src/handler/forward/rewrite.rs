@@ -0,0 +1,124 @@
+//! Mapping URLs and cookies in upstream responses back to what the client
+//! sees: the reverse of `build_upstream_uri`.
+
+use hyper::http::{header, HeaderMap, HeaderValue, Uri};
+
+use crate::config::forward::ForwardTarget;
+
+use super::format_host;
+
+/// Where a response came from, and where the client thinks it did.
+pub struct ResponseRewrite<'a> {
+    pub target: &'a ForwardTarget,
+    /// The `Host` the upstream was sent, which it builds its URLs from.
+    pub upstream_host: Option<&'a str>,
+    pub public_scheme: &'a str,
+    pub public_host: &'a str,
+}
+
+impl ResponseRewrite<'_> {
+    /// Rewrite `Location`, `Content-Location` and `Refresh`.
+    pub fn urls(&self, headers: &mut HeaderMap) {
+        for name in [header::LOCATION, header::CONTENT_LOCATION] {
+            let Some(url) = headers.get(&name).and_then(|v| v.to_str().ok()) else { continue };
+            if let Some(url) = self.url(url).and_then(|u| HeaderValue::from_str(&u).ok()) {
+                headers.insert(name, url);
+            }
+        }
+        let refresh = header::HeaderName::from_static("refresh");
+        let Some(value) = headers.get(&refresh).and_then(|v| v.to_str().ok()) else { return };
+        // `5; url=https://example.com/`
+        let Some(at) = value.to_ascii_lowercase().find("url=") else { return };
+        let (before, url) = value.split_at(at + "url=".len());
+        let quote = url.chars().next().filter(|c| *c == '\'' || *c == '"');
+        let url = quote.map_or(url, |q| url.trim_matches(q));
+        if let Some(url) = self.url(url) {
+            let q = quote.map(String::from).unwrap_or_default();
+            if let Ok(value) = HeaderValue::from_str(&format!("{before}{q}{url}{q}")) {
+                headers.insert(refresh, value);
+            }
+        }
+    }
+
+    /// Rewrite the `Domain` and `Path` of every `Set-Cookie`.
+    pub fn cookies(&self, headers: &mut HeaderMap) {
+        let cookies: Vec<HeaderValue> = headers.get_all(header::SET_COOKIE).iter()
+            .map(|cookie| match cookie.to_str() {
+                Ok(text) => HeaderValue::from_str(&self.cookie(text)).unwrap_or_else(|_| cookie.clone()),
+                Err(_) => cookie.clone(),
+            })
+            .collect();
+        headers.remove(header::SET_COOKIE);
+        for cookie in cookies {
+            headers.append(header::SET_COOKIE, cookie);
+        }
+    }
+
+    /// `url` as the client should see it, if it points into the upstream.
+    fn url(&self, url: &str) -> Option<String> {
+        if url.starts_with('/') && !url.starts_with("//") {
+            return self.strip_prefix(url);
+        }
+        let uri: Uri = url.parse().ok()?;
+        let authority = uri.authority()?;
+        let ours = self.upstream_hosts().any(|h| h.eq_ignore_ascii_case(authority.as_str()));
+        if !ours || uri.scheme_str() != Some(self.target.scheme.as_str()) {
+            return None;
+        }
+        let (_, after_scheme) = url.split_once("://")?;
+        let rest = after_scheme.get(authority.as_str().len()..)?;
+        let path = self.strip_prefix(rest).unwrap_or_else(|| rest.to_string());
+        Some(format!("{}://{}{path}", self.public_scheme, self.public_host))
+    }
+
+    fn cookie(&self, cookie: &str) -> String {
+        let mut parts: Vec<String> = cookie.split(';').map(str::to_string).collect();
+        // the first part is `name=value`, only attributes follow
+        for part in parts.iter_mut().skip(1) {
+            let Some((name, value)) = part.split_once('=') else { continue };
+            let (name, value) = (name.trim(), value.trim());
+            if name.eq_ignore_ascii_case("domain") {
+                let domain = value.trim_start_matches('.');
+                if self.upstream_hosts().any(|h| strip_port(&h).eq_ignore_ascii_case(domain)) {
+                    *part = format!(" {name}={}", strip_port(self.public_host));
+                }
+            } else if name.eq_ignore_ascii_case("path")
+                && let Some(path) = self.strip_prefix(value) {
+                *part = format!(" {name}={path}");
+            }
+        }
+        parts.join(";")
+    }
+
+    /// The names the upstream may know itself by.
+    fn upstream_hosts(&self) -> impl Iterator<Item = String> + '_ {
+        let target = self.target;
+        [
+            Some(format_host(target.host(), target.port(), target.scheme)),
+            Some(format!("{}:{}", target.host(), target.port())),
+            self.upstream_host.map(str::to_string),
+        ].into_iter().flatten()
+    }
+
+    /// `path` without the target's `path_prefix`, if it starts with it.
+    fn strip_prefix(&self, path: &str) -> Option<String> {
+        let prefix = self.target.path_prefix.trim_matches('/');
+        if prefix.is_empty() {
+            return None;
+        }
+        let rest = path.strip_prefix('/')?.strip_prefix(prefix)?;
+        match rest {
+            rest if rest.starts_with('/') => Some(rest.to_string()),
+            rest if rest.is_empty() || rest.starts_with(['?', '#']) => Some(format!("/{rest}")),
+            _ => None,
+        }
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        // not the colons of a bracketed IPv6 address
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    }
+}
@@ -0,0 +1,1413 @@
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{http, Request};
+
+use std::sync::atomic::Ordering;
+
+use crate::test_util::{echo, get, read_until, send, spawn_counting_upstream, spawn_echo_upstream, spawn_server, spawn_tls_upstream, spawn_upstream, TestPki};
+
+fn forward_yaml(upstream: std::net::SocketAddr, extra: &str) -> String {
+    format!(r#"
+bind: "127.0.0.1:0"
+service:
+  handler: forward
+  target:
+    scheme: http
+    host: "{}"
+    port: {}
+{extra}
+"#, upstream.ip(), upstream.port())
+}
+
+fn header_line<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    body.lines()
+        .find_map(|l| l.strip_prefix(name).and_then(|rest| rest.strip_prefix(": ")))
+}
+
+#[tokio::test]
+async fn appends_client_ip_to_x_forwarded_for() {
+    let upstream = spawn_echo_upstream().await;
+    let gw = spawn_server(&forward_yaml(upstream, "")).await;
+
+    let (_, _, body) = send(gw, get("/plain")).await;
+    let body = String::from_utf8_lossy(&body);
+    assert_eq!(header_line(&body, "x-forwarded-for"), Some("127.0.0.1"));
+
+    let req: Request<Full<Bytes>> = Request::builder()
+        .uri("/chained")
+        .header(http::header::HOST, "gateway.test")
+        .header("x-forwarded-for", "203.0.113.7")
+        .body(Full::default())
+        .unwrap();
+    let (_, _, body) = send(gw, req).await;
+    let body = String::from_utf8_lossy(&body);
+    // no `trusted_proxies`: nobody may claim to forward for someone else
+    assert_eq!(header_line(&body, "x-forwarded-for"), Some("127.0.0.1"));
+    assert_eq!(header_line(&body, "x-forwarded-proto"), Some("http"));
+    assert_eq!(header_line(&body, "forwarded"), None);
+}
+
+#[tokio::test]
+async fn emits_rfc7239_forwarded_when_enabled() {
+    let upstream = spawn_echo_upstream().await;
+    let gw = spawn_server(&forward_yaml(upstream, "  forwarded: true")).await;
+
+    let (_, _, body) = send(gw, get("/")).await;
+    let body = String::from_utf8_lossy(&body);
+    assert_eq!(
+        header_line(&body, "forwarded"),
+        Some("for=127.0.0.1;proto=http;host=\"gateway.test\""),
+    );
+}
+
+#[tokio::test]
+async fn trusted_chain_is_forwarded_without_spoofed_hops() {
+    let upstream = spawn_echo_upstream().await;
+    let yaml = forward_yaml(upstream, "").replace(
+        "bind: \"127.0.0.1:0\"",
+        "bind: \"127.0.0.1:0\"\ntrusted_proxies: [\"127.0.0.1\", \"10.0.0.0/8\"]",
+    );
+    let gw = spawn_server(&yaml).await;
+
+    let req: Request<Full<Bytes>> = Request::builder()
+        .uri("/")
+        .header(http::header::HOST, "gateway.test")
+        .header("x-forwarded-for", "1.1.1.1, 203.0.113.9, 10.1.2.3")
+        .body(Full::default())
+        .unwrap();
+    let (_, _, body) = send(gw, req).await;
+    let body = String::from_utf8_lossy(&body);
+    assert_eq!(header_line(&body, "x-forwarded-for"), Some("203.0.113.9, 10.1.2.3, 127.0.0.1"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn forwards_to_unix_socket_upstream() {
+    let path = std::env::temp_dir().join(format!("oxidase-{}-upstream.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    crate::test_util::spawn_unix_upstream(&path, crate::test_util::echo);
+
+    let gw = spawn_server(&format!(r#"
+bind: "127.0.0.1:0"
+service:
+  handler: forward
+  pass_host: target
+  target:
+    scheme: http
+    unix: "{}"
+"#, path.display())).await;
+
+    let (status, _, body) = send(gw, get("/via/unix?q=1")).await;
+    let body = String::from_utf8_lossy(&body);
+    assert_eq!(status, http::StatusCode::OK);
+    assert!(body.starts_with("GET /via/unix?q=1\n"), "{body}");
+    assert_eq!(header_line(&body, "host"), Some("localhost"));
+    let _ = std::fs::remove_file(&path);
+}
+
+fn https_yaml(upstream: std::net::SocketAddr, host: &str, tls: &str) -> String {
+    format!(r#"
+bind: "127.0.0.1:0"
+service:
+  handler: forward
+  target:
+    scheme: https
+    host: "{host}"
+    port: {}
+  tls:
+    use_system_roots: false
+{tls}
+"#, upstream.port())
+}
+
+fn built_err(yaml: &str) -> String {
+    let cfg: crate::config::http_server::HttpServer = serde_yaml::from_str(yaml).unwrap();
+    crate::build::build_http_server(cfg).expect_err("build should fail").to_string()
+}
+
+#[tokio::test]
+async fn https_upstream_is_verified_against_configured_cas() {
+    let pki = TestPki::new("verify");
+    let upstream = spawn_tls_upstream(pki.server_config(false, rustls::DEFAULT_VERSIONS), echo).await;
+
+    let ca_file = format!("    ca_file: \"{}\"", pki.path("ca.pem"));
+    let gw = spawn_server(&https_yaml(upstream, "localhost", &ca_file)).await;
+    let (status, _, body) = send(gw, get("/secure")).await;
+    assert_eq!(status, http::StatusCode::OK, "{body:?}");
+    assert!(body.starts_with(b"GET /secure"));
+
+    let inline = format!("    ca_inline: |\n{}", pki.ca_pem.lines().map(|l| format!("      {l}\n")).collect::<String>());
+    let gw = spawn_server(&https_yaml(upstream, "localhost", &inline)).await;
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::OK);
+
+    // an issuer nobody trusts
+    let other = TestPki::new("verify-other");
+    let ca_files = format!("    ca_files: [\"{}\"]", other.path("ca.pem"));
+    let gw = spawn_server(&https_yaml(upstream, "localhost", &ca_files)).await;
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::BAD_GATEWAY);
+
+    assert!(built_err(&https_yaml(upstream, "localhost", "")).contains("trusts no certificate authority"));
+}
+
+#[tokio::test]
+async fn https_upstream_host_name_checks() {
+    let pki = TestPki::new("names");
+    let upstream = spawn_tls_upstream(pki.server_config(false, rustls::DEFAULT_VERSIONS), echo).await;
+    let ca_file = format!("    ca_file: \"{}\"", pki.path("ca.pem"));
+
+    // the certificate is for localhost, not the IP
+    let gw = spawn_server(&https_yaml(upstream, "127.0.0.1", &format!("{ca_file}\n  errors: {{ debug: true }}"))).await;
+    let (status, _, body) = send(gw, get("/")).await;
+    assert_eq!(status, http::StatusCode::BAD_GATEWAY);
+    assert!(String::from_utf8_lossy(&body).contains("certificate"), "{body:?}");
+
+    for extra in ["    sni: localhost", "    allow_invalid_hostnames: true"] {
+        let gw = spawn_server(&https_yaml(upstream, "127.0.0.1", &format!("{ca_file}\n{extra}"))).await;
+        assert_eq!(send(gw, get("/")).await.0, http::StatusCode::OK, "{extra}");
+    }
+
+    let gw = spawn_server(&https_yaml(upstream, "127.0.0.1", "    insecure_skip_verify: true")).await;
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn https_upstream_client_certificates() {
+    let pki = TestPki::new("mtls");
+    let upstream = spawn_tls_upstream(pki.server_config(true, rustls::DEFAULT_VERSIONS), echo).await;
+    let ca_file = format!("    ca_file: \"{}\"", pki.path("ca.pem"));
+
+    let gw = spawn_server(&https_yaml(upstream, "localhost", &ca_file)).await;
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::BAD_GATEWAY);
+
+    let with_cert = format!(
+        "{ca_file}\n    client_cert_file: \"{}\"\n    client_key_file: \"{}\"",
+        pki.path("client.pem"), pki.path("client.key"),
+    );
+    let gw = spawn_server(&https_yaml(upstream, "localhost", &with_cert)).await;
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::OK);
+
+    let half = format!("{ca_file}\n    client_cert_file: \"{}\"", pki.path("client.pem"));
+    assert!(built_err(&https_yaml(upstream, "localhost", &half)).contains("must be set together"));
+}
+
+#[tokio::test]
+async fn https_upstream_versions_and_suites() {
+    let pki = TestPki::new("versions");
+    let upstream = spawn_tls_upstream(pki.server_config(false, &[&rustls::version::TLS12]), echo).await;
+    let ca_file = format!("    ca_file: \"{}\"", pki.path("ca.pem"));
+
+    let gw = spawn_server(&https_yaml(upstream, "localhost", &format!("{ca_file}\n    max_tls: \"1.2\""))).await;
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::OK);
+    let gw = spawn_server(&https_yaml(upstream, "localhost", &format!("{ca_file}\n    min_tls: \"1.3\""))).await;
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::BAD_GATEWAY);
+
+    let suites = format!("{ca_file}\n    cipher_suites: [TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256]");
+    let gw = spawn_server(&https_yaml(upstream, "localhost", &suites)).await;
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::OK);
+
+    let unknown = format!("{ca_file}\n    cipher_suites: [TLS_NULL]");
+    assert!(built_err(&https_yaml(upstream, "localhost", &unknown)).contains("unknown suite `TLS_NULL`"));
+    let inverted = format!("{ca_file}\n    min_tls: \"1.3\"\n    max_tls: \"1.2\"");
+    assert!(built_err(&https_yaml(upstream, "localhost", &inverted)).contains("min_tls"));
+}
+
+#[tokio::test]
+async fn https_upstream_handshake_timeout() {
+    // accepts TCP connections but never answers the ClientHello
+    let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream = silent.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut open = Vec::new();
+        while let Ok((stream, _)) = silent.accept().await {
+            open.push(stream);
+        }
+    });
+
+    let tls = "    insecure_skip_verify: true\n    handshake_timeout_ms: 200\n  errors: { debug: true }";
+    let gw = spawn_server(&https_yaml(upstream, "localhost", tls)).await;
+    let started = std::time::Instant::now();
+    let (status, _, body) = send(gw, get("/")).await;
+    assert_eq!(status, http::StatusCode::GATEWAY_TIMEOUT);
+    assert!(String::from_utf8_lossy(&body).contains("timed out"), "{body:?}");
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn upstream_connections_are_reused() {
+    let (upstream, accepted) = spawn_counting_upstream(echo).await;
+    let gw = spawn_server(&forward_yaml(upstream, "")).await;
+
+    for _ in 0..3 {
+        let (status, _, _) = send(gw, get("/")).await;
+        assert_eq!(status, http::StatusCode::OK);
+    }
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn upstream_pool_can_be_disabled() {
+    let (upstream, accepted) = spawn_counting_upstream(echo).await;
+    let gw = spawn_server(&forward_yaml(upstream, "  pool:\n    max_idle_per_host: 0")).await;
+
+    for _ in 0..3 {
+        send(gw, get("/")).await;
+    }
+    assert_eq!(accepted.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn idle_upstream_connections_expire() {
+    let (upstream, accepted) = spawn_counting_upstream(echo).await;
+    let gw = spawn_server(&forward_yaml(upstream, "  pool:\n    idle_timeout_ms: 50\n    tcp_keepalive_ms: 30000")).await;
+
+    send(gw, get("/")).await;
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    send(gw, get("/")).await;
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn pool_durations_are_validated() {
+    let upstream: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    for pool in ["idle_timeout_ms: 0", "tcp_keepalive_ms: 0"] {
+        let err = built_err(&forward_yaml(upstream, &format!("  pool:\n    {pool}")));
+        assert!(err.contains("`forward.pool`"), "{err}");
+    }
+}
+
+#[tokio::test]
+async fn response_bodies_are_streamed() {
+    use tokio::io::AsyncWriteExt;
+
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let (release, released) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(async move {
+        let (mut tcp, _) = upstream.accept().await.unwrap();
+        read_until(&mut tcp, "\r\n\r\n").await;
+        tcp.write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nfirst\r\n").await.unwrap();
+        released.await.unwrap();
+        tcp.write_all(b"6\r\nsecond\r\n0\r\n\r\n").await.unwrap();
+    });
+    let gw = spawn_server(&forward_yaml(upstream_addr, "")).await;
+
+    let mut tcp = tokio::net::TcpStream::connect(gw).await.unwrap();
+    tcp.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+    let head = read_until(&mut tcp, "first").await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    assert!(!head.contains("second"));
+
+    release.send(()).unwrap();
+    read_until(&mut tcp, "second").await;
+}
+
+#[tokio::test]
+async fn request_bodies_are_streamed() {
+    use tokio::io::AsyncWriteExt;
+
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let (got_first, first_seen) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(async move {
+        let (mut tcp, _) = upstream.accept().await.unwrap();
+        read_until(&mut tcp, "first").await;
+        got_first.send(()).unwrap();
+        read_until(&mut tcp, "0\r\n\r\n").await;
+        tcp.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\ndone").await.unwrap();
+    });
+    let gw = spawn_server(&forward_yaml(upstream_addr, "")).await;
+
+    let mut tcp = tokio::net::TcpStream::connect(gw).await.unwrap();
+    tcp.write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5\r\nfirst\r\n").await.unwrap();
+    // the upstream sees the start of the body while the client is still sending
+    tokio::time::timeout(std::time::Duration::from_secs(5), first_seen).await
+        .expect("request body was buffered").unwrap();
+
+    tcp.write_all(b"6\r\nsecond\r\n0\r\n\r\n").await.unwrap();
+    let raw = read_until(&mut tcp, "done").await;
+    assert!(raw.starts_with("HTTP/1.1 200"), "{raw}");
+}
+
+/// An upstream that accepts connections and never says a word.
+async fn spawn_silent_upstream() -> std::net::SocketAddr {
+    let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = silent.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut open = Vec::new();
+        while let Ok((stream, _)) = silent.accept().await {
+            open.push(stream);
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn connect_failures_are_502_and_connect_timeouts_504() {
+    // nothing listens on a port freed right away
+    let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let gw = spawn_server(&forward_yaml(closed, "  connect_ms: 1000")).await;
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::BAD_GATEWAY);
+
+    // a listener that never accepts, with its backlog full, leaves SYNs unanswered
+    let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+    socket.bind(&"127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap().into()).unwrap();
+    socket.listen(0).unwrap();
+    let full = socket.local_addr().unwrap().as_socket().unwrap();
+    let mut queued = Vec::new();
+    let connect = || tokio::time::timeout(std::time::Duration::from_millis(200), tokio::net::TcpStream::connect(full));
+    while let Ok(stream) = connect().await {
+        queued.push(stream.unwrap());
+    }
+
+    let gw = spawn_server(&forward_yaml(full, "  connect_ms: 200\n  errors: { debug: true }")).await;
+    let started = std::time::Instant::now();
+    let (status, _, body) = send(gw, get("/")).await;
+    assert_eq!(status, http::StatusCode::GATEWAY_TIMEOUT);
+    assert!(String::from_utf8_lossy(&body).contains("connect timed out after 200 ms"), "{body:?}");
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn slow_response_heads_get_504() {
+    let upstream = spawn_silent_upstream().await;
+    let gw = spawn_server(&forward_yaml(upstream, "  read_ms: 200\n  errors: { debug: true }")).await;
+
+    let started = std::time::Instant::now();
+    let (status, _, body) = send(gw, get("/")).await;
+    assert_eq!(status, http::StatusCode::GATEWAY_TIMEOUT);
+    assert!(String::from_utf8_lossy(&body).contains("waiting for the response timed out after 200 ms"), "{body:?}");
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn slow_uploads_do_not_count_against_read_ms() {
+    use tokio::io::AsyncWriteExt;
+
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut tcp, _) = upstream.accept().await.unwrap();
+        read_until(&mut tcp, "0\r\n\r\n").await;
+        tcp.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\ndone").await.unwrap();
+    });
+    let gw = spawn_server(&forward_yaml(upstream_addr, "  read_ms: 200")).await;
+
+    let mut tcp = tokio::net::TcpStream::connect(gw).await.unwrap();
+    tcp.write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n").await.unwrap();
+    // the upload as a whole takes longer than `read_ms`
+    for _ in 0..4 {
+        tcp.write_all(b"5\r\nchunk\r\n").await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+    }
+    tcp.write_all(b"0\r\n\r\n").await.unwrap();
+    let raw = read_until(&mut tcp, "done").await;
+    assert!(raw.starts_with("HTTP/1.1 200"), "{raw}");
+}
+
+#[tokio::test]
+async fn stalled_response_bodies_are_cut_off() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut tcp, _) = upstream.accept().await.unwrap();
+        read_until(&mut tcp, "\r\n\r\n").await;
+        tcp.write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nfirst\r\n").await.unwrap();
+        // hold the connection open without sending the rest
+        tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+    });
+    let gw = spawn_server(&forward_yaml(upstream_addr, "  read_ms: 200")).await;
+
+    let mut tcp = tokio::net::TcpStream::connect(gw).await.unwrap();
+    tcp.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut raw = Vec::new();
+    tokio::time::timeout(std::time::Duration::from_secs(5), tcp.read_to_end(&mut raw)).await
+        .expect("body was never cut off")
+        .ok();
+    let raw = String::from_utf8_lossy(&raw);
+    assert!(raw.starts_with("HTTP/1.1 200") && raw.contains("first"), "{raw}");
+    assert!(!raw.ends_with("0\r\n\r\n"), "body should end without its last chunk: {raw}");
+}
+
+#[tokio::test]
+async fn stalled_request_bodies_get_504() {
+    use tokio::io::AsyncWriteExt;
+
+    let upstream = spawn_silent_upstream().await;
+    let gw = spawn_server(&forward_yaml(upstream, "  write_ms: 200")).await;
+
+    let mut tcp = tokio::net::TcpStream::connect(gw).await.unwrap();
+    tcp.write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nfirst\r\n").await.unwrap();
+    let raw = read_until(&mut tcp, "\r\n\r\n").await;
+    assert!(raw.starts_with("HTTP/1.1 504"), "{raw}");
+}
+
+#[test]
+fn timeouts_are_validated() {
+    let upstream: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    for field in ["connect_ms", "read_ms", "write_ms", "upgrade_idle_ms"] {
+        let err = built_err(&forward_yaml(upstream, &format!("  {field}: 0")));
+        assert!(err.contains("`forward` timeouts must be positive"), "{err}");
+    }
+}
+
+fn version_echo(req: Request<hyper::body::Incoming>) -> hyper::Response<Full<Bytes>> {
+    hyper::Response::new(Full::from(format!("{:?}", req.version())))
+}
+
+#[tokio::test]
+async fn http2_upstreams_over_cleartext() {
+    let upstream = spawn_upstream(version_echo).await;
+
+    let gw = spawn_server(&forward_yaml(upstream, "")).await;
+    assert_eq!(send(gw, get("/")).await.2, "HTTP/1.1");
+
+    let gw = spawn_server(&forward_yaml(upstream, "  http_version: 2")).await;
+    let (status, _, body) = send(gw, get("/")).await;
+    assert_eq!(status, http::StatusCode::OK);
+    assert_eq!(body, "HTTP/2.0");
+}
+
+#[tokio::test]
+async fn http2_upstreams_over_tls() {
+    let pki = TestPki::new("h2");
+    let mut config = pki.server_config(false, rustls::DEFAULT_VERSIONS);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let upstream = spawn_tls_upstream(config, version_echo).await;
+    let tls = format!("    ca_file: \"{}\"", pki.path("ca.pem"));
+
+    let gw = spawn_server(&https_yaml(upstream, "localhost", &tls)).await;
+    assert_eq!(send(gw, get("/")).await.2, "HTTP/1.1");
+
+    let gw = spawn_server(&format!("{}  http_version: 2\n", https_yaml(upstream, "localhost", &tls))).await;
+    let (status, _, body) = send(gw, get("/")).await;
+    assert_eq!(status, http::StatusCode::OK);
+    assert_eq!(body, "HTTP/2.0");
+}
+
+#[tokio::test]
+async fn trailers_are_forwarded_both_ways() {
+    use http_body_util::BodyExt;
+
+    // answers with the request's `x-sum` trailer in its own trailers, as gRPC servers report status
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let svc = hyper::service::service_fn(|req: Request<hyper::body::Incoming>| async move {
+            let collected = req.into_body().collect().await.unwrap();
+            let sum = collected.trailers().and_then(|t| t.get("x-sum")).cloned()
+                .unwrap_or(http::HeaderValue::from_static("missing"));
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+            trailers.insert("x-sum", sum);
+            let body = Full::new(collected.to_bytes()).with_trailers(std::future::ready(Some(Ok(trailers))));
+            Ok::<_, std::convert::Infallible>(hyper::Response::new(body))
+        });
+        let _ = hyper::server::conn::http2::Builder::new(hyper_util::rt::TokioExecutor::new())
+            .serve_connection(hyper_util::rt::TokioIo::new(stream), svc)
+            .await;
+    });
+    let gw = spawn_server(&forward_yaml(upstream, "  http_version: 2")).await;
+
+    let tcp = tokio::net::TcpStream::connect(gw).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http2::handshake(
+        hyper_util::rt::TokioExecutor::new(),
+        hyper_util::rt::TokioIo::new(tcp),
+    ).await.unwrap();
+    tokio::spawn(conn);
+
+    let mut trailers = http::HeaderMap::new();
+    trailers.insert("x-sum", http::HeaderValue::from_static("42"));
+    let body = Full::new(Bytes::from_static(b"payload")).with_trailers(std::future::ready(Some(Ok(trailers))));
+    let req = Request::builder()
+        .method(http::Method::POST)
+        .uri(format!("http://{gw}/svc.Method"))
+        .header(http::header::TE, "trailers")
+        .body(body)
+        .unwrap();
+    let resp = sender.send_request(req).await.unwrap();
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    let collected = resp.into_body().collect().await.unwrap();
+    let trailers = collected.trailers().cloned().expect("response trailers");
+    assert_eq!(collected.to_bytes(), "payload");
+    assert_eq!(trailers["grpc-status"], "0");
+    assert_eq!(trailers["x-sum"], "42");
+}
+
+#[test]
+fn http_version_is_parsed() {
+    use crate::config::http_version::HttpVersion;
+
+    for (raw, expected) in [("1.1", HttpVersion::V1_1), ("\"1.1\"", HttpVersion::V1_1), ("2", HttpVersion::V2), ("\"2\"", HttpVersion::V2)] {
+        assert_eq!(serde_yaml::from_str::<HttpVersion>(raw).unwrap(), expected, "{raw}");
+    }
+    let err = serde_yaml::from_str::<HttpVersion>("3").unwrap_err().to_string();
+    assert!(err.contains("unknown HTTP version `3`"), "{err}");
+}
+
+fn pool_yaml(upstreams: &[(std::net::SocketAddr, u32)], balance: &str) -> String {
+    let targets: String = upstreams.iter()
+        .map(|(addr, weight)| format!("    - {{ scheme: http, host: \"{}\", port: {}, weight: {weight} }}\n", addr.ip(), addr.port()))
+        .collect();
+    format!(r#"
+bind: "127.0.0.1:0"
+service:
+  handler: forward
+  target:
+{targets}  balance: {balance}
+"#)
+}
+
+/// Upstream that answers with its name.
+async fn spawn_named_upstream(name: &'static str) -> std::net::SocketAddr {
+    spawn_upstream(move |_| hyper::Response::new(Full::from(name))).await
+}
+
+async fn names(gw: std::net::SocketAddr, requests: usize, header: Option<(&str, String)>) -> Vec<String> {
+    let mut out = Vec::new();
+    for _ in 0..requests {
+        let mut req = get("/");
+        if let Some((name, value)) = &header {
+            req.headers_mut().insert(http::HeaderName::from_bytes(name.as_bytes()).unwrap(), value.parse().unwrap());
+        }
+        let (status, _, body) = send(gw, req).await;
+        assert_eq!(status, http::StatusCode::OK);
+        out.push(String::from_utf8_lossy(&body).into_owned());
+    }
+    out
+}
+
+fn count(names: &[String], name: &str) -> usize {
+    names.iter().filter(|n| *n == name).count()
+}
+
+#[tokio::test]
+async fn round_robin_follows_weights() {
+    let (a, b, c) = (spawn_named_upstream("a").await, spawn_named_upstream("b").await, spawn_named_upstream("c").await);
+    let gw = spawn_server(&pool_yaml(&[(a, 2), (b, 1), (c, 1)], "round_robin")).await;
+
+    let seen = names(gw, 8, None).await;
+    assert_eq!((count(&seen, "a"), count(&seen, "b"), count(&seen, "c")), (4, 2, 2), "{seen:?}");
+    // smooth: the heavier target's turns are spread out, not taken in a row
+    assert_eq!(seen[..4], ["a", "b", "c", "a"]);
+}
+
+#[tokio::test]
+async fn least_connections_avoids_busy_targets() {
+    use std::sync::Arc;
+
+    // both answer `/hold` only once released
+    let release = Arc::new(tokio::sync::Notify::new());
+    let mut addrs = Vec::new();
+    for name in ["a", "b"] {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        addrs.push((listener.local_addr().unwrap(), 1));
+        let release = release.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let release = release.clone();
+                let svc = hyper::service::service_fn(move |req: Request<hyper::body::Incoming>| {
+                    let release = release.clone();
+                    async move {
+                        if req.uri().path() == "/hold" {
+                            release.notified().await;
+                        }
+                        Ok::<_, std::convert::Infallible>(hyper::Response::new(Full::<Bytes>::from(name)))
+                    }
+                });
+                tokio::spawn(hyper::server::conn::http1::Builder::new()
+                    .serve_connection(hyper_util::rt::TokioIo::new(stream), svc));
+            }
+        });
+    }
+    let gw = spawn_server(&pool_yaml(&addrs, "least_connections")).await;
+
+    let held = tokio::spawn(async move { send(gw, get("/hold")).await.2 });
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let seen = names(gw, 4, None).await;
+    release.notify_one();
+    let held = String::from_utf8_lossy(&held.await.unwrap()).into_owned();
+
+    assert!(seen.iter().all(|n| *n != held), "{held} was busy, yet got {seen:?}");
+}
+
+#[tokio::test]
+async fn random_two_choices_uses_every_target() {
+    let (a, b) = (spawn_named_upstream("a").await, spawn_named_upstream("b").await);
+    let gw = spawn_server(&pool_yaml(&[(a, 1), (b, 1)], "random_two_choices")).await;
+
+    let seen = names(gw, 40, None).await;
+    assert!(count(&seen, "a") > 0 && count(&seen, "b") > 0, "{seen:?}");
+}
+
+#[tokio::test]
+async fn hash_keeps_keys_on_one_target() {
+    let (a, b, c) = (spawn_named_upstream("a").await, spawn_named_upstream("b").await, spawn_named_upstream("c").await);
+    let gw = spawn_server(&pool_yaml(&[(a, 1), (b, 1), (c, 1)], "{ hash: \"${header.x-user-id}\" }")).await;
+
+    let mut used = std::collections::HashSet::new();
+    for user in 0..30 {
+        let seen = names(gw, 3, Some(("x-user-id", format!("user-{user}")))).await;
+        assert!(seen.iter().all(|n| *n == seen[0]), "user-{user} moved: {seen:?}");
+        used.insert(seen[0].clone());
+    }
+    assert_eq!(used.len(), 3, "{used:?}");
+
+    // without a key requests still go somewhere
+    assert_eq!(names(gw, 3, None).await.len(), 3);
+}
+
+#[test]
+fn pools_are_validated() {
+    let up: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    let err = built_err(&pool_yaml(&[(up, 0)], "round_robin"));
+    assert!(err.contains("`forward.target.weight` must be between 1 and 1000"), "{err}");
+
+    let err = built_err(&pool_yaml(&[(up, 1)], "{ hash: \"${header.x|nope}\" }"));
+    assert!(err.contains("`forward.balance.hash`"), "{err}");
+
+    let err = built_err("bind: \"127.0.0.1:0\"\nservice:\n  handler: forward\n  target: []\n");
+    assert!(err.contains("`forward.target` cannot be empty"), "{err}");
+}
+
+/// Upstream answering with its name, or 503 while `failing` is set.
+async fn spawn_flaky_upstream(name: &'static str, failing: std::sync::Arc<std::sync::atomic::AtomicBool>) -> std::net::SocketAddr {
+    spawn_upstream(move |_| {
+        let mut resp = hyper::Response::new(Full::from(name));
+        if failing.load(Ordering::SeqCst) {
+            *resp.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+        }
+        resp
+    }).await
+}
+
+async fn statuses_and_names(gw: std::net::SocketAddr, requests: usize) -> Vec<(u16, String)> {
+    let mut out = Vec::new();
+    for _ in 0..requests {
+        let (status, _, body) = send(gw, get("/")).await;
+        out.push((status.as_u16(), String::from_utf8_lossy(&body).into_owned()));
+    }
+    out
+}
+
+#[tokio::test]
+async fn active_health_checks_take_targets_out_and_back() {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    let failing = Arc::new(AtomicBool::new(false));
+    let a = spawn_named_upstream("a").await;
+    let b = spawn_flaky_upstream("b", failing.clone()).await;
+    let health = "\n  health:\n    active: { path: /healthz, interval_ms: 50, healthy_threshold: 2, unhealthy_threshold: 2 }";
+    let gw = spawn_server(&(pool_yaml(&[(a, 1), (b, 1)], "round_robin") + health)).await;
+
+    failing.store(true, Ordering::SeqCst);
+    tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+    let seen = statuses_and_names(gw, 6).await;
+    assert!(seen.iter().all(|(status, name)| *status == 200 && name == "a"), "{seen:?}");
+
+    failing.store(false, Ordering::SeqCst);
+    tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+    let seen = statuses_and_names(gw, 6).await;
+    assert!(seen.iter().any(|(_, name)| name == "b"), "{seen:?}");
+}
+
+#[tokio::test]
+async fn passive_health_checks_eject_failing_targets() {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    let failing = Arc::new(AtomicBool::new(true));
+    let a = spawn_named_upstream("a").await;
+    let b = spawn_flaky_upstream("b", failing.clone()).await;
+    let health = "\n  health:\n    passive: { max_failures: 2, eject_ms: 400 }";
+    let gw = spawn_server(&(pool_yaml(&[(a, 1), (b, 1)], "round_robin") + health)).await;
+
+    // b fails twice while taking its turns, then is left out
+    let seen = statuses_and_names(gw, 4).await;
+    assert_eq!(seen.iter().filter(|(status, _)| *status == 503).count(), 2, "{seen:?}");
+    let seen = statuses_and_names(gw, 6).await;
+    assert!(seen.iter().all(|(status, name)| *status == 200 && name == "a"), "{seen:?}");
+
+    // and gets traffic again once the ejection is over
+    failing.store(false, Ordering::SeqCst);
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    let seen = statuses_and_names(gw, 4).await;
+    assert!(seen.iter().any(|(_, name)| name == "b"), "{seen:?}");
+}
+
+#[tokio::test]
+async fn requests_still_go_out_when_every_target_is_down() {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    let failing = Arc::new(AtomicBool::new(true));
+    let a = spawn_flaky_upstream("a", failing.clone()).await;
+    let b = spawn_flaky_upstream("b", failing.clone()).await;
+    let health = "\n  health:\n    active: { interval_ms: 50, unhealthy_threshold: 1 }";
+    let gw = spawn_server(&(pool_yaml(&[(a, 1), (b, 1)], "round_robin") + health)).await;
+
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    let seen = statuses_and_names(gw, 4).await;
+    assert!(seen.iter().all(|(status, _)| *status == 503), "{seen:?}");
+}
+
+/// Upstream counting the requests it gets.
+async fn spawn_probed_upstream() -> (std::net::SocketAddr, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    let probes = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let count = probes.clone();
+    let addr = spawn_upstream(move |_| {
+        count.fetch_add(1, Ordering::SeqCst);
+        hyper::Response::new(Full::default())
+    }).await;
+    (addr, probes)
+}
+
+#[tokio::test]
+async fn health_checks_run_only_while_served() {
+    use std::time::Duration;
+
+    let health = "\n  health:\n    active: { interval_ms: 20 }";
+    let config = |up| serde_yaml::from_str(&(pool_yaml(&[(up, 1)], "round_robin") + health)).unwrap();
+    let (a, probed_a) = spawn_probed_upstream().await;
+    let (b, probed_b) = spawn_probed_upstream().await;
+
+    // a config only built, as for a reload check, probes nothing
+    let built = crate::build::build_http_server(config(a)).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(probed_a.load(Ordering::SeqCst), 0);
+
+    let (_, handle) = crate::test_util::spawn_with_handle(config(a), Duration::from_secs(1)).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(probed_a.load(Ordering::SeqCst) > 0);
+
+    // swapped out: only the new service is probed
+    handle.reload(crate::build::build_http_server(config(b)).unwrap());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let before = probed_a.load(Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(probed_a.load(Ordering::SeqCst), before);
+    assert!(probed_b.load(Ordering::SeqCst) > 0);
+
+    // and nothing once the listener is shut down
+    handle.shutdown().await;
+    let before = probed_b.load(Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(probed_b.load(Ordering::SeqCst), before);
+    drop(built);
+}
+
+#[test]
+fn health_checks_are_validated() {
+    let up: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    for (health, expected) in [
+        ("active: { path: healthz }", "`health.active.path` must start with '/'"),
+        ("active: { interval_ms: 0 }", "`health.active` durations must be positive"),
+        ("active: { unhealthy_threshold: 0 }", "`health.active` thresholds must be positive"),
+        ("active: { expect_status: [200, 700] }", "invalid status 700"),
+        ("passive: { eject_ms: 0 }", "`health.passive` settings must be positive"),
+    ] {
+        let err = built_err(&format!("{}\n  health:\n    {health}", pool_yaml(&[(up, 1)], "round_robin")));
+        assert!(err.contains(expected), "{health}: {err}");
+    }
+}
+
+/// Upstream answering 503 to every request, counting them.
+async fn spawn_unavailable_upstream() -> (std::net::SocketAddr, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    let hits = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = hits.clone();
+    let addr = spawn_upstream(move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        let mut resp = hyper::Response::new(Full::from("down"));
+        *resp.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+        resp
+    }).await;
+    (addr, hits)
+}
+
+#[tokio::test]
+async fn connect_errors_are_retried_on_another_target() {
+    let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let a = spawn_named_upstream("a").await;
+
+    let gw = spawn_server(&pool_yaml(&[(closed, 1), (a, 1)], "round_robin")).await;
+    let seen = statuses_and_names(gw, 4).await;
+    assert!(seen.iter().any(|(status, _)| *status == 502), "{seen:?}");
+
+    let gw = spawn_server(&(pool_yaml(&[(closed, 1), (a, 1)], "round_robin") + "\n  retry: {}")).await;
+    let seen = statuses_and_names(gw, 4).await;
+    assert!(seen.iter().all(|(status, name)| *status == 200 && name == "a"), "{seen:?}");
+}
+
+#[tokio::test]
+async fn listed_statuses_are_retried_for_idempotent_requests_only() {
+    let (down, hits) = spawn_unavailable_upstream().await;
+    let a = spawn_named_upstream("a").await;
+    let gw = spawn_server(&(pool_yaml(&[(down, 1), (a, 1)], "round_robin") + "\n  retry: { on: [connect_error, 503] }")).await;
+
+    let seen = statuses_and_names(gw, 4).await;
+    assert!(seen.iter().all(|(status, name)| *status == 200 && name == "a"), "{seen:?}");
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+    // a POST may have done its work already, and its body is gone
+    let mut statuses = Vec::new();
+    for _ in 0..4 {
+        let req: Request<Full<Bytes>> = Request::post("/").body(Full::from("payload")).unwrap();
+        statuses.push(send(gw, req).await.0.as_u16());
+    }
+    assert_eq!(statuses.iter().filter(|s| **s == 503).count(), 2, "{statuses:?}");
+}
+
+#[tokio::test]
+async fn retries_stop_at_attempts_and_budget() {
+    let (down, hits) = spawn_unavailable_upstream().await;
+    let gw = spawn_server(&forward_yaml(down, "  retry: { attempts: 3, on: [503], backoff_ms: 50 }")).await;
+    let started = std::time::Instant::now();
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+    // 50 ms, then 100 ms
+    assert!(started.elapsed() >= std::time::Duration::from_millis(150));
+
+    let (down, hits) = spawn_unavailable_upstream().await;
+    let gw = spawn_server(&forward_yaml(down, "  retry: { attempts: 5, on: [503], backoff_ms: 100, budget_ms: 250 }")).await;
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::SERVICE_UNAVAILABLE);
+    // the second retry would start 300 ms in
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn retry_policies_are_validated() {
+    let up: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    for (retry, expected) in [
+        ("{ attempts: 0 }", "`forward.retry.attempts` must be positive"),
+        ("{ backoff_ms: 500, max_backoff_ms: 100 }", "`forward.retry.max_backoff_ms` cannot be below `backoff_ms`"),
+        ("{ budget_ms: 0 }", "`forward.retry.budget_ms` must be positive"),
+        ("{ on: [reset, 99] }", "`forward.retry.on` has invalid status 99"),
+    ] {
+        let err = built_err(&forward_yaml(up, &format!("  retry: {retry}")));
+        assert!(err.contains(expected), "{retry}: {err}");
+    }
+}
+
+#[tokio::test]
+async fn hop_by_hop_request_headers_are_stripped() {
+    let upstream = spawn_echo_upstream().await;
+    let gw = spawn_server(&forward_yaml(upstream, "  via: oxidase")).await;
+
+    let req: Request<Full<Bytes>> = Request::builder()
+        .uri("/")
+        .header(http::header::HOST, "gateway.test")
+        .header(http::header::CONNECTION, "keep-alive, x-hop")
+        .header("keep-alive", "timeout=5")
+        .header("x-hop", "1")
+        .header("x-end", "1")
+        .header(http::header::PROXY_AUTHORIZATION, "Basic Zm9vOmJhcg==")
+        .header("proxy-connection", "keep-alive")
+        .header("proxy-trace-id", "42")
+        .header(http::header::TE, "gzip, trailers;q=0.5")
+        .header(http::header::VIA, "1.0 edge")
+        .body(Full::default())
+        .unwrap();
+    let (_, _, body) = send(gw, req).await;
+    let body = String::from_utf8_lossy(&body);
+    for name in ["keep-alive", "x-hop", "proxy-authorization", "proxy-connection"] {
+        assert_eq!(header_line(&body, name), None, "{name} in {body}");
+    }
+    assert_eq!(header_line(&body, "x-end"), Some("1"));
+    // only those three `Proxy-` headers are the proxy's
+    assert_eq!(header_line(&body, "proxy-trace-id"), Some("42"));
+    assert_eq!(header_line(&body, "te"), Some("trailers"));
+    assert_eq!(header_line(&body, "via"), Some("1.0 edge, 1.1 oxidase"));
+}
+
+async fn spawn_chatty_upstream() -> std::net::SocketAddr {
+    spawn_upstream(|_| {
+        let mut resp = hyper::Response::new(Full::from("hi"));
+        let headers = resp.headers_mut();
+        headers.insert(http::header::CONNECTION, http::HeaderValue::from_static("x-hop"));
+        headers.insert("x-hop", http::HeaderValue::from_static("1"));
+        headers.insert("keep-alive", http::HeaderValue::from_static("timeout=5"));
+        headers.insert(http::header::PROXY_AUTHENTICATE, http::HeaderValue::from_static("Basic"));
+        headers.insert("x-powered-by", http::HeaderValue::from_static("php"));
+        headers.insert(http::header::CONTENT_TYPE, http::HeaderValue::from_static("text/plain"));
+        resp
+    }).await
+}
+
+#[tokio::test]
+async fn hop_by_hop_response_headers_are_stripped() {
+    let upstream = spawn_chatty_upstream().await;
+    let gw = spawn_server(&forward_yaml(upstream, "  via: oxidase\n  response_headers: { deny: [X-Powered-By] }")).await;
+
+    let (status, headers, body) = send(gw, get("/")).await;
+    assert_eq!((status, &body[..]), (http::StatusCode::OK, &b"hi"[..]));
+    for name in ["x-hop", "keep-alive", "proxy-authenticate", "x-powered-by"] {
+        assert!(!headers.contains_key(name), "{name} in {headers:?}");
+    }
+    assert_eq!(headers[http::header::CONTENT_TYPE], "text/plain");
+    assert_eq!(headers[http::header::VIA], "1.1 oxidase");
+}
+
+#[tokio::test]
+async fn response_headers_can_be_allow_listed() {
+    let upstream = spawn_chatty_upstream().await;
+    let gw = spawn_server(&forward_yaml(upstream, "  response_headers: { allow: [content-type, content-length, x-hop] }")).await;
+
+    let (_, headers, body) = send(gw, get("/")).await;
+    assert_eq!(&body[..], b"hi");
+    assert_eq!(headers[http::header::CONTENT_TYPE], "text/plain");
+    // listed, but still only meant for the connection it came over
+    for name in ["x-hop", "x-powered-by", "via"] {
+        assert!(!headers.contains_key(name), "{name} in {headers:?}");
+    }
+}
+
+#[test]
+fn header_settings_are_validated() {
+    let up: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    for (extra, expected) in [
+        ("  via: \"my proxy\"", "`forward.via` must be a single token, got `my proxy`"),
+        ("  response_headers: { deny: [\"bad header\"] }", "`forward.response_headers` has invalid header name `bad header`"),
+    ] {
+        let err = built_err(&forward_yaml(up, extra));
+        assert!(err.contains(expected), "{extra}: {err}");
+    }
+}
+
+/// Upstream that switches to an echo protocol when asked to, as WebSocket servers do.
+async fn spawn_upgrading_upstream() -> std::net::SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let svc = hyper::service::service_fn(|mut req: Request<hyper::body::Incoming>| async move {
+                let mut resp = hyper::Response::new(Full::<Bytes>::default());
+                if req.headers().get(http::header::UPGRADE).is_some_and(|p| p == "echo") {
+                    let key = req.headers().get("sec-websocket-key").cloned();
+                    let upgraded = hyper::upgrade::on(&mut req);
+                    tokio::spawn(async move {
+                        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+                        let mut io = hyper_util::rt::TokioIo::new(upgraded.await.unwrap());
+                        let mut buf = [0u8; 1024];
+                        while let Ok(n @ 1..) = io.read(&mut buf).await {
+                            io.write_all(&buf[..n]).await.unwrap();
+                        }
+                    });
+                    *resp.status_mut() = http::StatusCode::SWITCHING_PROTOCOLS;
+                    resp.headers_mut().insert(http::header::CONNECTION, http::HeaderValue::from_static("upgrade"));
+                    resp.headers_mut().insert(http::header::UPGRADE, http::HeaderValue::from_static("echo"));
+                    if let Some(key) = key {
+                        resp.headers_mut().insert("sec-websocket-accept", key);
+                    }
+                }
+                Ok::<_, std::convert::Infallible>(resp)
+            });
+            tokio::spawn(hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), svc)
+                .with_upgrades());
+        }
+    });
+    addr
+}
+
+async fn upgrade_through(gw: std::net::SocketAddr) -> (tokio::net::TcpStream, String) {
+    use tokio::io::AsyncWriteExt;
+
+    let mut tcp = tokio::net::TcpStream::connect(gw).await.unwrap();
+    tcp.write_all(b"GET /ws HTTP/1.1\r\nHost: gateway.test\r\nConnection: Upgrade\r\nUpgrade: echo\r\nSec-WebSocket-Key: abc\r\n\r\n").await.unwrap();
+    let head = read_until(&mut tcp, "\r\n\r\n").await;
+    (tcp, head)
+}
+
+#[tokio::test]
+async fn upgraded_connections_are_joined() {
+    use tokio::io::AsyncWriteExt;
+
+    let upstream = spawn_upgrading_upstream().await;
+    let gw = spawn_server(&forward_yaml(upstream, "")).await;
+
+    let (mut tcp, head) = upgrade_through(gw).await;
+    assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+    let head = head.to_ascii_lowercase();
+    assert!(head.contains("connection: upgrade\r\n") && head.contains("upgrade: echo\r\n"), "{head}");
+    assert!(head.contains("sec-websocket-accept: abc\r\n"), "{head}");
+
+    for msg in ["ping", "pong"] {
+        tcp.write_all(msg.as_bytes()).await.unwrap();
+        read_until(&mut tcp, msg).await;
+    }
+
+    // without `Connection: upgrade` it is a plain request
+    let mut req = get("/ws");
+    req.headers_mut().insert(http::header::UPGRADE, http::HeaderValue::from_static("echo"));
+    assert_eq!(send(gw, req).await.0, http::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn idle_upgraded_connections_are_closed() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let upstream = spawn_upgrading_upstream().await;
+    let gw = spawn_server(&forward_yaml(upstream, "  upgrade_idle_ms: 200")).await;
+
+    let (mut tcp, head) = upgrade_through(gw).await;
+    assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    tcp.write_all(b"still here").await.unwrap();
+    read_until(&mut tcp, "still here").await;
+
+    let started = std::time::Instant::now();
+    let mut buf = [0u8; 16];
+    let n = tokio::time::timeout(std::time::Duration::from_secs(5), tcp.read(&mut buf)).await.unwrap().unwrap_or(0);
+    assert_eq!(n, 0);
+    assert!(started.elapsed() >= std::time::Duration::from_millis(150));
+}
+
+#[tokio::test]
+async fn upgraded_connections_count_until_they_end() {
+    use crate::http_server::DrainReport;
+    use std::time::Duration;
+
+    let upstream = spawn_upgrading_upstream().await;
+    let yaml = format!("{}limits: {{ max_connections: 1 }}\n", forward_yaml(upstream, ""));
+    let (gw, handle) = crate::test_util::spawn_with_handle(serde_yaml::from_str(&yaml).unwrap(), Duration::from_millis(200)).await;
+
+    let (_tcp, head) = upgrade_through(gw).await;
+    assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+    // the upgraded stream still holds the only connection allowed
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::SERVICE_UNAVAILABLE);
+    // and is waited for, then aborted, on shutdown
+    assert_eq!(handle.shutdown().await, DrainReport { drained: 0, aborted: 1 });
+}
+
+fn dynamic_yaml(host: &str, port: u16, dynamic: &str) -> String {
+    format!(r#"
+bind: "127.0.0.1:0"
+service:
+  handler: router
+  rules:
+    - ops:
+        - set_host: "{host}"
+        - set_port: {port}
+  next:
+    handler: forward
+    dynamic_target: {dynamic}
+"#)
+}
+
+#[tokio::test]
+async fn dynamic_targets_follow_the_rewritten_uri() {
+    let upstream = spawn_echo_upstream().await;
+    let gw = spawn_server(&dynamic_yaml("127.0.0.1", upstream.port(), &format!("{{ allow: [10.0.0.0/8, 127.0.0.0/8], ports: [{}] }}", upstream.port()))).await;
+
+    let (status, _, body) = send(gw, get("/some/path?q=1")).await;
+    assert_eq!(status, http::StatusCode::OK);
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.starts_with("GET /some/path?q=1"), "{body}");
+    // `set_host` set the Host header too
+    assert_eq!(header_line(&body, "host"), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn dynamic_targets_outside_the_allow_list_are_forbidden() {
+    let upstream = spawn_echo_upstream().await;
+    for (host, dynamic) in [
+        // names are matched as names, never resolved into a network
+        ("localhost", "{ allow: [127.0.0.0/8] }"),
+        ("127.0.0.1", "{ allow: [\"*.example.com\", 10.0.0.1] }"),
+        // only 80 and 443 unless `ports` says otherwise
+        ("127.0.0.1", "{ allow: [127.0.0.1] }"),
+    ] {
+        let gw = spawn_server(&dynamic_yaml(host, upstream.port(), dynamic)).await;
+        let (status, _, body) = send(gw, get("/")).await;
+        assert_eq!(status, http::StatusCode::FORBIDDEN, "{host} with {dynamic}");
+        assert!(String::from_utf8_lossy(&body).contains("is not allowed"), "{body:?}");
+    }
+
+    // without a Router, the client's own Host names the upstream
+    let gw = spawn_server(r#"
+bind: "127.0.0.1:0"
+service:
+  handler: forward
+  dynamic_target: { allow: [localhost, "*.internal"] }
+"#).await;
+    assert_eq!(send(gw, get("/")).await.0, http::StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn dynamic_upstreams_are_capped() {
+    use std::sync::Arc;
+    use super::DynamicUpstreams;
+
+    let config: crate::config::forward::ForwardService =
+        serde_yaml::from_str("dynamic_target: { allow: [127.0.0.1], ports: [1, 2, 3], max_upstreams: 2 }").unwrap();
+    let upstreams = DynamicUpstreams::new(config.dynamic_target.as_ref().unwrap(), None, false).unwrap();
+    let upstream = |port: u16| {
+        let req = Request::get(format!("http://127.0.0.1:{port}/")).body(crate::handler::empty()).unwrap();
+        upstreams.upstream(&req, &config).unwrap_or_else(|_| panic!("port {port} is allowed"))
+    };
+
+    let (one, two) = (upstream(1), upstream(2));
+    assert!(Arc::ptr_eq(&one, &upstream(1)));
+    // makes room by dropping 2, used longest ago
+    upstream(3);
+    assert!(Arc::ptr_eq(&one, &upstream(1)));
+    assert!(!Arc::ptr_eq(&two, &upstream(2)));
+}
+
+#[test]
+fn allowed_hosts_match_names_subdomains_and_networks() {
+    use crate::config::forward::AllowedHost;
+
+    let allowed = |entry: &str, host: &str| entry.parse::<AllowedHost>().unwrap().matches(host);
+    assert!(allowed("api.example.com", "API.example.com."));
+    assert!(!allowed("api.example.com", "example.com"));
+    assert!(allowed("*.example.com", "a.b.example.com"));
+    assert!(!allowed("*.example.com", "example.com"));
+    assert!(!allowed("*.example.com", "badexample.com"));
+    assert!(allowed("10.0.0.0/8", "10.1.2.3"));
+    assert!(allowed("::1", "[::1]"));
+    assert!(!allowed("10.0.0.0/8", "ten.example"));
+    assert!("exa mple.com".parse::<AllowedHost>().is_err());
+}
+
+#[test]
+fn dynamic_targets_are_validated() {
+    let up: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    let dynamic_only = |extra: &str| format!("bind: \"127.0.0.1:0\"\nservice:\n  handler: forward\n{extra}\n");
+    for (yaml, expected) in [
+        (dynamic_only(""), "`forward.target` cannot be empty"),
+        (forward_yaml(up, "  dynamic_target: { allow: [localhost] }"), "cannot both be set"),
+        (dynamic_only("  dynamic_target: { allow: [] }"), "`forward.dynamic_target.allow` cannot be empty"),
+        (dynamic_only("  dynamic_target: { allow: [10.0.0.0/33] }"), "`forward.dynamic_target.allow`: invalid CIDR"),
+        (dynamic_only("  dynamic_target: { allow: [\"bad host\"] }"), "invalid host `bad host`"),
+        (dynamic_only("  dynamic_target: { allow: [localhost], ports: [] }"), "`forward.dynamic_target.ports` must list valid ports"),
+        (dynamic_only("  dynamic_target: { allow: [localhost], max_upstreams: 0 }"), "`forward.dynamic_target.max_upstreams` must be positive"),
+        (dynamic_only("  dynamic_target: { allow: [localhost] }\n  health: { active: {} }"), "`forward.health.active` needs fixed targets"),
+    ] {
+        let err = built_err(&yaml);
+        assert!(err.contains(expected), "{yaml}: {err}");
+    }
+}
+
+/// Upstream behind `/app` that redirects and sets cookies as if it were public.
+async fn spawn_redirecting_upstream() -> std::net::SocketAddr {
+    spawn_upstream(|req| {
+        // URLs are built from the Host it was sent, as most applications do
+        let own = format!("http://{}", req.headers()[http::header::HOST].to_str().unwrap());
+        let mut resp = hyper::Response::new(Full::from("moved"));
+        *resp.status_mut() = http::StatusCode::FOUND;
+        let headers = resp.headers_mut();
+        let mut set = |name: &'static str, value: String| {
+            headers.append(name, http::HeaderValue::from_str(&value).unwrap());
+        };
+        set("location", format!("{own}/app/login?next=%2Fapp%2F"));
+        set("content-location", "/app/page".into());
+        set("refresh", format!("3; url='{own}/app'"));
+        set("set-cookie", "sid=1; Domain=127.0.0.1; Path=/app/account; HttpOnly".into());
+        set("set-cookie", "theme=dark; Domain=.elsewhere.test; Path=/other".into());
+        resp
+    }).await
+}
+
+#[tokio::test]
+async fn redirects_and_cookies_point_back_at_the_public_host() {
+    let upstream = spawn_redirecting_upstream().await;
+    let extra = "    path_prefix: /app\n  pass_host: target\n  rewrite_redirects: true\n  rewrite_cookies: true";
+    let gw = spawn_server(&forward_yaml(upstream, extra)).await;
+
+    let (status, headers, _) = send(gw, get("/login")).await;
+    assert_eq!(status, http::StatusCode::FOUND);
+    assert_eq!(headers["location"], "http://gateway.test/login?next=%2Fapp%2F");
+    assert_eq!(headers["content-location"], "/page");
+    assert_eq!(headers["refresh"], "3; url='http://gateway.test/'");
+    let cookies: Vec<_> = headers.get_all("set-cookie").iter().map(|c| c.to_str().unwrap()).collect();
+    assert_eq!(cookies, ["sid=1; Domain=gateway.test; Path=/account; HttpOnly", "theme=dark; Domain=.elsewhere.test; Path=/other"]);
+
+    // left alone unless asked for
+    let gw = spawn_server(&forward_yaml(upstream, "    path_prefix: /app\n  pass_host: target")).await;
+    let (_, headers, _) = send(gw, get("/login")).await;
+    assert_eq!(headers["location"], format!("http://{upstream}/app/login?next=%2Fapp%2F"));
+    assert_eq!(headers["content-location"], "/app/page");
+}
+
+#[tokio::test]
+async fn error_details_are_hidden_unless_debugging() {
+    let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+
+    let gw = spawn_server(&forward_yaml(closed, "")).await;
+    let (status, _, body) = send(gw, get("/")).await;
+    assert_eq!((status, &body[..]), (http::StatusCode::BAD_GATEWAY, &b"Bad Gateway"[..]));
+
+    let gw = spawn_server(&forward_yaml(closed, "  errors: { debug: true }")).await;
+    let (_, _, body) = send(gw, get("/")).await;
+    assert!(String::from_utf8_lossy(&body).contains("upstream request failed"), "{body:?}");
+}
+
+#[tokio::test]
+async fn error_pages_replace_failures_and_upstream_errors() {
+    let dir = std::env::temp_dir().join(format!("oxidase-error-pages-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("busy.html"), "<h1>busy</h1>").unwrap();
+    let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let (down, _) = spawn_unavailable_upstream().await;
+    let pages = format!(r#"  errors:
+    pages:
+      - on: connect_error
+        body: "down for ${{path}}"
+        headers: {{ content-type: text/plain, retry-after: "30" }}
+      - on: [503, timeout]
+        status: 500
+        file: "{}"
+      - on: 404
+        use:
+          handler: router
+          rules:
+            - ops:
+                - respond: {{ status: 200, body: from the router }}"#, dir.join("busy.html").display());
+
+    let gw = spawn_server(&forward_yaml(closed, &pages)).await;
+    let (status, headers, body) = send(gw, get("/where")).await;
+    assert_eq!((status, &body[..]), (http::StatusCode::BAD_GATEWAY, &b"down for /where"[..]));
+    assert_eq!(headers["retry-after"], "30");
+
+    let gw = spawn_server(&forward_yaml(down, &pages)).await;
+    let (status, headers, body) = send(gw, get("/")).await;
+    assert_eq!((status, &body[..]), (http::StatusCode::INTERNAL_SERVER_ERROR, &b"<h1>busy</h1>"[..]));
+    assert_eq!(headers[http::header::CONTENT_TYPE], "text/html");
+
+    // the page's body, the status it stands in for
+    let missing = spawn_upstream(|_| {
+        let mut resp = hyper::Response::new(Full::from("no such page at 10.0.0.7"));
+        *resp.status_mut() = http::StatusCode::NOT_FOUND;
+        resp
+    }).await;
+    let gw = spawn_server(&forward_yaml(missing, &pages)).await;
+    let (status, _, body) = send(gw, get("/")).await;
+    assert_eq!((status, &body[..]), (http::StatusCode::NOT_FOUND, &b"from the router"[..]));
+
+    // anything else passes
+    let gw = spawn_server(&forward_yaml(spawn_named_upstream("ok").await, &pages)).await;
+    assert_eq!(send(gw, get("/")).await.2, "ok");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn tls_failures_have_their_own_error_page() {
+    let pki = TestPki::new("error-pages");
+    let upstream = spawn_tls_upstream(pki.server_config(false, rustls::DEFAULT_VERSIONS), echo).await;
+    // the certificate is for localhost, not the IP
+    let extra = format!("    ca_file: \"{}\"\n  errors:\n    pages: [{{ on: tls_error, status: 503, body: bad certificate }}]", pki.path("ca.pem"));
+    let gw = spawn_server(&https_yaml(upstream, "127.0.0.1", &extra)).await;
+    let (status, _, body) = send(gw, get("/")).await;
+    assert_eq!((status, &body[..]), (http::StatusCode::SERVICE_UNAVAILABLE, &b"bad certificate"[..]));
+}
+
+#[test]
+fn error_pages_are_validated() {
+    let up: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    for (page, expected) in [
+        ("{ on: [], body: x }", "`errors.pages.on` cannot be empty"),
+        ("{ on: 700, body: x }", "`errors.pages` has invalid status 700"),
+        ("{ on: 502, status: 99, body: x }", "`errors.pages` has invalid status 99"),
+        ("{ on: 502 }", "needs exactly one of `file`, `body` and `use`"),
+        ("{ on: 502, body: x, file: x.html }", "needs exactly one of `file`, `body` and `use`"),
+        ("{ on: 502, file: /nonexistent/oxidase.html }", "`errors.pages.file` /nonexistent/oxidase.html"),
+    ] {
+        let err = built_err(&forward_yaml(up, &format!("  errors:\n    pages: [{page}]")));
+        assert!(err.contains(expected), "{page}: {err}");
+    }
+}
+
+/// Upstream answering with the request body, once it has all of it.
+async fn spawn_body_echo_upstream() -> std::net::SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let svc = hyper::service::service_fn(|req: Request<hyper::body::Incoming>| async move {
+                use http_body_util::BodyExt;
+                let body = req.into_body().collect().await?.to_bytes();
+                Ok::<_, hyper::Error>(hyper::Response::new(Full::new(body)))
+            });
+            tokio::spawn(hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), svc));
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn expect_continue_is_answered_once_the_upstream_is_reached() {
+    use tokio::io::AsyncWriteExt;
+
+    let upstream = spawn_body_echo_upstream().await;
+    let gw = spawn_server(&forward_yaml(upstream, "  max_request_body_bytes: 8")).await;
+
+    let mut tcp = tokio::net::TcpStream::connect(gw).await.unwrap();
+    tcp.write_all(b"PUT /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n").await.unwrap();
+    let interim = read_until(&mut tcp, "\r\n\r\n").await;
+    assert!(interim.starts_with("HTTP/1.1 100 Continue"), "{interim}");
+    tcp.write_all(b"hello").await.unwrap();
+    let raw = read_until(&mut tcp, "hello").await;
+    assert!(raw.starts_with("HTTP/1.1 200"), "{raw}");
+
+    // too large to be worth sending: no 100, just the final answer
+    let mut tcp = tokio::net::TcpStream::connect(gw).await.unwrap();
+    tcp.write_all(b"PUT /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 9\r\n\r\n").await.unwrap();
+    let raw = read_until(&mut tcp, "\r\n\r\n").await;
+    assert!(raw.starts_with("HTTP/1.1 413"), "{raw}");
+}
+
+#[tokio::test]
+async fn chunked_uploads_are_limited_as_they_stream() {
+    use tokio::io::AsyncWriteExt;
+
+    let upstream = spawn_body_echo_upstream().await;
+    let gw = spawn_server(&forward_yaml(upstream, "  max_request_body_bytes: 8")).await;
+
+    let mut tcp = tokio::net::TcpStream::connect(gw).await.unwrap();
+    tcp.write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n3\r\nabc\r\n4\r\ndefg\r\n0\r\n\r\n").await.unwrap();
+    let raw = read_until(&mut tcp, "abcdefg").await;
+    assert!(raw.starts_with("HTTP/1.1 200"), "{raw}");
+
+    let mut tcp = tokio::net::TcpStream::connect(gw).await.unwrap();
+    tcp.write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5\r\nfirst\r\n6\r\nsecond\r\n0\r\n\r\n").await.unwrap();
+    let raw = read_until(&mut tcp, "\r\n\r\n").await;
+    assert!(raw.starts_with("HTTP/1.1 413"), "{raw}");
+}
+
+#[test]
+fn request_body_limit_is_validated() {
+    let up: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    let err = built_err(&forward_yaml(up, "  max_request_body_bytes: 0"));
+    assert!(err.contains("`forward.max_request_body_bytes` must be positive"), "{err}");
+}
+
+#[test]
+fn nested_forwards_need_targets() {
+    // only the top-level service used to be validated; nested ones failed on their first request
+    let nested = |forward: &str| format!("bind: \"127.0.0.1:0\"\nservice:\n  handler: router\n  rules:\n    - ops:\n        - use: {forward}\n");
+    let err = built_err(&nested("{ handler: forward }"));
+    assert!(err.contains("`forward.target` cannot be empty"), "{err}");
+    let err = built_err(&nested("{ handler: forward, target: { scheme: http, host: localhost, weight: 0 } }"));
+    assert!(err.contains("`forward.target.weight`"), "{err}");
+}
+
+#[test]
+fn nested_services_are_validated() {
+    let forward = "{ handler: forward }";
+    for service in [
+        format!("{{ handler: router, rules: [{{ ops: [{{ branch: {{ if: {{ var: path, is: /a }}, then: [], else: [{{ use: {forward} }}] }} }}] }}] }}"),
+        format!("{{ handler: router, rules: [{{ ops: [] }}], next: {forward} }}"),
+        format!("{{ handler: cache, service: {forward} }}"),
+        format!("{{ handler: forward, target: {{ scheme: http, host: localhost }}, errors: {{ pages: [{{ on: 502, use: {forward} }}] }} }}"),
+    ] {
+        let yaml = format!("bind: \"127.0.0.1:0\"\nservice: {service}\n");
+        let cfg: crate::config::http_server::HttpServer = serde_yaml::from_str(&yaml).unwrap();
+        let err = cfg.validate().expect_err(&service).to_string();
+        assert!(err.contains("`forward.target` cannot be empty"), "{service}: {err}");
+    }
+}
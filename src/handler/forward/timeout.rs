@@ -0,0 +1,128 @@
+//! The Forward `timeouts`: a body that gives up when its next chunk is slow
+//! to come, one that tells when it has all been sent, and the error every
+//! timeout is reported with.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use hyper::body::{Body, Frame, SizeHint};
+use tokio::sync::oneshot;
+use tokio::time::{sleep, Sleep};
+
+use crate::handler::BoxError;
+
+/// An upstream took longer than `timeouts` allow.
+#[derive(Debug)]
+pub struct TimedOut {
+    pub what: &'static str,
+    pub after: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} timed out after {} ms", self.what, self.after.as_millis())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Wraps a body so that waiting longer than `limit` for a chunk ends it with
+/// [`TimedOut`]. The clock only runs while the body is polled, so a reader
+/// that is itself slow never trips it.
+pub struct IdleTimeout<B> {
+    inner: B,
+    what: &'static str,
+    limit: Duration,
+    waiting: Option<Pin<Box<Sleep>>>,
+}
+
+impl<B> IdleTimeout<B> {
+    pub fn new(inner: B, what: &'static str, limit: Duration) -> Self {
+        IdleTimeout { inner, what, limit, waiting: None }
+    }
+}
+
+impl<B> Body for IdleTimeout<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let this = &mut *self;
+        if let Poll::Ready(frame) = Pin::new(&mut this.inner).poll_frame(cx) {
+            this.waiting = None;
+            return Poll::Ready(frame.map(|f| f.map_err(Into::into)));
+        }
+        let waiting = this.waiting.get_or_insert_with(|| Box::pin(sleep(this.limit)));
+        match waiting.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Some(Err(TimedOut { what: this.what, after: this.limit }.into()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Wraps a request body to fire the receiver [`Sent::new`] returns once the
+/// last of it is gone, or once it is dropped unfinished.
+pub struct Sent<B> {
+    inner: B,
+    sent: Option<oneshot::Sender<()>>,
+}
+
+impl<B: Body> Sent<B> {
+    pub fn new(inner: B) -> (Self, oneshot::Receiver<()>) {
+        let (tx, rx) = oneshot::channel();
+        let mut body = Sent { inner, sent: Some(tx) };
+        // an empty body may never be polled at all
+        if body.inner.is_end_stream() {
+            body.finish();
+        }
+        (body, rx)
+    }
+
+    fn finish(&mut self) {
+        if let Some(sent) = self.sent.take() {
+            let _ = sent.send(());
+        }
+    }
+}
+
+impl<B> Body for Sent<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, B::Error>>> {
+        let this = &mut *self;
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        // the client may not ask again once the body says it is over
+        if frame.is_none() || this.inner.is_end_stream() {
+            this.finish();
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,87 @@
+//! Protocol switches (`Upgrade` and `101 Switching Protocols`), after which
+//! the client and upstream connections are joined byte for byte, as
+//! WebSockets need.
+
+use std::time::Duration;
+
+use hyper::http::{self, header, HeaderValue};
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::config::forward::ForwardService;
+use crate::config::http_version::HttpVersion;
+use crate::handler::RequestBody;
+use crate::util::http::ConnTasks;
+
+use super::InFlight;
+
+/// Copy buffer for each direction.
+const SPLICE_BUF: usize = 16 * 1024;
+
+/// The protocol the client asks to switch to, when it can be passed on:
+/// only HTTP/1.1 has `Upgrade`, on both sides.
+pub fn requested_protocol(req: &http::Request<RequestBody>, config: &ForwardService) -> Option<HeaderValue> {
+    if req.version() != http::Version::HTTP_11 || config.http_version != HttpVersion::V1_1 {
+        return None;
+    }
+    let asks = req.headers().get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+    if asks { req.headers().get(header::UPGRADE).cloned() } else { None }
+}
+
+/// Once both sides have switched, join them until either goes quiet for
+/// `idle` or both are done. The request stays counted against its upstream
+/// meanwhile, and the client connection against its listener.
+pub fn spawn_splice(tasks: &ConnTasks, downstream: OnUpgrade, upstream: OnUpgrade, idle: Duration, in_flight: InFlight) {
+    tasks.spawn(async move {
+        let _in_flight = in_flight;
+        let (downstream, upstream) = match tokio::try_join!(downstream, upstream) {
+            Ok(both) => both,
+            Err(e) => {
+                eprintln!("Forward: upgrade failed: {e}");
+                return;
+            }
+        };
+        if let Err(e) = splice(downstream, upstream, idle).await {
+            eprintln!("Forward: upgraded connection failed: {e}");
+        }
+    });
+}
+
+async fn splice(downstream: Upgraded, upstream: Upgraded, idle: Duration) -> std::io::Result<()> {
+    let (mut down_read, mut down_write) = tokio::io::split(TokioIo::new(downstream));
+    let (mut up_read, mut up_write) = tokio::io::split(TokioIo::new(upstream));
+    let (mut down_buf, mut up_buf) = (vec![0u8; SPLICE_BUF], vec![0u8; SPLICE_BUF]);
+    let (mut down_open, mut up_open) = (true, true);
+    while down_open || up_open {
+        tokio::select! {
+            n = down_read.read(&mut down_buf), if down_open => {
+                down_open = forward_chunk(n?, &down_buf, &mut up_write).await?;
+            }
+            n = up_read.read(&mut up_buf), if up_open => {
+                up_open = forward_chunk(n?, &up_buf, &mut down_write).await?;
+            }
+            _ = tokio::time::sleep(idle) => {
+                eprintln!("Forward: closing upgraded connection idle for {} ms", idle.as_millis());
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Pass `n` bytes of `buf` on, or the end of the stream when `n` is 0;
+/// whether the side read from is still open.
+async fn forward_chunk<W: AsyncWrite + Unpin>(n: usize, buf: &[u8], to: &mut W) -> std::io::Result<bool> {
+    if n == 0 {
+        to.shutdown().await?;
+        return Ok(false);
+    }
+    to.write_all(&buf[..n]).await?;
+    to.flush().await?;
+    Ok(true)
+}
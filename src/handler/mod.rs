@@ -1,27 +1,48 @@
 pub mod r#static;
 pub mod forward;
 pub mod router;
+pub mod cache;
 
-use hyper::{body, http};
-use http_body_util::Full;
+use hyper::http;
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
 use bytes::Bytes;
 use std::future::Future;
 use std::pin::Pin;
 
-use crate::build::service::LoadedService;
+use crate::build::LoadedService;
 
-pub type BoxResponseFuture<'a> = Pin<Box<dyn Future<Output = http::Response<Full<Bytes>>> + Send + 'a>>;
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Request body handed to services: the client's body, capped at the listener's `max_body_bytes`.
+///
+/// It is streamed, so a service that takes it leaves an empty body behind.
+pub type RequestBody = BoxBody<Bytes, BoxError>;
+
+/// Response body: buffered for answers made here, streamed for proxied ones.
+pub type ResponseBody = BoxBody<Bytes, BoxError>;
+
+pub type BoxResponseFuture<'a> = Pin<Box<dyn Future<Output = http::Response<ResponseBody>> + Send + 'a>>;
+
+/// A body holding `bytes`, sent as is.
+pub fn full(bytes: impl Into<Bytes>) -> ResponseBody {
+    Full::new(bytes.into()).map_err(|never| match never {}).boxed()
+}
+
+pub fn empty() -> ResponseBody {
+    Empty::new().map_err(|never| match never {}).boxed()
+}
 
 pub trait ServiceHandler {
-    fn handle_request<'a>(&'a self, req: &'a mut http::Request<body::Incoming>) -> BoxResponseFuture<'a>;
+    fn handle_request<'a>(&'a self, req: &'a mut http::Request<RequestBody>) -> BoxResponseFuture<'a>;
 }
 
 impl ServiceHandler for LoadedService {
-    fn handle_request<'a>(&'a self, req: &'a mut http::Request<body::Incoming>) -> BoxResponseFuture<'a> {
+    fn handle_request<'a>(&'a self, req: &'a mut http::Request<RequestBody>) -> BoxResponseFuture<'a> {
         match self {
             LoadedService::Static(handler) => handler.handle_request(req),
             LoadedService::Router(handler) => handler.handle_request(req),
             LoadedService::Forward(handler) => handler.handle_request(req),
+            LoadedService::Cache(handler) => handler.handle_request(req),
         }
     }
 }
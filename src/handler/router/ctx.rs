@@ -1,10 +1,12 @@
 use std::collections::HashMap;
 
-use hyper::{body, http};
+use hyper::http;
 use percent_encoding::percent_decode_str;
 
 use crate::config::http_method::HttpMethod;
+use crate::handler::RequestBody;
 use crate::template::ValueProvider;
+use crate::util::http::{request_scheme, ClientAddr};
 
 #[derive(Debug, Clone)]
 pub struct RouterCtx {
@@ -17,6 +19,7 @@ pub struct RouterCtx {
     pub headers: HashMap<String, Vec<String>>,
     pub cookies: HashMap<String, String>,
     pub captures: HashMap<String, String>,
+    pub client: Option<ClientAddr>,
 }
 
 impl ValueProvider for RouterCtx {
@@ -27,13 +30,15 @@ impl ValueProvider for RouterCtx {
             "host" => Some(self.host.clone()),
             "port" => self.port.map(|p| p.to_string()),
             "path" => Some(self.path.clone()),
+            "client.ip" => self.client.map(|c| c.ip.to_string()),
+            "client.port" => self.client.and_then(|c| c.port).map(|p| p.to_string()),
             v if v.starts_with("header.") => {
                 let name = v.trim_start_matches("header.").to_ascii_lowercase();
-                self.headers.get(&name).and_then(|vals| vals.get(0)).cloned()
+                self.headers.get(&name).and_then(|vals| vals.first()).cloned()
             }
             v if v.starts_with("query.") => {
                 let k = v.trim_start_matches("query.");
-                self.query.get(k).and_then(|vals| vals.get(0)).cloned()
+                self.query.get(k).and_then(|vals| vals.first()).cloned()
             }
             v if v.starts_with("cookie.") => {
                 let k = v.trim_start_matches("cookie.");
@@ -45,14 +50,15 @@ impl ValueProvider for RouterCtx {
 }
 
 impl RouterCtx {
-    pub fn from_request(req: &http::Request<body::Incoming>) -> Self {
+    pub fn from_request(req: &http::Request<RequestBody>) -> Self {
         let method = HttpMethod::try_from(req.method().as_str()).ok();
-        let scheme = req.uri().scheme_str().map(|s| s.to_ascii_lowercase());
+        let scheme = request_scheme(req).map(|s| s.to_ascii_lowercase());
         let (host, port) = parse_host_and_port(req);
         let path = req.uri().path().to_string();
         let query = parse_query(req.uri().query());
         let headers = collect_headers(req);
         let cookies = parse_cookies(headers.get("cookie"));
+        let client = req.extensions().get::<ClientAddr>().copied();
         RouterCtx {
             method,
             scheme,
@@ -63,18 +69,30 @@ impl RouterCtx {
             headers,
             cookies,
             captures: HashMap::new(),
+            client,
         }
     }
 }
 
-pub fn apply_ctx_to_request(ctx: &RouterCtx, req: &mut http::Request<body::Incoming>) {
-    if !ctx.host.is_empty() {
-        if let Ok(val) = http::HeaderValue::from_str(&ctx.host) {
-            req.headers_mut().insert(http::header::HOST, val);
-        }
+pub fn apply_ctx_to_request(ctx: &RouterCtx, req: &mut http::Request<RequestBody>) {
+    if !ctx.host.is_empty()
+        && let Ok(val) = http::HeaderValue::from_str(&ctx.host) {
+        req.headers_mut().insert(http::header::HOST, val);
     }
 
-    let mut uri = ctx.path.clone();
+    // keep the URI in absolute form when the scheme is known, so that nested
+    // services still see which scheme the client used
+    let mut uri = String::new();
+    if let Some(scheme) = &ctx.scheme
+        && !ctx.host.is_empty() {
+        uri.push_str(scheme);
+        uri.push_str("://");
+        uri.push_str(&ctx.host);
+        if let Some(port) = ctx.port {
+            uri.push_str(&format!(":{port}"));
+        }
+    }
+    uri.push_str(&ctx.path);
     if !ctx.query.is_empty() {
         let mut parts = Vec::new();
         for (k, vals) in &ctx.query {
@@ -90,20 +108,18 @@ pub fn apply_ctx_to_request(ctx: &RouterCtx, req: &mut http::Request<body::Incom
     }
 }
 
-fn parse_host_and_port(req: &http::Request<body::Incoming>) -> (String, Option<u16>) {
+fn parse_host_and_port(req: &http::Request<RequestBody>) -> (String, Option<u16>) {
     if let Some(host) = req.uri().host() {
         let port = req.uri().port_u16();
         return (host.to_string(), port);
     }
-    if let Some(host_header) = req.headers().get(http::header::HOST) {
-        if let Ok(hs) = host_header.to_str() {
-            if let Some((h, p)) = hs.split_once(':') {
-                if let Ok(port) = p.parse::<u16>() {
-                    return (h.to_string(), Some(port));
-                }
-            }
-            return (hs.to_string(), None);
+    if let Some(host_header) = req.headers().get(http::header::HOST)
+        && let Ok(hs) = host_header.to_str() {
+        if let Some((h, p)) = hs.split_once(':')
+            && let Ok(port) = p.parse::<u16>() {
+            return (h.to_string(), Some(port));
         }
+        return (hs.to_string(), None);
     }
     ("".into(), None)
 }
@@ -122,7 +138,7 @@ fn parse_query(q: Option<&str>) -> HashMap<String, Vec<String>> {
     out
 }
 
-fn collect_headers(req: &http::Request<body::Incoming>) -> HashMap<String, Vec<String>> {
+fn collect_headers(req: &http::Request<RequestBody>) -> HashMap<String, Vec<String>> {
     let mut map: HashMap<String, Vec<String>> = HashMap::new();
     for (name, value) in req.headers() {
         let key = name.as_str().to_ascii_lowercase();
@@ -59,10 +59,9 @@ pub fn matches_rule(
         if !ok {
             return MatchResult::NoMatch;
         }
-        if let Some(v) = vals.first() {
-            if let Some(caps) = h.pattern.captures_map(v) {
-                ctx.captures.extend(caps);
-            }
+        if let Some(v) = vals.first()
+            && let Some(caps) = h.pattern.captures_map(v) {
+            ctx.captures.extend(caps);
         }
     }
 
@@ -73,10 +72,9 @@ pub fn matches_rule(
         if !ok {
             return MatchResult::NoMatch;
         }
-        if let Some(v) = vals.first() {
-            if let Some(caps) = q.pattern.captures_map(v) {
-                ctx.captures.extend(caps);
-            }
+        if let Some(v) = vals.first()
+            && let Some(caps) = q.pattern.captures_map(v) {
+            ctx.captures.extend(caps);
         }
     }
 
@@ -2,23 +2,22 @@ mod ctx;
 mod matcher;
 mod ops;
 
-use bytes::Bytes;
-use http_body_util::Full;
-use hyper::{body, http};
+use hyper::http;
 
-use crate::build::service::LoadedRouter;
+use crate::build::LoadedRouter;
 use crate::config::router::OnMatch;
-use crate::handler::{BoxResponseFuture, ServiceHandler};
+use crate::handler::{BoxResponseFuture, RequestBody, ResponseBody, ServiceHandler};
 use crate::util::http::make_error_resp;
 
-use ctx::{apply_ctx_to_request, RouterCtx};
+pub use ctx::RouterCtx;
+use ctx::apply_ctx_to_request;
 use matcher::{matches_rule, MatchResult};
 use ops::{run_ops, OpOutcome};
 
 impl ServiceHandler for LoadedRouter {
     fn handle_request<'a>(
         &'a self,
-        req: &'a mut http::Request<body::Incoming>,
+        req: &'a mut http::Request<RequestBody>,
     ) -> BoxResponseFuture<'a> {
         Box::pin(async move { route_request(self, req).await })
     }
@@ -26,8 +25,8 @@ impl ServiceHandler for LoadedRouter {
 
 async fn route_request(
     router: &LoadedRouter,
-    req: &mut http::Request<body::Incoming>,
-) -> http::Response<Full<Bytes>> {
+    req: &mut http::Request<RequestBody>,
+) -> http::Response<ResponseBody> {
     let mut ctx = RouterCtx::from_request(req);
     let mut step = 0u32;
     let mut idx = 0usize;
@@ -57,9 +56,6 @@ async fn route_request(
         }
 
         match run_ops(&rule.ops, &mut ctx, req).await {
-            OpOutcome::ContinueNextRule => {
-                idx += 1;
-            }
             OpOutcome::Restart => {
                 ctx.captures.clear();
                 step += 1;
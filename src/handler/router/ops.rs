@@ -1,6 +1,4 @@
-use bytes::Bytes;
-use http_body_util::Full;
-use hyper::{body, http};
+use hyper::http;
 use std::collections::HashMap;
 
 use crate::build::router::{
@@ -10,7 +8,7 @@ use crate::build::router::{
     LoadedOp,
 };
 use crate::config::url_scheme::Scheme;
-use crate::handler::ServiceHandler;
+use crate::handler::{empty, full, RequestBody, ResponseBody, ServiceHandler};
 use crate::template::expand_template;
 use crate::util::http::make_error_resp;
 
@@ -18,17 +16,16 @@ use super::ctx::{apply_ctx_to_request, RouterCtx};
 
 #[derive(Debug)]
 pub enum OpOutcome {
-    ContinueNextRule,
     Restart,
-    Respond(http::Response<Full<Bytes>>),
-    UseService(http::Response<Full<Bytes>>),
+    Respond(http::Response<ResponseBody>),
+    UseService(http::Response<ResponseBody>),
     Fallthrough,
 }
 
 pub async fn run_ops(
     ops: &[LoadedOp],
     ctx: &mut RouterCtx,
-    req: &mut http::Request<body::Incoming>,
+    req: &mut http::Request<RequestBody>,
 ) -> OpOutcome {
     let mut stack: Vec<(&[LoadedOp], usize)> = vec![(ops, 0)];
 
@@ -143,7 +140,7 @@ pub async fn run_ops(
                     let resp = http::Response::builder()
                         .status(status_code)
                         .header(http::header::LOCATION, loc.as_str())
-                        .body(Full::default())
+                        .body(empty())
                         .unwrap_or_else(|_| make_error_resp(http::StatusCode::INTERNAL_SERVER_ERROR, "redirect build failed"));
                     return OpOutcome::Respond(resp);
                 }
@@ -169,7 +166,7 @@ pub async fn run_ops(
                         None => String::new(),
                     };
                     let resp = builder
-                        .body(Full::from(body_val))
+                        .body(full(body_val))
                         .unwrap_or_else(|_| make_error_resp(http::StatusCode::INTERNAL_SERVER_ERROR, "respond build failed"));
                     return OpOutcome::Respond(resp);
                 }
@@ -230,11 +227,11 @@ pub(crate) fn eval_cond(node: &CompiledCondNode, ctx: &RouterCtx) -> (bool, Hash
 fn eval_test(t: &CompiledTestCond, ctx: &RouterCtx) -> (bool, HashMap<String, String>) {
     match &t.cond {
         CompiledBasicCond::Equals(is) => {
-            let pass = value_of(&t.var, ctx).map_or(false, |v| serde_yaml::Value::String(v) == *is);
+            let pass = value_of(&t.var, ctx).is_some_and(|v| serde_yaml::Value::String(v) == *is);
             (pass, HashMap::new())
         }
         CompiledBasicCond::In(list) => {
-            let pass = value_of(&t.var, ctx).map_or(false, |v| list.contains(&serde_yaml::Value::String(v)));
+            let pass = value_of(&t.var, ctx).is_some_and(|v| list.contains(&serde_yaml::Value::String(v)));
             (pass, HashMap::new())
         }
         CompiledBasicCond::Present(p) => {
@@ -242,11 +239,10 @@ fn eval_test(t: &CompiledTestCond, ctx: &RouterCtx) -> (bool, HashMap<String, St
             (has == *p, HashMap::new())
         }
         CompiledBasicCond::Pattern(pat) => {
-            if let Some(v) = value_of(&t.var, ctx) {
-                if pat.is_match(&v) {
-                    let caps = pat.captures_map(&v).unwrap_or_default();
-                    return (true, caps);
-                }
+            if let Some(v) = value_of(&t.var, ctx)
+                && pat.is_match(&v) {
+                let caps = pat.captures_map(&v).unwrap_or_default();
+                return (true, caps);
             }
             (false, HashMap::new())
         }
@@ -260,13 +256,15 @@ fn value_of(var: &str, ctx: &RouterCtx) -> Option<String> {
         "host" => Some(ctx.host.clone()),
         "port" => ctx.port.map(|p| p.to_string()),
         "path" => Some(ctx.path.clone()),
+        "client.ip" => ctx.client.map(|c| c.ip.to_string()),
+        "client.port" => ctx.client.and_then(|c| c.port).map(|p| p.to_string()),
         v if v.starts_with("header.") => {
             let key = v.trim_start_matches("header.").to_ascii_lowercase();
-            ctx.headers.get(&key).and_then(|vals| vals.get(0)).cloned()
+            ctx.headers.get(&key).and_then(|vals| vals.first()).cloned()
         }
         v if v.starts_with("query.") => {
             let key = v.trim_start_matches("query.");
-            ctx.query.get(key).and_then(|vals| vals.get(0)).cloned()
+            ctx.query.get(key).and_then(|vals| vals.first()).cloned()
         }
         v if v.starts_with("cookie.") => {
             let key = v.trim_start_matches("cookie.");
@@ -4,6 +4,7 @@ use crate::pattern::{compile, compile_host};
 use crate::pattern::context::PathCtx;
 use crate::template::{compile_template, expand_template, CompiledTemplate, ValueProvider};
 
+use crate::util::http::ClientAddr;
 use super::ctx::RouterCtx;
 use super::ops::eval_cond;
 use crate::build::router::{CompiledBasicCond, CompiledCondNode, CompiledTestCond};
@@ -19,6 +20,7 @@ fn ctx_with_path(path: &str) -> RouterCtx {
         headers: HashMap::new(),
         cookies: HashMap::new(),
         captures: HashMap::new(),
+        client: None,
     }
 }
 
@@ -33,6 +35,7 @@ fn ctx_with_host(host: &str) -> RouterCtx {
         headers: HashMap::new(),
         cookies: HashMap::new(),
         captures: HashMap::new(),
+        client: None,
     }
 }
 
@@ -248,6 +251,7 @@ fn template_header_and_query_case_insensitive() {
         headers: HashMap::new(),
         cookies: HashMap::new(),
         captures: HashMap::new(),
+        client: None,
     };
     ctx.headers.insert("x-foo".into(), vec!["Bar".into()]);
     ctx.query.insert("q".into(), vec!["1".into()]);
@@ -265,3 +269,16 @@ fn template_capture_overwrites() {
     let out = expand_template(&t, &ctx).unwrap();
     assert_eq!(out, "222");
 }
+
+#[test]
+fn template_client_address() {
+    let mut ctx = ctx_with_path("/");
+    ctx.client = Some(ClientAddr { ip: "192.0.2.10".parse().unwrap(), port: Some(51234) });
+    let t = tpl("${client.ip}:${client.port}");
+    assert_eq!(expand_template(&t, &ctx).unwrap(), "192.0.2.10:51234");
+    let cond = CompiledCondNode::Test(CompiledTestCond {
+        var: "client.ip".to_string(),
+        cond: CompiledBasicCond::Equals(serde_yaml::Value::String("192.0.2.10".into())),
+    });
+    assert!(eval_cond(&cond, &ctx).0);
+}
@@ -1,27 +1,26 @@
 use bytes::Bytes;
-use http_body_util::Full;
-use hyper::{body, http};
+use hyper::http;
 use mime_guess::from_path;
 use percent_encoding::percent_decode_str;
 use std::fs;
 use std::path::{Component, Path, PathBuf};
 
-use crate::build::service::LoadedStatic;
+use crate::build::LoadedStatic;
 use crate::config::r#static::{
     EvilDirStrategyIndexExists,
     EvilDirStrategyIndexMissing,
     IndexStrategy,
 };
-use crate::handler::{BoxResponseFuture, ServiceHandler};
+use crate::handler::{empty, full, BoxResponseFuture, RequestBody, ResponseBody, ServiceHandler};
 use crate::util::http::make_error_resp;
 
 impl ServiceHandler for LoadedStatic {
     fn handle_request<'a>(
         &'a self,
-        req: &'a mut http::Request<body::Incoming>,
+        req: &'a mut http::Request<RequestBody>,
     ) -> BoxResponseFuture<'a> {
         Box::pin(async move {
-            let head_only = req.method() == &http::Method::HEAD;
+            let head_only = req.method() == http::Method::HEAD;
 
             let url_path_raw = req.uri().path();
             let is_url_path_dir = url_path_raw.ends_with('/');
@@ -37,7 +36,7 @@ impl ServiceHandler for LoadedStatic {
             let is_target_dir = is_existing_dir(&target_path);
             let is_target_index =
                 !is_url_path_dir
-                && target_path.file_name().map_or(false, |f| f == self.config.file_index.as_str());
+                && target_path.file_name().is_some_and(|f| f == self.config.file_index.as_str());
 
             eprintln!("Mapped to path: {:?} (is dir: {}, is index: {})", target_path, is_target_dir, is_target_index);
 
@@ -142,10 +141,10 @@ fn cascade_404_path(base: &Path, start: &Path, file_404: &str) -> Option<PathBuf
     None
 }
 
-fn make_response(status: http::StatusCode, body: &[u8]) -> http::Response<Full<Bytes>> {
+fn make_response(status: http::StatusCode, body: &[u8]) -> http::Response<ResponseBody> {
     http::Response::builder()
         .status(status)
-        .body(Full::new(Bytes::copy_from_slice(body)))
+        .body(full(Bytes::copy_from_slice(body)))
         .unwrap()
 }
 
@@ -154,22 +153,16 @@ fn nearest_404(
     start: &Path,
     file_404: &str,
     head_only: bool,
-) -> http::Response<Full<Bytes>> {
+) -> http::Response<ResponseBody> {
     let nf = cascade_404_path(base, start, file_404)
         .or_else(|| {
             let global = base.join(file_404);
             if global.is_file() { Some(global) } else { None }
         });
 
-    match nf {
-        Some(p) => {
-            match fs::read(&p) {
-                Ok(body) =>
-                    return with_ct(http::StatusCode::NOT_FOUND, &p, body, head_only),
-                Err(_) => {},
-            }
-        }
-        None => {},
+    if let Some(p) = nf
+        && let Ok(body) = fs::read(&p) {
+        return with_ct(http::StatusCode::NOT_FOUND, &p, body, head_only);
     }
 
     make_response(http::StatusCode::NOT_FOUND, b"404 Not Found")
@@ -180,20 +173,20 @@ fn with_ct(
     path: &Path,
     content: Vec<u8>,
     head_only: bool,
-) -> http::Response<Full<Bytes>> {
+) -> http::Response<ResponseBody> {
     let mime = from_path(path).first_or_octet_stream();
     if head_only {
         http::Response::builder()
             .status(status)
             .header(http::header::CONTENT_TYPE, mime.as_ref())
             .header(http::header::CONTENT_LENGTH, content.len().to_string())
-            .body(Full::new(Bytes::new()))
+            .body(empty())
             .unwrap()
     } else {
         http::Response::builder()
             .status(status)
             .header(http::header::CONTENT_TYPE, mime.as_ref())
-            .body(Full::new(Bytes::from(content)))
+            .body(full(content))
             .unwrap()
     }
 }
@@ -203,7 +196,7 @@ fn serve_file_or_404(
     path: &Path,
     file_404: &str,
     head_only: bool,
-) -> http::Response<Full<Bytes>> {
+) -> http::Response<ResponseBody> {
     match std::fs::read(path) {
         Ok(body) => with_ct(hyper::http::StatusCode::OK, path, body, head_only),
         Err(_) => nearest_404(base, path, file_404, head_only),
@@ -213,7 +206,7 @@ fn serve_file_or_404(
 fn redirect_to(
     location: &str,
     code: u16,
-) -> http::Response<Full<Bytes>> {
+) -> http::Response<ResponseBody> {
     let status = http::StatusCode::from_u16(code)
         .unwrap_or(http::StatusCode::PERMANENT_REDIRECT);
 
@@ -221,14 +214,14 @@ fn redirect_to(
         .status(status)
         .header(
             http::header::LOCATION,
-            http::HeaderValue::from_str(&location)
+            http::HeaderValue::from_str(location)
                 .unwrap_or_else(|_| http::HeaderValue::from_static("/")),
         )
-        .body(Full::new(Bytes::new()))
+        .body(empty())
         .unwrap()
 }
 
-fn location_with_slash(req: &http::Request<body::Incoming>) -> String {
+fn location_with_slash(req: &http::Request<RequestBody>) -> String {
     let mut location = req.uri().path().to_string();
     if !location.ends_with('/') { location.push('/'); }
     if let Some(query) = req.uri().query() {
@@ -238,7 +231,7 @@ fn location_with_slash(req: &http::Request<body::Incoming>) -> String {
     location
 }
 
-fn location_cur_dir(req: &http::Request<body::Incoming>) -> String {
+fn location_cur_dir(req: &http::Request<RequestBody>) -> String {
     let mut location = req.uri().path().to_string();
     location = location.trim_end_matches(|c| c != '/').to_string();
     if let Some(query) = req.uri().query() {
@@ -0,0 +1,209 @@
+//! Per-connection activity tracking behind the header-read and idle timeouts.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0";
+
+#[derive(Debug)]
+pub struct Activity {
+    /// Last time bytes went either way.
+    pub last_io: Instant,
+    /// When the first byte of a request head arrived, until the head is complete.
+    pub head_started: Option<Instant>,
+    /// Requests currently inside the service.
+    pub in_flight: usize,
+    /// HTTP/2 multiplexes requests, so head timing only applies until the first one.
+    pub h2: bool,
+    /// Requests seen so far.
+    pub requests: usize,
+}
+
+/// Which timeout ends a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// A request head is still incomplete: answer 408 and close.
+    Head,
+    /// Nothing is going on: close quietly.
+    Idle,
+}
+
+impl Activity {
+    /// Returns true when this read started a request head.
+    fn on_read(&mut self, bytes: &[u8]) -> bool {
+        let now = Instant::now();
+        self.last_io = now;
+        if self.requests == 0 && self.head_started.is_none() && bytes.starts_with(H2_PREFACE) {
+            self.h2 = true;
+        }
+        let awaiting_head = self.in_flight == 0 && !(self.h2 && self.requests > 0);
+        if awaiting_head && self.head_started.is_none() {
+            self.head_started = Some(now);
+            return true;
+        }
+        false
+    }
+
+    /// The next moment the connection should be given up, if any.
+    /// A connection that never sent anything gets the header timeout to start.
+    pub fn deadline(&self, header_timeout: Duration, idle_timeout: Duration) -> Option<(Instant, Expiry)> {
+        if let Some(started) = self.head_started {
+            return Some((started + header_timeout, Expiry::Head));
+        }
+        if self.in_flight > 0 {
+            return None;
+        }
+        let wait = if self.requests == 0 { header_timeout } else { idle_timeout };
+        Some((self.last_io + wait, Expiry::Idle))
+    }
+}
+
+/// Activity of one connection, shared by its stream, its requests and its watchdog.
+#[derive(Clone)]
+pub struct SharedActivity {
+    activity: Arc<Mutex<Activity>>,
+    changed: Arc<Notify>,
+}
+
+impl SharedActivity {
+    pub fn new(h2: bool) -> Self {
+        SharedActivity {
+            activity: Arc::new(Mutex::new(Activity {
+                last_io: Instant::now(),
+                head_started: None,
+                in_flight: 0,
+                h2,
+                requests: 0,
+            })),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Activity> {
+        self.activity.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Resolves after a change that may move the deadline earlier.
+    pub async fn changed(&self) {
+        self.changed.notified().await
+    }
+
+    fn on_read(&self, bytes: &[u8]) {
+        if self.lock().on_read(bytes) {
+            self.changed.notify_one();
+        }
+    }
+
+    /// Mark a request as inside the service until the guard is dropped.
+    pub fn start_request(&self) -> RequestGuard {
+        let mut a = self.lock();
+        a.head_started = None;
+        a.in_flight += 1;
+        a.requests += 1;
+        RequestGuard(self.clone())
+    }
+}
+
+pub struct RequestGuard(SharedActivity);
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        let mut a = self.0.lock();
+        a.in_flight -= 1;
+        a.last_io = Instant::now();
+        drop(a);
+        self.0.changed.notify_one();
+    }
+}
+
+/// One of a listener's `max_connections` slots, released on drop.
+pub struct ConnSlot(Arc<AtomicUsize>);
+
+impl ConnSlot {
+    /// Take a slot, or `None` when `max` connections are already open.
+    pub fn acquire(active: &Arc<AtomicUsize>, max: Option<usize>) -> Option<ConnSlot> {
+        let max = max.unwrap_or(usize::MAX);
+        active.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < max).then_some(n + 1))
+            .ok()
+            .map(|_| ConnSlot(active.clone()))
+    }
+}
+
+impl Drop for ConnSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A stream that records its traffic in a `SharedActivity`.
+///
+/// The stream itself is shared too, so once hyper has given up on the connection
+/// (a header read timeout) the server can still write its own response to it.
+pub struct TrackedIo<S> {
+    inner: Arc<Mutex<S>>,
+    activity: SharedActivity,
+}
+
+impl<S> TrackedIo<S> {
+    pub fn new(stream: S, activity: SharedActivity) -> (Self, Arc<Mutex<S>>) {
+        let inner = Arc::new(Mutex::new(stream));
+        (TrackedIo { inner: inner.clone(), activity }, inner)
+    }
+
+    fn stream(&self) -> MutexGuard<'_, S> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TrackedIo<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut *self.stream()).poll_read(cx, buf);
+        if matches!(res, Poll::Ready(Ok(()))) && buf.filled().len() > filled {
+            self.activity.on_read(&buf.filled()[filled..]);
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TrackedIo<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut *self.stream()).poll_write(cx, buf);
+        if matches!(res, Poll::Ready(Ok(n)) if n > 0) {
+            self.activity.lock().last_io = Instant::now();
+        }
+        res
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut *self.stream()).poll_write_vectored(cx, bufs);
+        if matches!(res, Poll::Ready(Ok(n)) if n > 0) {
+            self.activity.lock().last_io = Instant::now();
+        }
+        res
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream().is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.stream()).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.stream()).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,201 @@
+//! Sockets a server accepts connections on: TCP, Unix domain sockets and
+//! sockets handed over by systemd socket activation.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::task::{Context, Poll};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::bind::{BindAddr, UnixSocketOptions};
+
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// Pending connections the kernel queues per listening socket.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Connections over a Unix socket come from a local process, so they get the
+/// loopback address wherever a client address is needed.
+pub const UNIX_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+pub enum Listener {
+    Tcp(TcpListener),
+    /// The socket file is removed again when the listener closes, unless it
+    /// was not created by this process.
+    #[cfg(unix)]
+    Unix { listener: UnixListener, _file: Option<SocketFile> },
+}
+
+pub enum Accepted {
+    Tcp(TcpStream, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listener {
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<Accepted>> {
+        match self {
+            Listener::Tcp(l) => l.poll_accept(cx).map_ok(|(stream, peer)| Accepted::Tcp(stream, peer)),
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => listener.poll_accept(cx).map_ok(|(stream, _)| Accepted::Unix(stream)),
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(l) => match l.local_addr() {
+                Ok(addr) => write!(f, "{addr}"),
+                Err(_) => f.write_str("tcp"),
+            },
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => match listener.local_addr().ok().as_ref().and_then(|a| a.as_pathname()) {
+                Some(path) => write!(f, "unix:{}", path.display()),
+                None => f.write_str("unix"),
+            },
+        }
+    }
+}
+
+/// Open the sockets for one `bind` entry.
+///
+/// `[::]` listens on IPv4 too unless `v6_only` is set, which is needed when
+/// `0.0.0.0` is bound on the same port.
+pub async fn bind(addr: &BindAddr, v6_only: bool, unix: &UnixSocketOptions) -> io::Result<Vec<Listener>> {
+    match addr {
+        BindAddr::Tcp { host, port } => BindAddr::resolve(host, *port).await?
+            .into_iter()
+            .map(|a| bind_tcp(a, v6_only).map(Listener::Tcp))
+            .collect(),
+        #[cfg(unix)]
+        BindAddr::Unix(path) => bind_unix(path, unix).await.map(|l| vec![l]),
+        #[cfg(unix)]
+        BindAddr::Systemd(name) => super::systemd::listeners(name),
+        #[cfg(not(unix))]
+        _ => {
+            let _ = unix;
+            Err(io::Error::new(io::ErrorKind::Unsupported, "only TCP binds are supported on this platform"))
+        }
+    }
+}
+
+fn bind_tcp(addr: SocketAddr, v6_only: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(v6_only)?;
+    }
+    // rebinding right after a restart must not wait for TIME_WAIT sockets
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// A socket file created by this process, removed when dropped.
+#[cfg(unix)]
+pub struct SocketFile(PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(unix)]
+async fn bind_unix(path: &Path, opts: &UnixSocketOptions) -> io::Result<Listener> {
+    remove_stale_socket(path).await?;
+    let listener = UnixListener::bind(path)?;
+    let file = SocketFile(path.to_path_buf());
+    set_permissions(path, opts)?;
+    Ok(Listener::Unix { listener, _file: Some(file) })
+}
+
+/// A socket file left behind by a process that is gone would make the bind
+/// fail; one that still accepts connections means the path is taken.
+#[cfg(unix)]
+async fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            if UnixStream::connect(path).await.is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, "another process is listening on this socket"));
+            }
+            std::fs::remove_file(path)
+        }
+        // anything else is left for the bind to report
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, opts: &UnixSocketOptions) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = opts.mode_bits().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    let uid = opts.owner.as_deref().map(user_id).transpose()?;
+    let gid = opts.group.as_deref().map(group_id).transpose()?;
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(path, uid, gid)?;
+    }
+    Ok(())
+}
+
+/// Look up a user by name, or take a numeric uid as is.
+#[cfg(unix)]
+fn user_id(name: &str) -> io::Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    let cname = std::ffi::CString::new(name)?;
+    lookup(&format!("unknown user `{name}`"), |buf| {
+        // SAFETY: every pointer refers to a live local of the right type and
+        // `buf` is as long as the length passed along with it
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        let rc = unsafe { libc::getpwnam_r(cname.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut found) };
+        (rc, (!found.is_null()).then_some(entry.pw_uid))
+    })
+}
+
+/// Look up a group by name, or take a numeric gid as is.
+#[cfg(unix)]
+fn group_id(name: &str) -> io::Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    let cname = std::ffi::CString::new(name)?;
+    lookup(&format!("unknown group `{name}`"), |buf| {
+        // SAFETY: as in `user_id`
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        let rc = unsafe { libc::getgrnam_r(cname.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut found) };
+        (rc, (!found.is_null()).then_some(entry.gr_gid))
+    })
+}
+
+/// Run a `get*nam_r` call, growing its buffer while it reports ERANGE.
+#[cfg(unix)]
+fn lookup(missing: &str, mut call: impl FnMut(&mut [libc::c_char]) -> (libc::c_int, Option<u32>)) -> io::Result<u32> {
+    let mut buf = vec![0; 4096];
+    loop {
+        match call(&mut buf) {
+            (0, Some(id)) => return Ok(id),
+            (0, None) => return Err(io::Error::new(io::ErrorKind::NotFound, missing.to_string())),
+            (libc::ERANGE, _) if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            (rc, _) => return Err(io::Error::from_raw_os_error(rc)),
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use hyper::{
+    service::service_fn,
+    Request,
+    body,
+    http,
+};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinSet;
+use tokio::time::{sleep_until, timeout, Instant};
+use tokio_rustls::TlsAcceptor;
+use std::net::SocketAddr;
+use crate::build::BuiltHttpServer;
+use crate::build::LoadedService;
+use crate::config::limits::ListenerLimits;
+use crate::handler::forward::HealthProbes;
+use crate::handler::{ResponseBody, ServiceHandler};
+use crate::util::cidr::IpCidr;
+use crate::util::http::{content_length, make_error_resp, ConnTasks, ListenerScheme, PeerAddr};
+use http_body_util::{BodyExt, Limited};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+
+use std::future::poll_fn;
+use std::io;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+
+mod conn;
+mod listener;
+mod proxy_protocol;
+mod real_ip;
+mod servers;
+mod shutdown;
+#[cfg(unix)]
+mod systemd;
+
+use conn::{ConnSlot, Expiry, SharedActivity, TrackedIo};
+use listener::Accepted;
+#[cfg(unix)]
+use listener::UNIX_PEER;
+pub use listener::{bind, Listener};
+use real_ip::resolve_client;
+pub use servers::{drain_all, Servers};
+pub use shutdown::{DrainReport, ServerHandle};
+#[cfg(unix)]
+pub use systemd::take_inherited as take_systemd_sockets;
+use shutdown::{ListenerClosed, ShutdownSignal};
+
+const ALPN_H2: &[u8] = b"h2";
+const ALPN_HTTP11: &[u8] = b"http/1.1";
+const REQUEST_TIMEOUT_RESPONSE: &[u8] =
+    b"HTTP/1.1 408 Request Timeout\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+
+/// First pause after an accept error that is not about a single connection.
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+/// Longest pause between accept attempts while the error persists.
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// Serve `hs` on already bound listeners in the background until the returned
+/// handle stops it. The handle can also swap in a new config without rebinding.
+///
+/// Health checks of the service run from here until it is swapped out or the
+/// listener has drained.
+pub fn spawn_server(bind: String, listeners: Vec<Listener>, hs: BuiltHttpServer, drain_timeout: Duration) -> ServerHandle {
+    let listening = listeners.iter().map(Listener::to_string).collect();
+    let probes: SharedProbes = Arc::new(Mutex::new(hs.service.start_probes()));
+    let state: SharedState = Arc::new(ArcSwap::from_pointee(ListenerState::new(hs)));
+    ServerHandle::spawn(bind, listening, state.clone(), probes.clone(), move |stop, closed| async move {
+        let report = serve(listeners, state, stop, closed, drain_timeout).await;
+        drop(probes);
+        report
+    })
+}
+
+impl ServerHandle {
+    /// Use `hs` for every connection accepted and request received from now on.
+    /// Requests already running finish with the config they started with, but
+    /// the old service is no longer health checked.
+    pub fn reload(&self, hs: BuiltHttpServer) {
+        let probes = hs.service.start_probes();
+        self.state.store(Arc::new(ListenerState::new(hs)));
+        *self.probes.lock().unwrap_or_else(|e| e.into_inner()) = probes;
+    }
+}
+
+/// State shared by all connections of one listener.
+struct ListenerState {
+    service: LoadedService,
+    tls: Option<TlsAcceptor>,
+    proxy_protocol: bool,
+    trusted_proxies: Vec<IpCidr>,
+    limits: ListenerLimits,
+}
+
+impl ListenerState {
+    fn new(hs: BuiltHttpServer) -> Self {
+        ListenerState {
+            service: hs.service,
+            tls: hs.tls.map(TlsAcceptor::from),
+            proxy_protocol: hs.proxy_protocol,
+            trusted_proxies: hs.trusted_proxies,
+            limits: hs.limits,
+        }
+    }
+
+    fn header_read_timeout(&self) -> Duration {
+        Duration::from_millis(self.limits.header_read_timeout_ms)
+    }
+}
+
+/// The current listener state, replaced as a whole on reload.
+type SharedState = Arc<ArcSwap<ListenerState>>;
+
+/// Health checks of the service in the current state, replaced along with it.
+type SharedProbes = Arc<Mutex<Vec<Arc<HealthProbes>>>>;
+
+/// Accept connections until `stop` fires, then close the listeners, notify
+/// `closed` and give open connections `drain_timeout` to finish.
+///
+/// Accept errors never end the loop: ones about a single connection are
+/// skipped, others (such as running out of file descriptors) pause accepting
+/// with a growing backoff until a connection gets through again.
+async fn serve(
+    listeners: Vec<Listener>,
+    state: SharedState,
+    mut stop: ShutdownSignal,
+    mut closed: ListenerClosed,
+    drain_timeout: Duration,
+) -> DrainReport {
+    let mut conns = JoinSet::new();
+    let active = Arc::new(AtomicUsize::new(0));
+    let mut backoff = None;
+    let mut paused_until = None;
+    let mut next_listener = 0;
+
+    loop {
+        tokio::select! {
+            // stop first: connections react to the same signal and must not be reaped uncounted
+            biased;
+            _ = stop.requested() => break,
+            _ = sleep_until(paused_until.unwrap_or_else(Instant::now)), if paused_until.is_some() => {
+                paused_until = None;
+            }
+            accepted = accept_any(&listeners, &mut next_listener), if paused_until.is_none() => match accepted {
+                Ok(conn) => {
+                    backoff = None;
+                    let slot = ConnSlot::acquire(&active, state.load().limits.max_connections);
+                    match conn {
+                        Accepted::Tcp(stream, peer) =>
+                            conns.spawn(handle_connection(stream, peer, state.clone(), stop.clone(), slot)),
+                        #[cfg(unix)]
+                        Accepted::Unix(stream) =>
+                            conns.spawn(handle_connection(stream, UNIX_PEER, state.clone(), stop.clone(), slot)),
+                    };
+                }
+                Err(e) if is_connection_error(&e) => {}
+                Err(e) => {
+                    let delay = next_backoff(backoff);
+                    eprintln!("Accept error: {e}, pausing for {delay:?}");
+                    backoff = Some(delay);
+                    paused_until = Some(Instant::now() + delay);
+                }
+            },
+            // reap finished connections so the set only holds live ones
+            Some(_) = conns.join_next(), if !conns.is_empty() => {}
+        }
+    }
+
+    drop(listeners);
+    closed.notify();
+    drain(conns, drain_timeout).await
+}
+
+/// Accept from whichever listener has a connection ready, trying `next`
+/// first and moving it past the one that answered, so a busy listener cannot
+/// starve the others.
+async fn accept_any(listeners: &[Listener], next: &mut usize) -> io::Result<Accepted> {
+    poll_fn(|cx| {
+        for i in 0..listeners.len() {
+            let at = (*next + i) % listeners.len();
+            if let Poll::Ready(res) = listeners[at].poll_accept(cx) {
+                *next = (at + 1) % listeners.len();
+                return Poll::Ready(res);
+            }
+        }
+        Poll::Pending
+    }).await
+}
+
+/// Errors that only concern the connection being accepted, not the listener.
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset
+    )
+}
+
+fn next_backoff(previous: Option<Duration>) -> Duration {
+    previous.map_or(ACCEPT_BACKOFF_MIN, |d| (d * 2).min(ACCEPT_BACKOFF_MAX))
+}
+
+async fn drain(mut conns: JoinSet<()>, drain_timeout: Duration) -> DrainReport {
+    let mut report = DrainReport::default();
+    let _ = timeout(drain_timeout, async {
+        while conns.join_next().await.is_some() {
+            report.drained += 1;
+        }
+    }).await;
+    report.aborted = conns.len();
+    conns.shutdown().await;
+    report
+}
+
+/// What a connection needs once its transport is established.
+struct ConnContext {
+    peer: SocketAddr,
+    shared: SharedState,
+    stop: ShutdownSignal,
+    scheme: http::uri::Scheme,
+    /// Accepted beyond `max_connections`: every request is answered with 503.
+    over_limit: bool,
+}
+
+async fn handle_connection<S>(
+    mut stream: S,
+    tcp_peer: SocketAddr,
+    shared: SharedState,
+    stop: ShutdownSignal,
+    slot: Option<ConnSlot>,
+)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut peer = tcp_peer;
+    let state = shared.load_full();
+    // the handshakes below count against the time allowed for the request head
+    let handshake_timeout = state.header_read_timeout();
+
+    if state.proxy_protocol {
+        // only hops we trust may speak for someone else
+        let trusted = state.trusted_proxies.iter().any(|c| c.contains(&tcp_peer.ip()));
+        if !trusted {
+            eprintln!("PROXY header from untrusted peer {tcp_peer}, closing connection");
+            return;
+        }
+        match timeout(handshake_timeout, proxy_protocol::read_header(&mut stream)).await {
+            Ok(Ok(Some(src))) => peer = src,
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => {
+                eprintln!("PROXY protocol error from {tcp_peer}: {e}");
+                return;
+            }
+            Err(_) => {
+                eprintln!("PROXY protocol header timeout from {tcp_peer}");
+                return;
+            }
+        }
+    }
+
+    let ctx = |scheme| ConnContext { peer, shared, stop, scheme, over_limit: slot.is_none() };
+    match &state.tls {
+        Some(acceptor) => match timeout(handshake_timeout, acceptor.accept(stream)).await {
+            Ok(Ok(tls_stream)) => {
+                let negotiated = tls_stream.get_ref().1.alpn_protocol();
+                let h2 = negotiated == Some(ALPN_H2);
+                let builder = tls_conn_builder(negotiated, acceptor.config());
+                serve_connection(builder, tls_stream, h2, ctx(http::uri::Scheme::HTTPS), &state.limits).await
+            }
+            Ok(Err(e)) => eprintln!("TLS handshake error: {e}"),
+            Err(_) => eprintln!("TLS handshake timeout from {peer}"),
+        },
+        None => {
+            let builder = auto::Builder::new(TokioExecutor::new());
+            serve_connection(builder, stream, false, ctx(http::uri::Scheme::HTTP), &state.limits).await
+        }
+    }
+    drop(slot);
+}
+
+/// Pick the protocol for a TLS connection: the ALPN result when there is one,
+/// otherwise whatever the listener allows.
+fn tls_conn_builder(negotiated: Option<&[u8]>, config: &rustls::ServerConfig) -> auto::Builder<TokioExecutor> {
+    let builder = auto::Builder::new(TokioExecutor::new());
+    match negotiated {
+        Some(ALPN_H2) => builder.http2_only(),
+        Some(_) => builder.http1_only(),
+        None => {
+            let offers = |id: &[u8]| config.alpn_protocols.iter().any(|p| p == id);
+            if config.alpn_protocols.is_empty() || (offers(ALPN_H2) && offers(ALPN_HTTP11)) {
+                builder
+            } else if offers(ALPN_H2) {
+                builder.http2_only()
+            } else {
+                builder.http1_only()
+            }
+        }
+    }
+}
+
+/// Serve HTTP/1.0, HTTP/1.1 and HTTP/2 (detected from the connection preface) on one stream.
+///
+/// A watchdog next to the connection enforces the header read and idle
+/// timeouts and turns a stop request into a graceful shutdown. Upgraded
+/// streams relayed for its requests keep it open until they end.
+async fn serve_connection<S>(
+    mut builder: auto::Builder<TokioExecutor>,
+    stream: S,
+    h2: bool,
+    ctx: ConnContext,
+    limits: &ListenerLimits,
+)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let ConnContext { peer, shared, mut stop, scheme, over_limit } = ctx;
+    if let Some(n) = limits.max_header_bytes {
+        builder.http1().max_buf_size(n);
+        builder.http2().max_header_list_size(n as u32);
+    }
+    if let Some(n) = limits.max_headers {
+        builder.http1().max_headers(n);
+    }
+    if over_limit {
+        builder.http1().keep_alive(false);
+    }
+    let header_timeout = Duration::from_millis(limits.header_read_timeout_ms);
+    // a connection over the limit gets one answer, then goes away
+    let idle_timeout = if over_limit { Duration::ZERO } else { Duration::from_millis(limits.idle_timeout_ms) };
+
+    let activity = SharedActivity::new(h2);
+    let (io, raw) = TrackedIo::new(stream, activity.clone());
+
+    let tasks = ConnTasks::default();
+    let requests = activity.clone();
+    let conn_tasks = tasks.clone();
+    let svc_fn
+        = service_fn(
+            move |req: Request<body::Incoming>| {
+                let guard = requests.start_request();
+                // picked per request so keep-alive connections follow reloads
+                let state = shared.load_full();
+                let scheme = scheme.clone();
+                let tasks = conn_tasks.clone();
+                async move {
+                    let resp = if over_limit {
+                        make_error_resp(http::StatusCode::SERVICE_UNAVAILABLE, "too many connections")
+                    } else {
+                        handle_request(req, peer, scheme, tasks, &state).await
+                    };
+                    // in flight, and so not idle, until the response is ready
+                    drop(guard);
+                    Ok::<_, hyper::Error>(resp)
+                }
+            }
+        );
+
+    let mut conn = Box::pin(builder.serve_connection_with_upgrades(TokioIo::new(io), svc_fn));
+    let mut closing = false;
+    let send_408 = loop {
+        let deadline = if closing { None } else { activity.lock().deadline(header_timeout, idle_timeout) };
+        tokio::select! {
+            res = conn.as_mut() => {
+                if let Err(e) = res {
+                    eprintln!("Serve error: {e:?}");
+                }
+                break false;
+            }
+            _ = stop.requested(), if !closing => {
+                // finish the request in flight, then close instead of keeping alive
+                conn.as_mut().graceful_shutdown();
+                closing = true;
+            }
+            _ = activity.changed() => {}
+            _ = sleep_until(deadline.map_or_else(Instant::now, |(at, _)| at)), if deadline.is_some() => {
+                match deadline.map(|(_, expiry)| expiry) {
+                    Some(Expiry::Head) => break !activity.lock().h2,
+                    _ => {
+                        conn.as_mut().graceful_shutdown();
+                        closing = true;
+                    }
+                }
+            }
+        }
+    };
+
+    // hyper never answers a stalled head, so write the 408 ourselves
+    drop(conn);
+    if send_408
+        && let Ok(stream) = Arc::try_unwrap(raw).map(Mutex::into_inner) {
+        let mut stream = stream.unwrap_or_else(|e| e.into_inner());
+        let _ = stream.write_all(REQUEST_TIMEOUT_RESPONSE).await;
+        let _ = stream.shutdown().await;
+    }
+    tasks.join().await;
+}
+
+async fn handle_request(
+    mut req: Request<body::Incoming>,
+    peer: SocketAddr,
+    scheme: http::uri::Scheme,
+    tasks: ConnTasks,
+    state: &ListenerState,
+) -> http::Response<ResponseBody> {
+    set_request_scheme(&mut req, &scheme);
+    req.extensions_mut().insert(ListenerScheme(scheme));
+
+    let max_body = state.limits.max_body_bytes;
+    if let (Some(max), Some(len)) = (max_body, content_length(&req))
+        && len > max {
+        return make_error_resp(http::StatusCode::PAYLOAD_TOO_LARGE, "request body too large");
+    }
+
+    let client = resolve_client(&mut req, peer, &state.trusted_proxies);
+    let limit = max_body.map_or(usize::MAX, |m| usize::try_from(m).unwrap_or(usize::MAX));
+    let mut req = req.map(|b| Limited::new(b, limit).boxed());
+    req.extensions_mut().insert(PeerAddr(peer));
+    req.extensions_mut().insert(client);
+    req.extensions_mut().insert(tasks);
+    state.service.handle_request(&mut req).await
+}
+
+/// Turn the request URI into absolute form so handlers can see the scheme the
+/// client actually used: the listener's, whatever the URI itself claims. The
+/// authority is taken from the `Host` header when the URI has none; without
+/// either the URI is left as is and only `ListenerScheme` tells the scheme.
+fn set_request_scheme<B>(req: &mut Request<B>, scheme: &http::uri::Scheme) {
+    let authority = match req.uri().authority() {
+        Some(a) => a.clone(),
+        None => match req.headers().get(http::header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.parse::<http::uri::Authority>().ok())
+        {
+            Some(a) => a,
+            None => return,
+        },
+    };
+
+    let mut parts = req.uri().clone().into_parts();
+    parts.scheme = Some(scheme.clone());
+    parts.authority = Some(authority);
+    if parts.path_and_query.is_none() {
+        parts.path_and_query = Some(http::uri::PathAndQuery::from_static("/"));
+    }
+
+    if let Ok(uri) = http::Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,100 @@
+//! HAProxy PROXY protocol (v1 text and v2 binary) header parsing.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V1_MAX_LEN: usize = 107;
+
+/// Read the PROXY header in front of a connection, consuming exactly its bytes.
+///
+/// Returns the source address announced by the proxy, or `None` for `LOCAL` /
+/// `UNKNOWN` headers, in which case the real peer address should be used.
+pub async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<SocketAddr>> {
+    // both versions are at least 12 bytes long ("PROXY UNKNOWN\r\n" is 15)
+    let mut head = [0u8; 12];
+    stream.read_exact(&mut head).await?;
+
+    if head == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if head.starts_with(b"PROXY ") {
+        read_v1(stream, &head).await
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+async fn read_v1<S: AsyncRead + Unpin>(stream: &mut S, head: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let mut line = head.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("PROXY v1 header is not ASCII"))?;
+    parse_v1(line)
+}
+
+fn parse_v1(line: &str) -> io::Result<Option<SocketAddr>> {
+    let mut parts = line.split(' ');
+    parts.next(); // "PROXY"
+    match parts.next() {
+        Some("UNKNOWN") => Ok(None),
+        Some(proto @ ("TCP4" | "TCP6")) => {
+            let fields: Vec<&str> = parts.collect();
+            let [src, _dst, sport, _dport] = fields[..] else {
+                return Err(invalid("malformed PROXY v1 header"));
+            };
+            let ip: IpAddr = src.parse().map_err(|_| invalid("bad PROXY v1 source address"))?;
+            if ip.is_ipv4() != (proto == "TCP4") {
+                return Err(invalid("PROXY v1 address family mismatch"));
+            }
+            let port: u16 = sport.parse().map_err(|_| invalid("bad PROXY v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("unsupported PROXY v1 protocol")),
+    }
+}
+
+async fn read_v2<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<SocketAddr>> {
+    let mut hdr = [0u8; 4];
+    stream.read_exact(&mut hdr).await?;
+    let [ver_cmd, family, len_hi, len_lo] = hdr;
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    let mut payload = vec![0u8; u16::from_be_bytes([len_hi, len_lo]) as usize];
+    stream.read_exact(&mut payload).await?;
+
+    match ver_cmd & 0x0f {
+        0x0 => return Ok(None), // LOCAL: health checks from the proxy itself
+        0x1 => {}
+        _ => return Err(invalid("unsupported PROXY v2 command")),
+    }
+
+    // high nibble is the address family, low nibble the transport (stream / dgram)
+    match family >> 4 {
+        0x1 if payload.len() >= 12 => {
+            let ip = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            let port = u16::from_be_bytes([payload[8], payload[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        0x2 if payload.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&payload[..16]);
+            let port = u16::from_be_bytes([payload[32], payload[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port)))
+        }
+        0x1 | 0x2 => Err(invalid("truncated PROXY v2 address block")),
+        _ => Ok(None), // AF_UNSPEC / AF_UNIX carry no usable client address
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
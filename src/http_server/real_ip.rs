@@ -0,0 +1,91 @@
+//! Client address resolution through trusted proxies.
+
+use std::net::{IpAddr, SocketAddr};
+
+use hyper::http;
+
+use crate::util::cidr::{to_canonical, IpCidr};
+use crate::util::http::ClientAddr;
+
+const X_FORWARDED_FOR: http::HeaderName = http::HeaderName::from_static("x-forwarded-for");
+
+/// Attribute a request to a client.
+///
+/// `X-Forwarded-For` (or `Forwarded` when absent) is walked right to left
+/// through `trusted` hops; the first untrusted hop is the client, and the hops
+/// before it are dropped since nobody we trust vouched for them. Peers outside
+/// the trusted list, every peer when it is empty, get their forwarding headers
+/// removed entirely.
+pub fn resolve_client<B>(req: &mut http::Request<B>, peer: SocketAddr, trusted: &[IpCidr]) -> ClientAddr {
+    let peer_client = ClientAddr { ip: to_canonical(peer.ip()), port: Some(peer.port()) };
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|c| c.contains(ip));
+    let headers = req.headers_mut();
+
+    if !is_trusted(&peer.ip()) {
+        headers.remove(X_FORWARDED_FOR);
+        headers.remove(http::header::FORWARDED);
+        return peer_client;
+    }
+
+    let (name, elements) = if headers.contains_key(X_FORWARDED_FOR) {
+        (X_FORWARDED_FOR, list_elements(headers, &X_FORWARDED_FOR))
+    } else {
+        (http::header::FORWARDED, list_elements(headers, &http::header::FORWARDED))
+    };
+    let is_xff = name == X_FORWARDED_FOR;
+
+    // walk from the hop closest to us, stopping at the first one we cannot vouch for
+    let mut client = peer_client;
+    let mut keep_from = elements.len();
+    for (idx, element) in elements.iter().enumerate().rev() {
+        let node = if is_xff { parse_node(element) } else { forwarded_for(element) };
+        let Some((ip, port)) = node else { break };
+        client = ClientAddr { ip, port };
+        keep_from = idx;
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+
+    if keep_from > 0 {
+        headers.remove(&name);
+        if keep_from < elements.len()
+            && let Ok(v) = http::HeaderValue::from_str(&elements[keep_from..].join(", ")) {
+            headers.insert(name, v);
+        }
+    }
+
+    client
+}
+
+fn list_elements(headers: &http::HeaderMap, name: &http::HeaderName) -> Vec<String> {
+    headers.get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// The `for=` node of one `Forwarded` element.
+fn forwarded_for(element: &str) -> Option<(IpAddr, Option<u16>)> {
+    element.split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("for"))
+        .and_then(|(_, v)| parse_node(v))
+}
+
+/// Parse `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1` or `"[2001:db8::1]:80"`.
+fn parse_node(raw: &str) -> Option<(IpAddr, Option<u16>)> {
+    let s = raw.trim().trim_matches('"');
+    if let Some(rest) = s.strip_prefix('[') {
+        let (ip, tail) = rest.split_once(']')?;
+        let port = tail.strip_prefix(':').and_then(|p| p.parse().ok());
+        return Some((to_canonical(ip.parse().ok()?), port));
+    }
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        return Some((to_canonical(ip), None));
+    }
+    s.parse::<SocketAddr>().ok().map(|a| (to_canonical(a.ip()), Some(a.port())))
+}
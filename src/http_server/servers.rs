@@ -0,0 +1,136 @@
+//! The set of running listeners and how a new config is applied to it.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use tokio::task::JoinSet;
+
+use crate::build::BuiltHttpServer;
+use crate::config::bind::BindAddr;
+
+use super::{bind, spawn_server, DrainReport, ServerHandle};
+
+/// Running listeners keyed by their `bind` entry; a server with several
+/// `bind` entries has one listener per entry.
+pub struct Servers {
+    running: HashMap<String, ServerHandle>,
+    drain_timeout: Duration,
+}
+
+impl Servers {
+    pub fn new(drain_timeout: Duration) -> Self {
+        Servers { running: HashMap::new(), drain_timeout }
+    }
+
+    pub fn count(&self) -> usize {
+        self.running.len()
+    }
+
+    #[cfg(test)]
+    pub fn local_addr(&self, bind: &str) -> Option<std::net::SocketAddr> {
+        self.running.get(bind).and_then(|h| h.listening.first()?.parse().ok())
+    }
+
+    /// Make `servers` the running set.
+    ///
+    /// Listeners whose `bind` is unchanged keep their socket and get the new
+    /// service swapped in. New addresses are bound before anything else is
+    /// touched, so any failure leaves the current set running as it was.
+    /// Listeners no longer configured stop accepting and are returned so the
+    /// caller can wait for them to drain. Sockets systemd passed that no
+    /// `systemd:` bind claims are closed.
+    pub async fn apply(&mut self, servers: Vec<BuiltHttpServer>) -> Result<Vec<ServerHandle>, String> {
+        let mut keep = HashSet::new();
+        for bind in servers.iter().flat_map(|hs| &hs.binds) {
+            if !keep.insert(bind.to_string()) {
+                return Err(format!("bind `{bind}` is used by more than one server"));
+            }
+        }
+        // `[::]` would take the IPv4 side of a port that `0.0.0.0` also wants
+        let v4_any_ports: HashSet<u16> = servers.iter()
+            .flat_map(|hs| &hs.binds)
+            .filter_map(BindAddr::ipv4_any_port)
+            .collect();
+
+        let mut bound = HashMap::new();
+        for hs in &servers {
+            for addr in &hs.binds {
+                let key = addr.to_string();
+                if self.running.contains_key(&key) {
+                    continue;
+                }
+                let v6_only = matches!(addr, BindAddr::Tcp { port, .. } if v4_any_ports.contains(port));
+                let listeners = bind(addr, v6_only, &hs.unix_socket).await
+                    .map_err(|e| format!("failed to bind `{addr}`: {e}"))?;
+                bound.insert(key, listeners);
+            }
+        }
+
+        // what nothing binds is no use kept open
+        #[cfg(unix)]
+        super::systemd::close_unclaimed(&servers.iter()
+            .flat_map(|hs| &hs.binds)
+            .filter_map(|b| match b {
+                BindAddr::Systemd(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect());
+
+        let mut retired = Vec::new();
+        for key in self.running.keys().filter(|b| !keep.contains(*b)).cloned().collect::<Vec<_>>() {
+            if let Some(mut handle) = self.running.remove(&key) {
+                handle.stop_accepting().await;
+                retired.push(handle);
+            }
+        }
+
+        for hs in servers {
+            for addr in &hs.binds {
+                let key = addr.to_string();
+                match bound.remove(&key) {
+                    Some(listeners) => {
+                        let handle = spawn_server(key.clone(), listeners, hs.clone(), self.drain_timeout);
+                        for listening in &handle.listening {
+                            println!("Listening on {listening}");
+                        }
+                        self.running.insert(key, handle);
+                    }
+                    None => {
+                        if let Some(handle) = self.running.get(&key) {
+                            handle.reload(hs.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(retired)
+    }
+
+    /// Wait until every listener has exited on its own (they only do on failure).
+    pub async fn finished(&mut self) {
+        for h in self.running.values_mut() {
+            h.finished().await;
+        }
+    }
+
+    /// Stop all listeners and drain their connections.
+    pub async fn shutdown(self) -> DrainReport {
+        drain_all(self.running.into_values().collect()).await
+    }
+}
+
+/// Shut down `handles` concurrently and add up their reports.
+pub async fn drain_all(handles: Vec<ServerHandle>) -> DrainReport {
+    let mut set = JoinSet::new();
+    for h in handles {
+        set.spawn(h.shutdown());
+    }
+    let mut total = DrainReport::default();
+    while let Some(res) = set.join_next().await {
+        if let Ok(report) = res {
+            total += report;
+        }
+    }
+    total
+}
@@ -0,0 +1,102 @@
+//! Stop signal shared by a listener and its connections, and the handle used to drain them.
+
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+
+use super::{SharedProbes, SharedState};
+
+/// Receiving side of a stop request. Cloned into every connection of a listener.
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    /// Resolve once a stop has been requested. A dropped handle never fires,
+    /// so detached servers keep running.
+    pub async fn requested(&mut self) {
+        if self.0.wait_for(|stop| *stop).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// How a listener's connections ended after a stop request.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DrainReport {
+    /// Connections that finished on their own within the drain timeout.
+    pub drained: usize,
+    /// Connections still open at the deadline, closed forcibly.
+    pub aborted: usize,
+}
+
+impl std::ops::AddAssign for DrainReport {
+    fn add_assign(&mut self, rhs: Self) {
+        self.drained += rhs.drained;
+        self.aborted += rhs.aborted;
+    }
+}
+
+/// Control over one running listener task.
+pub struct ServerHandle {
+    pub bind: String,
+    /// What the listener's sockets are bound to, for display.
+    pub listening: Vec<String>,
+    pub(super) state: SharedState,
+    pub(super) probes: SharedProbes,
+    stop: watch::Sender<bool>,
+    closed: Option<oneshot::Receiver<()>>,
+    task: JoinHandle<DrainReport>,
+    exited: Option<DrainReport>,
+}
+
+/// Sender half kept by the listener to tell its handle the socket is gone.
+pub struct ListenerClosed(Option<oneshot::Sender<()>>);
+
+impl ListenerClosed {
+    pub fn notify(&mut self) {
+        if let Some(tx) = self.0.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+impl ServerHandle {
+    /// Spawn `run` with a fresh stop signal.
+    pub(super) fn spawn<F, Fut>(bind: String, listening: Vec<String>, state: SharedState, probes: SharedProbes, run: F) -> Self
+    where
+        F: FnOnce(ShutdownSignal, ListenerClosed) -> Fut,
+        Fut: Future<Output = DrainReport> + Send + 'static,
+    {
+        let (stop, rx) = watch::channel(false);
+        let (closed_tx, closed_rx) = oneshot::channel();
+        let task = tokio::spawn(run(ShutdownSignal(rx), ListenerClosed(Some(closed_tx))));
+        ServerHandle { bind, listening, state, probes, stop, closed: Some(closed_rx), task, exited: None }
+    }
+
+    /// Ask the listener to stop accepting and wait until its socket is closed,
+    /// so the address can be bound again. In-flight connections keep draining.
+    pub async fn stop_accepting(&mut self) {
+        let _ = self.stop.send(true);
+        if let Some(closed) = self.closed.take() {
+            let _ = closed.await;
+        }
+    }
+
+    /// Stop accepting and wait for the connections to drain.
+    pub async fn shutdown(mut self) -> DrainReport {
+        self.stop_accepting().await;
+        self.finished().await
+    }
+
+    /// Wait for the listener task to end, whether stopped or failed.
+    pub async fn finished(&mut self) -> DrainReport {
+        if let Some(report) = self.exited {
+            return report;
+        }
+        let report = (&mut self.task).await.unwrap_or_else(|e| {
+            eprintln!("Server {} failed: {e}", self.bind);
+            DrainReport::default()
+        });
+        self.exited = Some(report);
+        report
+    }
+}
@@ -0,0 +1,94 @@
+//! Sockets passed in by systemd socket activation (`LISTEN_FDS`).
+
+use std::collections::HashSet;
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::sync::{Mutex, MutexGuard};
+
+use socket2::Socket;
+use tokio::net::{TcpListener, UnixListener};
+
+use super::listener::Listener;
+
+/// The first descriptor systemd passes; the others follow it.
+const LISTEN_FDS_START: RawFd = 3;
+
+static INHERITED: Mutex<Vec<(String, OwnedFd)>> = Mutex::new(Vec::new());
+
+/// Take over the sockets systemd passed to this process and clear the
+/// variables describing them, so child processes do not think they are theirs.
+///
+/// Must run before any other thread is started: changing the environment
+/// while another thread reads it is undefined behaviour.
+pub fn take_inherited() {
+    const VARS: [&str; 3] = ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"];
+    let [pid, fds, names] = VARS.map(|key| std::env::var(key).ok());
+    let passed = passed_fds(pid, fds, names, std::process::id());
+    for key in VARS {
+        // SAFETY: called from `main` before the runtime starts, so no other thread exists yet
+        unsafe { std::env::remove_var(key) };
+    }
+    lock().extend(
+        passed.into_iter()
+            // SAFETY: systemd hands these descriptors to this process and nothing else claims them
+            .map(|(name, fd)| (name, unsafe { OwnedFd::from_raw_fd(fd) })),
+    );
+}
+
+/// Close the inherited sockets whose name is not in `claimed`. Binds get
+/// duplicates of the rest, so a listener closed by one reload can be opened
+/// again by a later one.
+pub fn close_unclaimed(claimed: &HashSet<&str>) {
+    lock().retain(|(name, _)| claimed.contains(name.as_str()));
+}
+
+fn lock() -> MutexGuard<'static, Vec<(String, OwnedFd)>> {
+    INHERITED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The descriptors described by the socket activation variables, provided
+/// they are meant for process `pid`. Unnamed sockets are called `unknown`, as
+/// systemd does.
+pub fn passed_fds(
+    listen_pid: Option<String>,
+    listen_fds: Option<String>,
+    names: Option<String>,
+    pid: u32,
+) -> Vec<(String, RawFd)> {
+    if listen_pid.and_then(|p| p.parse::<u32>().ok()) != Some(pid) {
+        return Vec::new();
+    }
+    let count: RawFd = listen_fds.and_then(|n| n.parse().ok()).unwrap_or(0);
+    let names = names.unwrap_or_default();
+    let mut names = names.split(':');
+    (0..count)
+        .map(|i| {
+            let name = names.next().filter(|n| !n.is_empty()).unwrap_or("unknown");
+            (name.to_string(), LISTEN_FDS_START + i)
+        })
+        .collect()
+}
+
+/// Listeners for every socket systemd passed under `name`.
+pub fn listeners(name: &str) -> io::Result<Vec<Listener>> {
+    let listeners = lock().iter()
+        .filter(|(n, _)| n == name)
+        .map(|(_, fd)| listener_from_fd(fd.try_clone()?))
+        .collect::<io::Result<Vec<_>>>()?;
+    if listeners.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("systemd passed no socket named `{name}`")));
+    }
+    Ok(listeners)
+}
+
+/// Wrap a listening socket of either family.
+pub fn listener_from_fd(fd: OwnedFd) -> io::Result<Listener> {
+    let socket = Socket::from(fd);
+    socket.set_nonblocking(true)?;
+    if socket.local_addr()?.is_unix() {
+        let std_listener = std::os::unix::net::UnixListener::from(OwnedFd::from(socket));
+        Ok(Listener::Unix { listener: UnixListener::from_std(std_listener)?, _file: None })
+    } else {
+        Ok(Listener::Tcp(TcpListener::from_std(socket.into())?))
+    }
+}
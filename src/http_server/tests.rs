@@ -0,0 +1,745 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{http, Request};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::pki_types::{CertificateDer, ServerName};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsConnector;
+
+use crate::config::http_server::HttpServer;
+use crate::http_server::DrainReport;
+use crate::test_util::{spawn_built, spawn_server, spawn_with_handle};
+
+struct SelfSigned {
+    dir: PathBuf,
+    cert_der: CertificateDer<'static>,
+}
+
+fn self_signed(name: &str) -> SelfSigned {
+    let dir = std::env::temp_dir().join(format!("oxidase-test-{}-{name}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let ck = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(dir.join("cert.pem"), ck.cert.pem()).unwrap();
+    std::fs::write(dir.join("key.pem"), ck.signing_key.serialize_pem()).unwrap();
+    SelfSigned { dir, cert_der: ck.cert.der().clone() }
+}
+
+/// Router that echoes the scheme it observed.
+fn server_yaml(tls: &str) -> String {
+    router_yaml(tls, "${scheme}")
+}
+
+/// Router answering every request with `body`, `top` is spliced into the HttpServer.
+fn router_yaml(top: &str, body: &str) -> String {
+    format!(r#"
+bind: "127.0.0.1:0"
+{top}
+service:
+  handler: router
+  rules:
+    - ops:
+        - respond:
+            status: 200
+            body: "{body}"
+"#)
+}
+
+async fn spawn(cfg: HttpServer) -> std::net::SocketAddr {
+    spawn_built(cfg).await
+}
+
+async fn tls_connect(
+    addr: std::net::SocketAddr,
+    certs: &SelfSigned,
+) -> tokio_rustls::client::TlsStream<TcpStream> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(certs.cert_der.clone()).unwrap();
+    let mut client_cfg = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client_cfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    TlsConnector::from(Arc::new(client_cfg))
+        .connect(ServerName::try_from("localhost").unwrap(), tcp)
+        .await
+        .expect("handshake failed")
+}
+
+fn get(uri: &str) -> Request<Empty<Bytes>> {
+    Request::builder()
+        .uri(uri)
+        .header(http::header::HOST, "localhost")
+        .body(Empty::new())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn tls_listener_reports_https_scheme() {
+    let certs = self_signed("tls-scheme");
+    let mut cfg: HttpServer = serde_yaml::from_str(&server_yaml(
+        "tls:\n  cert_file: cert.pem\n  key_file: key.pem",
+    )).unwrap();
+    cfg.base_dir = Some(certs.dir.clone());
+    let addr = spawn(cfg).await;
+
+    let tls = tls_connect(addr, &certs).await;
+    assert_eq!(tls.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(tls)).await.unwrap();
+    tokio::spawn(conn);
+    let resp = sender.send_request(get("/")).await.unwrap();
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"https");
+
+    let _ = std::fs::remove_dir_all(&certs.dir);
+}
+
+#[tokio::test]
+async fn plain_listener_reports_http_scheme() {
+    let cfg: HttpServer = serde_yaml::from_str(&server_yaml("")).unwrap();
+    let addr = spawn(cfg).await;
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(tcp)).await.unwrap();
+    tokio::spawn(conn);
+    let resp = sender.send_request(get("/")).await.unwrap();
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"http");
+}
+
+#[tokio::test]
+async fn clients_cannot_claim_https_over_plaintext() {
+    let cfg: HttpServer = serde_yaml::from_str(&server_yaml("")).unwrap();
+    let addr = spawn(cfg).await;
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(tcp)).await.unwrap();
+    tokio::spawn(conn);
+    let req = Request::builder()
+        .uri("https://evil.example/x")
+        .header(http::header::HOST, "evil.example")
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    let resp = sender.send_request(req).await.unwrap();
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"http");
+}
+
+#[tokio::test]
+async fn requests_without_a_host_still_see_the_scheme() {
+    let cfg: HttpServer = serde_yaml::from_str(&server_yaml("")).unwrap();
+    let addr = spawn(cfg).await;
+
+    let raw = raw_exchange(addr, b"GET / HTTP/1.0\r\n\r\n").await;
+    assert!(raw.starts_with("HTTP/1.0 200"), "{raw}");
+    assert!(raw.ends_with("\r\n\r\nhttp"), "{raw}");
+}
+
+#[tokio::test]
+async fn tls_listener_negotiates_h2_via_alpn() {
+    let certs = self_signed("tls-h2");
+    let mut cfg: HttpServer = serde_yaml::from_str(&server_yaml(
+        "tls:\n  cert_file: cert.pem\n  key_file: key.pem\n  alpn: [h2, http/1.1]",
+    )).unwrap();
+    cfg.base_dir = Some(certs.dir.clone());
+    let addr = spawn(cfg).await;
+
+    let tls = tls_connect(addr, &certs).await;
+    assert_eq!(tls.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+    let (mut sender, conn) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(tls))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+    let resp = sender.send_request(get("https://localhost/")).await.unwrap();
+    assert_eq!(resp.version(), http::Version::HTTP_2);
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"https");
+
+    let _ = std::fs::remove_dir_all(&certs.dir);
+}
+
+#[tokio::test]
+async fn plain_listener_accepts_h2c_prior_knowledge() {
+    let cfg: HttpServer = serde_yaml::from_str(&server_yaml("")).unwrap();
+    let addr = spawn(cfg).await;
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(tcp))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+    let resp = sender.send_request(get("http://localhost/")).await.unwrap();
+    assert_eq!(resp.version(), http::Version::HTTP_2);
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"http");
+}
+
+#[tokio::test]
+async fn plain_listener_accepts_http10() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let cfg: HttpServer = serde_yaml::from_str(&server_yaml("")).unwrap();
+    let addr = spawn(cfg).await;
+
+    let mut tcp = TcpStream::connect(addr).await.unwrap();
+    tcp.write_all(b"GET / HTTP/1.0\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut raw = Vec::new();
+    tcp.read_to_end(&mut raw).await.unwrap();
+    let raw = String::from_utf8_lossy(&raw);
+    assert!(raw.starts_with("HTTP/1.0 200"), "{raw}");
+    assert!(raw.ends_with("http"), "{raw}");
+}
+
+#[test]
+fn tls_requires_existing_cert_and_key() {
+    let mut cfg: HttpServer = serde_yaml::from_str(&server_yaml(
+        "tls:\n  cert_file: missing-cert.pem\n  key_file: missing-key.pem",
+    )).unwrap();
+    cfg.base_dir = Some(std::env::temp_dir());
+    assert!(cfg.validate().is_err());
+}
+
+async fn raw_exchange(addr: std::net::SocketAddr, bytes: &[u8]) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut tcp = TcpStream::connect(addr).await.unwrap();
+    tcp.write_all(bytes).await.unwrap();
+    let mut raw = Vec::new();
+    let _ = tcp.read_to_end(&mut raw).await;
+    String::from_utf8_lossy(&raw).into_owned()
+}
+
+const CLIENT_BODY: &str = "${client.ip}|${client.port}";
+const CLOSE_REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+#[tokio::test]
+async fn proxy_protocol_v1_sets_client_address() {
+    let cfg: HttpServer = serde_yaml::from_str(&router_yaml("proxy_protocol: true\ntrusted_proxies: [\"127.0.0.1\"]", CLIENT_BODY)).unwrap();
+    let addr = spawn(cfg).await;
+
+    let mut bytes = b"PROXY TCP4 198.51.100.1 10.0.0.1 56324 443\r\n".to_vec();
+    bytes.extend_from_slice(CLOSE_REQUEST);
+    let raw = raw_exchange(addr, &bytes).await;
+    assert!(raw.starts_with("HTTP/1.1 200"), "{raw}");
+    assert!(raw.ends_with("198.51.100.1|56324"), "{raw}");
+}
+
+#[tokio::test]
+async fn proxy_protocol_v2_sets_client_address() {
+    let cfg: HttpServer = serde_yaml::from_str(&router_yaml("proxy_protocol: true\ntrusted_proxies: [\"127.0.0.1\"]", CLIENT_BODY)).unwrap();
+    let addr = spawn(cfg).await;
+
+    let src: std::net::Ipv6Addr = "2001:db8::7".parse().unwrap();
+    let dst: std::net::Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let mut bytes = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    bytes.extend_from_slice(&[0x21, 0x21, 0, 36]);
+    bytes.extend_from_slice(&src.octets());
+    bytes.extend_from_slice(&dst.octets());
+    bytes.extend_from_slice(&4000u16.to_be_bytes());
+    bytes.extend_from_slice(&443u16.to_be_bytes());
+    bytes.extend_from_slice(CLOSE_REQUEST);
+    let raw = raw_exchange(addr, &bytes).await;
+    assert!(raw.ends_with("2001:db8::7|4000"), "{raw}");
+
+    // LOCAL command keeps the real peer
+    let mut bytes = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    bytes.extend_from_slice(&[0x20, 0x00, 0, 0]);
+    bytes.extend_from_slice(CLOSE_REQUEST);
+    let raw = raw_exchange(addr, &bytes).await;
+    assert!(raw.contains("127.0.0.1|"), "{raw}");
+}
+
+#[tokio::test]
+async fn proxy_protocol_rejects_missing_header() {
+    let cfg: HttpServer = serde_yaml::from_str(&router_yaml("proxy_protocol: true\ntrusted_proxies: [\"127.0.0.1\"]", CLIENT_BODY)).unwrap();
+    let addr = spawn(cfg).await;
+
+    let raw = raw_exchange(addr, CLOSE_REQUEST).await;
+    assert!(raw.is_empty(), "{raw}");
+}
+
+fn xff_request(xff: &str) -> Vec<u8> {
+    format!("GET / HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-For: {xff}\r\nConnection: close\r\n\r\n").into_bytes()
+}
+
+#[tokio::test]
+async fn trusted_proxies_resolve_client_from_x_forwarded_for() {
+    let cfg: HttpServer = serde_yaml::from_str(&router_yaml(
+        "trusted_proxies: [\"127.0.0.0/8\", \"10.0.0.0/8\"]",
+        CLIENT_BODY,
+    )).unwrap();
+    let addr = spawn(cfg).await;
+
+    let raw = raw_exchange(addr, &xff_request("1.1.1.1, 203.0.113.9, 10.1.2.3")).await;
+    assert!(raw.ends_with("203.0.113.9|"), "{raw}");
+
+    // only trusted hops: the furthest one is the client
+    let raw = raw_exchange(addr, &xff_request("10.9.9.9")).await;
+    assert!(raw.ends_with("10.9.9.9|"), "{raw}");
+}
+
+#[tokio::test]
+async fn untrusted_peer_cannot_spoof_client() {
+    let cfg: HttpServer = serde_yaml::from_str(&router_yaml(
+        "trusted_proxies: [\"10.0.0.0/8\"]",
+        CLIENT_BODY,
+    )).unwrap();
+    let addr = spawn(cfg).await;
+
+    let raw = raw_exchange(addr, &xff_request("203.0.113.9")).await;
+    assert!(raw.contains("\r\n\r\n127.0.0.1|"), "{raw}");
+}
+
+#[test]
+fn proxy_protocol_needs_trusted_proxies() {
+    // an empty list trusts nobody, so every header would be refused
+    let cfg: HttpServer = serde_yaml::from_str(&router_yaml("proxy_protocol: true", CLIENT_BODY)).unwrap();
+    assert!(cfg.validate().unwrap_err().to_string().contains("`proxy_protocol` needs `trusted_proxies`"));
+}
+
+#[test]
+fn trusted_proxies_must_be_valid_cidrs() {
+    let cfg: HttpServer = serde_yaml::from_str(&router_yaml(
+        "trusted_proxies: [\"10.0.0.0/33\"]",
+        CLIENT_BODY,
+    )).unwrap();
+    assert!(cfg.validate().is_err());
+}
+
+/// Upstream that holds every request for `delay` before answering "slow".
+async fn spawn_slow_upstream(delay: Duration) -> std::net::SocketAddr {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut tcp, _)) = listener.accept().await else { continue };
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                let _ = tcp.read(&mut buf).await;
+                tokio::time::sleep(delay).await;
+                let _ = tcp.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\nslow").await;
+            });
+        }
+    });
+    addr
+}
+
+fn slow_forward_yaml(upstream: std::net::SocketAddr) -> String {
+    format!(r#"
+bind: "127.0.0.1:0"
+service:
+  handler: forward
+  target:
+    scheme: http
+    host: "{}"
+    port: {}
+"#, upstream.ip(), upstream.port())
+}
+
+#[tokio::test]
+async fn shutdown_drains_in_flight_requests() {
+    let upstream = spawn_slow_upstream(Duration::from_millis(300)).await;
+    let cfg: HttpServer = serde_yaml::from_str(&slow_forward_yaml(upstream)).unwrap();
+    let (addr, handle) = spawn_with_handle(cfg, Duration::from_secs(5)).await;
+
+    let in_flight = tokio::spawn(raw_exchange(addr, CLOSE_REQUEST));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let report = handle.shutdown().await;
+    let raw = in_flight.await.unwrap();
+    assert!(raw.starts_with("HTTP/1.1 200"), "{raw}");
+    assert!(raw.ends_with("slow"), "{raw}");
+    assert_eq!(report, DrainReport { drained: 1, aborted: 0 });
+
+    // the listener is gone
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn shutdown_aborts_connections_after_drain_timeout() {
+    let upstream = spawn_slow_upstream(Duration::from_secs(10)).await;
+    let cfg: HttpServer = serde_yaml::from_str(&slow_forward_yaml(upstream)).unwrap();
+    let (addr, handle) = spawn_with_handle(cfg, Duration::from_millis(100)).await;
+
+    let in_flight = tokio::spawn(raw_exchange(addr, CLOSE_REQUEST));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let report = handle.shutdown().await;
+    assert_eq!(report, DrainReport { drained: 0, aborted: 1 });
+    assert_eq!(in_flight.await.unwrap(), "");
+}
+
+#[tokio::test]
+async fn shutdown_closes_idle_keep_alive_connections() {
+    let cfg: HttpServer = serde_yaml::from_str(&server_yaml("")).unwrap();
+    let (addr, handle) = spawn_with_handle(cfg, Duration::from_secs(5)).await;
+
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(TcpStream::connect(addr).await.unwrap()))
+        .await
+        .unwrap();
+    let conn = tokio::spawn(conn);
+    let resp = sender.send_request(get("http://localhost/")).await.unwrap();
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    resp.into_body().collect().await.unwrap();
+
+    assert!(!conn.is_finished(), "server closed the connection early");
+    let report = tokio::time::timeout(Duration::from_secs(2), handle.shutdown()).await.unwrap();
+    assert_eq!(report, DrainReport { drained: 1, aborted: 0 });
+    conn.await.unwrap().unwrap();
+}
+
+fn built(yaml: &str) -> crate::build::BuiltHttpServer {
+    crate::build::build_http_server(serde_yaml::from_str(yaml).unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn reload_swaps_service_without_rebinding() {
+    let mut servers = super::Servers::new(Duration::from_secs(5));
+    servers.apply(vec![built(&router_yaml("", "one"))]).await.unwrap();
+    let addr = servers.local_addr("127.0.0.1:0").unwrap();
+
+    // a keep-alive connection opened before the reload
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(TcpStream::connect(addr).await.unwrap()))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+    let resp = sender.send_request(get("http://localhost/")).await.unwrap();
+    assert_eq!(resp.into_body().collect().await.unwrap().to_bytes(), "one");
+
+    let retired = servers.apply(vec![built(&router_yaml("", "two"))]).await.unwrap();
+    assert!(retired.is_empty());
+    assert_eq!(servers.local_addr("127.0.0.1:0"), Some(addr));
+
+    let resp = sender.send_request(get("http://localhost/")).await.unwrap();
+    assert_eq!(resp.into_body().collect().await.unwrap().to_bytes(), "two");
+    let raw = raw_exchange(addr, CLOSE_REQUEST).await;
+    assert!(raw.ends_with("two"), "{raw}");
+}
+
+#[tokio::test]
+async fn reload_keeps_old_servers_when_bind_fails() {
+    let mut servers = super::Servers::new(Duration::from_secs(5));
+    servers.apply(vec![built(&router_yaml("", "one"))]).await.unwrap();
+    let addr = servers.local_addr("127.0.0.1:0").unwrap();
+
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let taken_bind = taken.local_addr().unwrap().to_string();
+    let second = router_yaml("", "other").replace("127.0.0.1:0", &taken_bind);
+    let err = servers.apply(vec![built(&router_yaml("", "two")), built(&second)]).await.err().unwrap();
+    assert!(err.contains(&taken_bind), "{err}");
+
+    let raw = raw_exchange(addr, CLOSE_REQUEST).await;
+    assert!(raw.ends_with("one"), "{raw}");
+    assert_eq!(servers.count(), 1);
+
+    let err = servers.apply(vec![built(&router_yaml("", "a")), built(&router_yaml("", "b"))]).await.err().unwrap();
+    assert!(err.contains("more than one server"), "{err}");
+}
+
+#[tokio::test]
+async fn reload_rebinds_changed_listeners() {
+    let mut servers = super::Servers::new(Duration::from_secs(5));
+    servers.apply(vec![built(&router_yaml("", "one"))]).await.unwrap();
+    let old = servers.local_addr("127.0.0.1:0").unwrap();
+
+    let moved = router_yaml("", "moved").replace("127.0.0.1:0", "127.0.0.2:0");
+    let retired = servers.apply(vec![built(&moved)]).await.unwrap();
+    assert_eq!(retired.len(), 1);
+    assert!(TcpStream::connect(old).await.is_err());
+
+    let new = servers.local_addr("127.0.0.2:0").unwrap();
+    let raw = raw_exchange(new, CLOSE_REQUEST).await;
+    assert!(raw.ends_with("moved"), "{raw}");
+    assert_eq!(super::drain_all(retired).await, DrainReport::default());
+}
+
+fn limited_yaml(limits: &str) -> String {
+    router_yaml(&format!("limits: {{ {limits} }}"), "ok")
+}
+
+async fn read_some(tcp: &mut TcpStream) -> String {
+    use tokio::io::AsyncReadExt;
+
+    let mut buf = vec![0u8; 4096];
+    let n = tokio::time::timeout(Duration::from_secs(5), tcp.read(&mut buf)).await
+        .expect("no response")
+        .unwrap();
+    String::from_utf8_lossy(&buf[..n]).into_owned()
+}
+
+#[tokio::test]
+async fn max_connections_answers_503_beyond_limit() {
+    use tokio::io::AsyncWriteExt;
+
+    let addr = spawn_server(&limited_yaml("max_connections: 1")).await;
+
+    let mut held = TcpStream::connect(addr).await.unwrap();
+    held.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    assert!(read_some(&mut held).await.starts_with("HTTP/1.1 200"));
+
+    let raw = raw_exchange(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert!(raw.starts_with("HTTP/1.1 503"), "{raw}");
+    assert!(raw.contains("connection: close"), "{raw}");
+
+    drop(held);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let raw = raw_exchange(addr, CLOSE_REQUEST).await;
+    assert!(raw.starts_with("HTTP/1.1 200"), "{raw}");
+}
+
+#[tokio::test]
+async fn stalled_request_head_gets_408() {
+    use tokio::io::AsyncWriteExt;
+
+    let addr = spawn_server(&limited_yaml("header_read_timeout_ms: 200")).await;
+
+    let mut tcp = TcpStream::connect(addr).await.unwrap();
+    tcp.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n").await.unwrap();
+    let raw = read_some(&mut tcp).await;
+    assert!(raw.starts_with("HTTP/1.1 408"), "{raw}");
+
+    // a connection that never says anything is closed without a response
+    let raw = raw_exchange(addr, b"").await;
+    assert_eq!(raw, "");
+}
+
+#[tokio::test]
+async fn idle_keep_alive_connections_are_closed() {
+    use tokio::io::AsyncWriteExt;
+
+    let addr = spawn_server(&limited_yaml("idle_timeout_ms: 200")).await;
+
+    let mut tcp = TcpStream::connect(addr).await.unwrap();
+    tcp.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    assert!(read_some(&mut tcp).await.starts_with("HTTP/1.1 200"));
+    assert_eq!(read_some(&mut tcp).await, "", "connection should be closed");
+}
+
+#[tokio::test]
+async fn oversized_headers_get_431() {
+    let addr = spawn_server(&limited_yaml("max_header_bytes: 8192, max_headers: 8")).await;
+
+    let big = format!("GET / HTTP/1.1\r\nHost: localhost\r\nx-big: {}\r\n\r\n", "a".repeat(10_000));
+    let raw = raw_exchange(addr, big.as_bytes()).await;
+    assert!(raw.starts_with("HTTP/1.1 431"), "{raw}");
+
+    let many: String = (0..10).map(|i| format!("x-h{i}: v\r\n")).collect();
+    let req = format!("GET / HTTP/1.1\r\nHost: localhost\r\n{many}\r\n");
+    let raw = raw_exchange(addr, req.as_bytes()).await;
+    assert!(raw.starts_with("HTTP/1.1 431"), "{raw}");
+}
+
+#[tokio::test]
+async fn oversized_bodies_get_413() {
+    let addr = spawn_server(&limited_yaml("max_body_bytes: 10")).await;
+
+    let req = b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 20\r\nConnection: close\r\n\r\n01234567890123456789";
+    let raw = raw_exchange(addr, req).await;
+    assert!(raw.starts_with("HTTP/1.1 413"), "{raw}");
+
+    // without a length the limit applies while the handler reads the body
+    let upstream = spawn_slow_upstream(Duration::ZERO).await;
+    let yaml = slow_forward_yaml(upstream).replace("service:", "limits: { max_body_bytes: 10 }\nservice:");
+    let addr = spawn_server(&yaml).await;
+    let req = b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n14\r\n01234567890123456789\r\n0\r\n\r\n";
+    let raw = raw_exchange(addr, req).await;
+    assert!(raw.starts_with("HTTP/1.1 413"), "{raw}");
+
+    let req = b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5\r\n01234\r\n0\r\n\r\n";
+    let raw = raw_exchange(addr, req).await;
+    assert!(raw.starts_with("HTTP/1.1 200"), "{raw}");
+}
+
+#[test]
+fn limits_are_validated() {
+    for bad in ["max_connections: 0", "max_header_bytes: 100", "idle_timeout_ms: 0"] {
+        let cfg: HttpServer = serde_yaml::from_str(&limited_yaml(bad)).unwrap();
+        assert!(cfg.validate().is_err(), "{bad}");
+    }
+}
+
+fn bind_yaml(bind: &str) -> String {
+    router_yaml("", "ok").replace("bind: \"127.0.0.1:0\"", &format!("bind: {bind}"))
+}
+
+#[test]
+fn bind_is_validated() {
+    for good in ["\"0.0.0.0:80\"", "\"[::]:80\"", "\"localhost:8080\"", "[\"127.0.0.1:80\", \"[::1]:80\"]"] {
+        let cfg: HttpServer = serde_yaml::from_str(&bind_yaml(good)).unwrap();
+        assert!(cfg.validate().is_ok(), "{good}");
+    }
+    for bad in ["\"::1:80\"", "\"localhost\"", "\"localhost:99999\"", "\":80\"", "\"[::1]80\"", "[]", "[\"a:1\", \"a:1\"]"] {
+        let cfg: HttpServer = serde_yaml::from_str(&bind_yaml(bad)).unwrap();
+        assert!(cfg.validate().is_err(), "{bad}");
+    }
+}
+
+#[tokio::test]
+async fn server_listens_on_every_bind() {
+    let mut servers = super::Servers::new(Duration::from_secs(5));
+    servers.apply(vec![built(&bind_yaml("[\"127.0.0.1:0\", \"localhost:0\"]"))]).await.unwrap();
+    assert_eq!(servers.count(), 2);
+
+    for bind in ["127.0.0.1:0", "localhost:0"] {
+        let addr = servers.local_addr(bind).unwrap();
+        let raw = raw_exchange(addr, CLOSE_REQUEST).await;
+        assert!(raw.ends_with("ok"), "{bind}: {raw}");
+    }
+}
+
+#[tokio::test]
+async fn busy_listeners_do_not_starve_the_others() {
+    let busy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let quiet = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (busy_addr, quiet_addr) = (busy.local_addr().unwrap(), quiet.local_addr().unwrap());
+    let mut clients = Vec::new();
+    for addr in [busy_addr, busy_addr, quiet_addr] {
+        clients.push(TcpStream::connect(addr).await.unwrap());
+    }
+
+    let listeners = [super::Listener::Tcp(busy), super::Listener::Tcp(quiet)];
+    let mut next = 0;
+    let mut served = Vec::new();
+    for _ in 0..2 {
+        let super::Accepted::Tcp(stream, _) = super::accept_any(&listeners, &mut next).await.unwrap() else {
+            panic!("accepted from a Unix socket");
+        };
+        served.push(stream.local_addr().unwrap());
+    }
+    assert_eq!(served, [busy_addr, quiet_addr]);
+}
+
+#[tokio::test]
+async fn unspecified_ipv6_bind_accepts_ipv4() {
+    let addr: crate::config::bind::BindAddr = "[::]:0".parse().unwrap();
+    // hosts without IPv6 cannot take part
+    let Ok(listeners) = super::bind(&addr, false, &Default::default()).await else { return };
+    let port = listeners[0].to_string().parse::<std::net::SocketAddr>().unwrap().port();
+    let handle = super::spawn_server(addr.to_string(), listeners, built(&router_yaml("", "ok")), Duration::from_secs(5));
+
+    let raw = raw_exchange(([127, 0, 0, 1], port).into(), CLOSE_REQUEST).await;
+    assert!(raw.ends_with("ok"), "{raw}");
+    handle.shutdown().await;
+}
+
+#[test]
+fn accept_backoff_grows_to_a_cap() {
+    let mut delay = super::next_backoff(None);
+    assert_eq!(delay, super::ACCEPT_BACKOFF_MIN);
+    for _ in 0..20 {
+        let next = super::next_backoff(Some(delay));
+        assert!(next >= delay);
+        delay = next;
+    }
+    assert_eq!(delay, super::ACCEPT_BACKOFF_MAX);
+}
+
+#[cfg(unix)]
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("oxidase-{}-{name}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[cfg(unix)]
+async fn unix_exchange(path: &std::path::Path, bytes: &[u8]) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::UnixStream::connect(path).await.unwrap();
+    stream.write_all(bytes).await.unwrap();
+    let mut raw = Vec::new();
+    let _ = stream.read_to_end(&mut raw).await;
+    String::from_utf8_lossy(&raw).into_owned()
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket_listener_serves_and_cleans_up() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = socket_path("listener");
+    // left behind by a process that is gone
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+    let yaml = router_yaml("unix_socket: { mode: \"600\" }", CLIENT_BODY)
+        .replace("127.0.0.1:0", &format!("unix:{}", path.display()));
+    let mut servers = super::Servers::new(Duration::from_secs(5));
+    servers.apply(vec![built(&yaml)]).await.unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let raw = unix_exchange(&path, CLOSE_REQUEST).await;
+    assert!(raw.ends_with("127.0.0.1|0"), "{raw}");
+
+    // a live socket is not taken over
+    let mut other = super::Servers::new(Duration::from_secs(5));
+    let err = other.apply(vec![built(&yaml)]).await.err().unwrap();
+    assert!(err.contains("another process"), "{err}");
+
+    servers.shutdown().await;
+    assert!(!path.exists());
+}
+
+#[test]
+fn unix_socket_options_are_validated() {
+    for bad in ["mode: \"999\"", "mode: \"17777\"", "owner: \"\""] {
+        let cfg: HttpServer = serde_yaml::from_str(&router_yaml(&format!("unix_socket: {{ {bad} }}"), "ok")).unwrap();
+        assert!(cfg.validate().is_err(), "{bad}");
+    }
+    let cfg: HttpServer = serde_yaml::from_str(&bind_yaml("[\"unix:/run/a.sock\", \"systemd:web\"]")).unwrap();
+    assert!(cfg.validate().is_ok());
+    for bad in ["\"unix:\"", "\"systemd:\""] {
+        let cfg: HttpServer = serde_yaml::from_str(&bind_yaml(bad)).unwrap();
+        assert!(cfg.validate().is_err(), "{bad}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn systemd_fds_are_read_from_the_environment() {
+    use super::systemd::passed_fds;
+
+    let env = |pid: &str, fds: &str, names: Option<&str>| {
+        passed_fds(Some(pid.into()), Some(fds.into()), names.map(Into::into), 42)
+    };
+    assert_eq!(env("42", "2", Some("web:admin")), [("web".to_string(), 3), ("admin".to_string(), 4)]);
+    assert_eq!(env("42", "2", None), [("unknown".to_string(), 3), ("unknown".to_string(), 4)]);
+    // meant for another process
+    assert!(env("7", "2", Some("web:admin")).is_empty());
+    assert!(passed_fds(None, None, None, 42).is_empty());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn systemd_sockets_of_either_family_are_served() {
+    use std::os::fd::OwnedFd;
+
+    let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = tcp.local_addr().unwrap();
+    let listener = super::systemd::listener_from_fd(OwnedFd::from(tcp)).unwrap();
+    assert_eq!(listener.to_string(), addr.to_string());
+    let _tcp = super::spawn_server("systemd:tcp".into(), vec![listener], built(&router_yaml("", "tcp")), Duration::from_secs(5));
+    assert!(raw_exchange(addr, CLOSE_REQUEST).await.ends_with("tcp"));
+
+    let path = socket_path("systemd");
+    let unix = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let listener = super::systemd::listener_from_fd(OwnedFd::from(unix)).unwrap();
+    assert_eq!(listener.to_string(), format!("unix:{}", path.display()));
+    let handle = super::spawn_server("systemd:unix".into(), vec![listener], built(&router_yaml("", "unix")), Duration::from_secs(5));
+    assert!(unix_exchange(&path, CLOSE_REQUEST).await.ends_with("unix"));
+
+    // the socket belongs to whoever passed it in
+    handle.shutdown().await;
+    assert!(path.exists());
+    let _ = std::fs::remove_file(&path);
+}
@@ -6,112 +6,172 @@ mod http_server;
 mod pattern;
 mod template;
 mod util;
+#[cfg(test)]
+mod test_util;
 
 use cli::Args;
 use clap::Parser;
-use std::path::Path;
-use tokio::task::JoinHandle;
+use std::process::ExitCode;
+use http_server::{DrainReport, ServerHandle, Servers};
 
-#[tokio::main]
-async fn main() {
+fn main() -> ExitCode {
     let args = Args::parse();
+    // before the runtime starts any thread, as this changes the environment
+    #[cfg(unix)]
+    http_server::take_systemd_sockets();
 
-    if args.watch {
-        run_watch_loop(&args).await;
-    } else {
-        run_once(&args).await;
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start the runtime: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let res = runtime.block_on(async {
+        if args.watch {
+            run_watch_loop(&args).await
+        } else {
+            run_once(&args).await
+        }
+    });
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
     }
 }
 
-async fn run_once(args: &Args) {
+async fn run_once(args: &Args) -> Result<(), String> {
     let servers = cli::load_http_servers(args)
-        .expect("Failed to load configuration");
+        .map_err(|e| format!("Configuration Error: {e}"))?;
 
     if args.validate_only {
         println!("configuration valid ({} server(s))", servers.len());
-        return;
+        return Ok(());
     }
 
-    let handles = spawn_servers(servers);
+    let mut running = Servers::new(args.drain_timeout());
+    apply_servers(servers, &mut running).await?;
 
-    for h in handles {
-        let _ = h.await;
+    tokio::select! {
+        sig = shutdown_signal() => println!("\n{sig} received, shutting down."),
+        // listeners only end on their own when they fail
+        _ = running.finished() => {
+            return if running.count() == 0 { Ok(()) } else { Err("Every server stopped unexpectedly".into()) };
+        }
     }
+    shutdown_servers(running).await;
+    Ok(())
 }
 
-async fn run_watch_loop(args: &Args) {
-    use notify::{RecursiveMode, Watcher};
+async fn run_watch_loop(args: &Args) -> Result<(), String> {
+    let mut watcher = cli::ConfigWatcher::new()
+        .map_err(|e| format!("Failed to create file watcher: {e}"))?;
 
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
-    
-    let mut watcher = notify::recommended_watcher(move |res: Result<notify::Event, _>| {
-        if let Ok(event) = res {
-             let _ = tx.send(event);
-        }
-    }).expect("Failed to create file watcher");
-
-    // Identify what to watch
-    if let Some(path) = &args.config {
-        println!("Watching config file: {:?}", path);
-        let _ = watcher.watch(path, RecursiveMode::NonRecursive);
-    } else if let Some(path) = &args.service_file {
-        println!("Watching service file: {:?}", path);
-        let _ = watcher.watch(path, RecursiveMode::NonRecursive);
-    } else {
-        println!("No file config provided (inline mode). Watching current directory.");
-        let _ = watcher.watch(Path::new("."), RecursiveMode::NonRecursive);
-    }
+    let mut running = Servers::new(args.drain_timeout());
 
     loop {
-        let mut handles = Vec::new();
-        
-        // Attempt to load and start
+        // Attempt to load and apply; on any failure the running servers stay untouched
         println!("Reloading configuration...");
         match cli::load_http_servers(args) {
-            Ok(servers) => {
-                if args.validate_only {
-                    println!("configuration valid ({} server(s))", servers.len());
-                } else {
-                    handles = spawn_servers(servers);
-                    println!("Servers running. Waiting for changes...");
-                }
+            Ok(servers) if args.validate_only => {
+                println!("configuration valid ({} server(s))", servers.len());
             }
+            Ok(servers) => match apply_servers(servers, &mut running).await {
+                Ok(retired) => {
+                    println!("{} server(s) running. Waiting for changes...", running.count());
+                    if !retired.is_empty() {
+                        tokio::spawn(async move {
+                            print_drain_report(http_server::drain_all(retired).await);
+                        });
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{e}");
+                    eprintln!("Keeping the previous configuration. Waiting for file changes to retry...");
+                }
+            },
             Err(e) => {
                 eprintln!("Configuration Error: {e}");
-                eprintln!("Waiting for file changes to retry...");
+                eprintln!("Keeping the previous configuration. Waiting for file changes to retry...");
+            }
+        }
+
+        // Follow whatever the config reads now, even if it failed to load
+        let files = cli::watched_files(args);
+        if &files != watcher.files() {
+            println!("Watching {} file(s):", files.len());
+            for f in &files {
+                println!("  {}", f.display());
             }
         }
+        watcher.set_files(files);
 
         // Wait for shutdown or change
         tokio::select! {
-            _ = tokio::signal::ctrl_c() => {
-                println!("\nCtrl+C received, shutting down.");
-                break;
-            }
-            Some(_) = rx.recv() => {
-                println!("\nFile change detected.");
-                // Simple debounce: consume buffered events
-                while rx.try_recv().is_ok() {} 
-                // Abort current servers
-                for h in handles {
-                    h.abort();
-                }
+            sig = shutdown_signal() => {
+                println!("\n{sig} received, shutting down.");
+                shutdown_servers(running).await;
+                return Ok(());
             }
+            _ = watcher.changed() => println!("\nFile change detected."),
         }
     }
 }
 
-fn spawn_servers(servers: Vec<config::http_server::HttpServer>) -> Vec<JoinHandle<()>> {
-    let mut handles = Vec::new();
-    for srv in servers {
-        match build::build_http_server(srv) {
-            Ok(built) => {
-                handles.push(tokio::spawn(http_server::start_server(built)));
-            }
+/// Build every server, then hand them to `running`. Nothing changes unless all succeed.
+async fn apply_servers(
+    servers: Vec<config::http_server::HttpServer>,
+    running: &mut Servers,
+) -> Result<Vec<ServerHandle>, String> {
+    let built = servers.into_iter()
+        .map(build::build_http_server)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to build server: {e}"))?;
+    running.apply(built).await
+}
+
+/// Drain every server; a second signal skips the wait.
+async fn shutdown_servers(running: Servers) {
+    tokio::select! {
+        report = running.shutdown() => print_drain_report(report),
+        sig = shutdown_signal() => println!("{sig} received again, exiting without draining."),
+    }
+}
+
+fn print_drain_report(report: DrainReport) {
+    if report.aborted > 0 {
+        println!(
+            "Drained {} connection(s), closed {} still open after the drain timeout.",
+            report.drained, report.aborted,
+        );
+    } else {
+        println!("Drained {} connection(s).", report.drained);
+    }
+}
+
+/// Resolve on Ctrl+C (SIGINT) or SIGTERM, naming the signal.
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = term.recv() => "SIGTERM",
+            },
             Err(e) => {
-                eprintln!("Failed to build server: {e}");
+                eprintln!("Failed to install SIGTERM handler: {e}");
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
             }
         }
     }
-    handles
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl+C"
+    }
 }
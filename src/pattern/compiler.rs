@@ -24,7 +24,7 @@ pub fn build_regex_source<C: PatternContext>(
                 if tail_only_name_seen { return Err(PatternError::TailOnlyMustBeLast); }
                 let mut buf = String::new();
                 let mut esc = false;
-                while let Some(c) = chars.next() {
+                for c in chars.by_ref() {
                     if esc { buf.push(c); esc = false; continue; }
                     if c == '\\' { esc = true; continue; }
                     if c == '>' { break; }
@@ -20,15 +20,11 @@ use error::PatternError;
 pub struct CompiledPattern {
     re: Regex,
     names: Vec<String>,
-    pub raw: String,
 }
 impl CompiledPattern {
     #[inline]
     pub fn is_match(&self, s: &str) -> bool { self.re.is_match(s) }
 
-    #[inline]
-    pub fn regex(&self) -> &Regex { &self.re }
-
     pub fn captures_map(&self, s: &str) -> Option<HashMap<String, String>> {
         let caps = self.re.captures(s)?;
         let mut out = HashMap::new();
@@ -42,7 +38,7 @@ impl CompiledPattern {
 pub fn compile<C: PatternContext>(input: &str, ctx: &C) -> Result<CompiledPattern, PatternError> {
     let (regex_src, names) = build_regex_source(input, ctx)?;
     let re = Regex::new(&regex_src)?;
-    Ok(CompiledPattern { re, names, raw: input.to_string() })
+    Ok(CompiledPattern { re, names })
 }
 
 pub fn compile_path(input: &str)  -> Result<CompiledPattern, PatternError> { compile(input, &PathCtx) }
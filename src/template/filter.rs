@@ -29,9 +29,9 @@ pub fn build_filter(name: &str, args: &[String]) -> Option<Filter> {
         "lower" => Some(Filter::Lower),
         "upper" => Some(Filter::Upper),
         "url_encode" => Some(Filter::UrlEncode),
-        "default" => args.get(0).map(|v| Filter::Default(v.clone())),
-        "trim_prefix" => args.get(0).map(|v| Filter::TrimPrefix(v.clone())),
-        "trim_suffix" => args.get(0).map(|v| Filter::TrimSuffix(v.clone())),
+        "default" => args.first().map(|v| Filter::Default(v.clone())),
+        "trim_prefix" => args.first().map(|v| Filter::TrimPrefix(v.clone())),
+        "trim_suffix" => args.first().map(|v| Filter::TrimSuffix(v.clone())),
         "replace" => {
             if args.len() == 2 {
                 Some(Filter::Replace { from: args[0].clone(), to: args[1].clone() })
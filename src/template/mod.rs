@@ -55,7 +55,7 @@ pub fn compile_template(src: &str) -> Result<CompiledTemplate, TemplateError> {
             chars.next(); // consume '{'
             let mut expr = String::new();
             let mut depth = 1;
-            while let Some(c) = chars.next() {
+            for c in chars.by_ref() {
                 if c == '{' {
                     depth += 1;
                 } else if c == '}' {
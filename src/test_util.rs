@@ -0,0 +1,229 @@
+//! Helpers shared by tests that need a running server or upstream.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{body, http, Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::build::build_http_server;
+use crate::config::http_server::HttpServer;
+use crate::http_server::ServerHandle;
+
+/// Start an oxidase server from an `HttpServer` YAML document on an ephemeral port.
+pub async fn spawn_server(yaml: &str) -> SocketAddr {
+    let cfg: HttpServer = serde_yaml::from_str(yaml).expect("invalid server yaml");
+    spawn_built(cfg).await
+}
+
+pub async fn spawn_built(cfg: HttpServer) -> SocketAddr {
+    // dropping the handle detaches the server, it runs until the test ends
+    spawn_with_handle(cfg, Duration::from_secs(30)).await.0
+}
+
+/// Like `spawn_built`, keeping the handle so the test can stop the server.
+pub async fn spawn_with_handle(cfg: HttpServer, drain_timeout: Duration) -> (SocketAddr, ServerHandle) {
+    let built = build_http_server(cfg).expect("build failed");
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    (addr, crate::http_server::spawn_server(addr.to_string(), vec![crate::http_server::Listener::Tcp(listener)], built, drain_timeout))
+}
+
+/// Start a stub upstream that answers every request with `f`.
+pub async fn spawn_upstream<F>(f: F) -> SocketAddr
+where
+    F: Fn(Request<body::Incoming>) -> Response<Full<Bytes>> + Clone + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else { continue };
+            tokio::spawn(serve_stub(stream, f.clone()));
+        }
+    });
+    addr
+}
+
+/// Like `spawn_upstream`, also counting the connections it accepts.
+pub async fn spawn_counting_upstream<F>(f: F) -> (SocketAddr, Arc<AtomicUsize>)
+where
+    F: Fn(Request<body::Incoming>) -> Response<Full<Bytes>> + Clone + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let count = accepted.clone();
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else { continue };
+            count.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(serve_stub(stream, f.clone()));
+        }
+    });
+    (addr, accepted)
+}
+
+/// Like `spawn_upstream`, listening on a Unix socket at `path`.
+#[cfg(unix)]
+pub fn spawn_unix_upstream<F>(path: &std::path::Path, f: F)
+where
+    F: Fn(Request<body::Incoming>) -> Response<Full<Bytes>> + Clone + Send + Sync + 'static,
+{
+    let listener = tokio::net::UnixListener::bind(path).unwrap();
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else { continue };
+            tokio::spawn(serve_stub(stream, f.clone()));
+        }
+    });
+}
+
+async fn serve_stub<S, F>(stream: S, f: F)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    F: Fn(Request<body::Incoming>) -> Response<Full<Bytes>> + Clone + Send + Sync + 'static,
+{
+    let svc = hyper::service::service_fn(move |req| {
+        let f = f.clone();
+        async move { Ok::<_, Infallible>(f(req)) }
+    });
+    let _ = auto::Builder::new(TokioExecutor::new())
+        .serve_connection(TokioIo::new(stream), svc)
+        .await;
+}
+
+/// Upstream that echoes the request line and headers back as the body.
+pub async fn spawn_echo_upstream() -> SocketAddr {
+    spawn_upstream(echo).await
+}
+
+/// Answer with the request line and headers.
+pub fn echo(req: Request<body::Incoming>) -> Response<Full<Bytes>> {
+    let mut out = format!("{} {}\n", req.method(), req.uri());
+    for (name, value) in req.headers() {
+        out.push_str(&format!("{}: {}\n", name, value.to_str().unwrap_or("")));
+    }
+    Response::new(Full::from(out))
+}
+
+/// Send one HTTP/1.1 request over a fresh connection and collect the response body.
+pub async fn send(addr: SocketAddr, req: Request<Full<Bytes>>) -> (http::StatusCode, http::HeaderMap, Bytes) {
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(tcp)).await.unwrap();
+    tokio::spawn(conn);
+    let resp = sender.send_request(req).await.unwrap();
+    let (parts, body) = resp.into_parts();
+    let body = body.collect().await.unwrap().to_bytes();
+    (parts.status, parts.headers, body)
+}
+
+/// Read from `tcp` until `needle` has been seen, failing after a few seconds.
+pub async fn read_until(tcp: &mut TcpStream, needle: &str) -> String {
+    use tokio::io::AsyncReadExt;
+
+    let mut seen = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !String::from_utf8_lossy(&seen).contains(needle) {
+        let n = tokio::time::timeout(Duration::from_secs(5), tcp.read(&mut buf)).await
+            .unwrap_or_else(|_| panic!("`{needle}` never arrived, got {:?}", String::from_utf8_lossy(&seen)))
+            .unwrap();
+        assert!(n > 0, "connection closed before `{needle}`");
+        seen.extend_from_slice(&buf[..n]);
+    }
+    String::from_utf8_lossy(&seen).into_owned()
+}
+
+pub fn get(path: &str) -> Request<Full<Bytes>> {
+    Request::builder()
+        .uri(path)
+        .header(http::header::HOST, "gateway.test")
+        .body(Full::default())
+        .unwrap()
+}
+
+/// A throwaway CA with a server certificate for `localhost` and a client
+/// certificate, written as PEM files into `dir`.
+pub struct TestPki {
+    pub dir: std::path::PathBuf,
+    pub ca_pem: String,
+}
+
+impl TestPki {
+    pub fn new(name: &str) -> Self {
+        use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+
+        let dir = std::env::temp_dir().join(format!("oxidase-pki-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+        std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+        for (file, san) in [("server", "localhost"), ("client", "client.test")] {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec![san.to_string()]).unwrap().signed_by(&key, &ca).unwrap();
+            std::fs::write(dir.join(format!("{file}.pem")), cert.pem()).unwrap();
+            std::fs::write(dir.join(format!("{file}.key")), key.serialize_pem()).unwrap();
+        }
+        TestPki { dir, ca_pem: ca.pem() }
+    }
+
+    pub fn path(&self, file: &str) -> String {
+        self.dir.join(file).display().to_string()
+    }
+
+    /// Server side of the upstream; with `client_auth` it demands a client certificate from the CA.
+    pub fn server_config(&self, client_auth: bool, versions: &[&'static rustls::SupportedProtocolVersion]) -> rustls::ServerConfig {
+        use rustls::pki_types::pem::PemObject;
+        use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+        let provider = std::sync::Arc::new(rustls::crypto::ring::default_provider());
+        let certs = CertificateDer::pem_file_iter(self.dir.join("server.pem")).unwrap().map(Result::unwrap).collect();
+        let key = PrivateKeyDer::from_pem_file(self.dir.join("server.key")).unwrap();
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(versions)
+            .unwrap();
+        let builder = if client_auth {
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add(CertificateDer::from_pem_file(self.dir.join("ca.pem")).unwrap()).unwrap();
+            let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(roots.into(), provider)
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+        builder.with_single_cert(certs, key).unwrap()
+    }
+}
+
+/// Like `spawn_upstream`, speaking TLS with `config`.
+pub async fn spawn_tls_upstream<F>(config: rustls::ServerConfig, f: F) -> SocketAddr
+where
+    F: Fn(Request<body::Incoming>) -> Response<Full<Bytes>> + Clone + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let acceptor = tokio_rustls::TlsAcceptor::from(std::sync::Arc::new(config));
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else { continue };
+            let (acceptor, f) = (acceptor.clone(), f.clone());
+            tokio::spawn(async move {
+                if let Ok(tls) = acceptor.accept(stream).await {
+                    serve_stub(tls, f).await;
+                }
+            });
+        }
+    });
+    addr
+}
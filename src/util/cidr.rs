@@ -0,0 +1,62 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IP network such as `10.0.0.0/8` or `2001:db8::/32`; a bare address is a single host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpCidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, to_canonical(*ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) =>
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix),
+            (IpAddr::V6(net), IpAddr::V6(ip)) =>
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((a, p)) => (a, Some(p)),
+            None => (s, None),
+        };
+        let addr = to_canonical(addr.parse::<IpAddr>().map_err(|e| format!("invalid CIDR `{s}`: {e}"))?);
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().ok().filter(|p| *p <= max)
+                .ok_or_else(|| format!("invalid CIDR `{s}`: bad prefix length"))?,
+            None => max,
+        };
+        Ok(IpCidr { addr, prefix })
+    }
+}
+
+/// Treat IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) as plain IPv4.
+pub fn to_canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let full = (prefix / 8) as usize;
+    if a[..full] != b[..full] {
+        return false;
+    }
+    let rem = prefix % 8;
+    if rem == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - rem);
+    a[full] & mask == b[full] & mask
+}
@@ -1,9 +1,64 @@
-use bytes::Bytes;
-use http_body_util::Full;
 use hyper::http;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinSet;
 
-pub fn make_error_resp(status: http::StatusCode, msg: &str) -> http::Response<Full<Bytes>> {
-    let mut resp = http::Response::new(Full::from(msg.to_string()));
+use crate::handler::{full, ResponseBody};
+
+pub fn make_error_resp(status: http::StatusCode, msg: &str) -> http::Response<ResponseBody> {
+    let mut resp = http::Response::new(full(msg.to_string()));
     *resp.status_mut() = status;
     resp
 }
+
+/// The length a request announces for its body, if any.
+pub fn content_length<B>(req: &http::Request<B>) -> Option<u64> {
+    req.headers()
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// The client a request is attributed to, attached to each request as an extension.
+///
+/// This is the peer itself unless the listener trusts proxies, in which case it is
+/// resolved from `X-Forwarded-For` / `Forwarded` and the port may be unknown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientAddr {
+    pub ip: IpAddr,
+    pub port: Option<u16>,
+}
+
+/// The scheme of the listener a request arrived on, for requests whose URI
+/// cannot carry it because they name no host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerScheme(pub http::uri::Scheme);
+
+/// The scheme a request was made with: its URI's, which the listener sets and
+/// a router may rewrite, else the listener's.
+pub fn request_scheme<B>(req: &http::Request<B>) -> Option<&str> {
+    req.uri().scheme_str().or_else(|| req.extensions().get::<ListenerScheme>().map(|s| s.0.as_str()))
+}
+
+/// The hop that connected to us: the TCP peer, or the source announced in a PROXY protocol header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// Work a request leaves running on its connection, such as relaying an
+/// upgraded stream, attached to each request as an extension. The connection
+/// waits for it before it counts as closed, and aborts it if aborted itself.
+#[derive(Debug, Clone, Default)]
+pub struct ConnTasks(Arc<Mutex<JoinSet<()>>>);
+
+impl ConnTasks {
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).spawn(task);
+    }
+
+    /// Wait for every task spawned so far.
+    pub async fn join(&self) {
+        let mut tasks = std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()));
+        while tasks.join_next().await.is_some() {}
+    }
+}
@@ -33,11 +33,11 @@ pub fn parse_call(raw: &str) -> Result<(String, Vec<String>), ParseError> {
 pub fn split_args(inner: &str) -> Result<Vec<String>, ParseError> {
     let mut args = Vec::new();
     let mut buf = String::new();
-    let mut chars = inner.chars().peekable();
+    let chars = inner.chars();
     let mut in_quote: Option<char> = None;
     let mut esc = false;
 
-    while let Some(ch) = chars.next() {
+    for ch in chars {
         if esc {
             buf.push(ch);
             esc = false;